@@ -9,24 +9,28 @@ import os
 # cache_sizes = ["4096"]
 # associativities = ["1", "128"]
 # block_sizes = ["32"]
+# replacements = ["lru", "fifo", "random", "tree-plru", "nru", "lfu", "srrip", "brrip", "drrip", "belady"]
 
 # cache size
 # name = "cache_size.csv"
 # cache_sizes = ["1024", "8192"]
 # associativities = ["2"]
 # block_sizes = ["32"]
+# replacements = ["lru"]
 
 # block size
 # name = "block_size.csv"
 # cache_sizes = ["4096"]
 # associativities = ["2"]
 # block_sizes = ["16", "64"]
+# replacements = ["lru"]
 
 # default
 # name = "default.csv"
 # cache_sizes = ["4096"]
 # associativities = ["2"]
 # block_sizes = ["32"]
+# replacements = ["lru"]
 
 path = os.path.dirname(os.path.abspath(__file__))
 root_path = os.path.abspath(os.path.join(path, ".."))
@@ -38,10 +42,10 @@ target = os.path.join(root_path, "target/release/coherence")
 out_path = os.path.join(data_path, name)
 
 
-protocols = ["mesi", "dragon", "mesi-advanced"]
+protocols = ["mesi", "dragon", "moesi", "mesif", "msi", "firefly", "berkeley", "write-once", "token", "mesi-advanced"]
 inputs = [blackscholes_path, bodytrack_path, fluidanimate_path]
 
-a = [protocols, inputs, cache_sizes, associativities, block_sizes]
+a = [protocols, inputs, cache_sizes, associativities, block_sizes, replacements]
 all_options = list(itertools.product(*a))
 
 columns = [
@@ -50,6 +54,7 @@ columns = [
     "cache_size",
     "associativity",
     "block_size",
+    "replacement",
     "total_cycles",
     "total_private_accesses",
     "total_shared_accesses",
@@ -188,22 +193,23 @@ def parse(lines):
 
 
 for i, options in enumerate(all_options):
-    protocol, input_file, cache_size, associativity, block_size = options
+    protocol, input_file, cache_size, associativity, block_size, replacement = options
+    replacement_args = ["--replacement", replacement]
     input_name = os.path.basename(input_file)
 
     print(f"[{i+1}/{len(all_options)}] {options}")
     if protocol == "mesi-advanced":
         p = subprocess.Popen(
-            [target, "mesi", input_file, cache_size, associativity, block_size, "--no-progress", "--read-broadcast"],
+            [target, "mesi", input_file, cache_size, associativity, block_size, "--no-progress", "--read-broadcast", *replacement_args],
             stdout=subprocess.PIPE,
         )
     else:
-        p = subprocess.Popen([target, *options, "--no-progress"], stdout=subprocess.PIPE)
+        p = subprocess.Popen([target, *options[:-1], "--no-progress", *replacement_args], stdout=subprocess.PIPE)
 
     out, err = p.communicate()
     lines = out.decode("utf-8").split("\n")
     df_data = pd.DataFrame(
-        [[protocol, input_name, cache_size, associativity, block_size, *parse(lines)]], columns=columns
+        [[protocol, input_name, cache_size, associativity, block_size, replacement, *parse(lines)]], columns=columns
     )
     df = pd.concat([df, df_data])
     df.to_csv(out_path, index=False)
@@ -1,5 +1,11 @@
+use crate::arbiter::ArbitrationKind;
+use crate::bus::BusStats;
 use crate::cache::CacheStats;
 use crate::core::CoreStats;
+use crate::directory::DirectoryStats;
+use crate::dram::DramStats;
+use crate::l2::L2Stats;
+use crate::store_buffer::StoreBufferStats;
 
 #[derive(Debug, Default)]
 pub struct Stats {
@@ -7,7 +13,24 @@ pub struct Stats {
     pub cores: Vec<CoreStats>,
     pub bus_traffic: usize,
     pub bus_num_invalid_or_upd: usize,
+    pub bus_num_persistent_requests: usize,
+    /// Cycles in which a transaction occupied the bus (summed over all buses)
+    pub bus_busy_cycles: usize,
+    pub bus_num_split_transactions: usize,
+    pub bus_max_outstanding: usize,
+    /// Each of the address-interleaved buses
+    pub buses: Vec<BusStats>,
+    /// Arbitration policy of the bus
+    pub arbitration: ArbitrationKind,
+    /// Jain's fairness index of the arbitration wait cycles of the cores
+    pub arbitration_fairness: f64,
+    /// Loads that returned an older version than the latest committed store
+    pub num_stale_reads: usize,
     pub cache: CacheStats,
+    pub directory: Option<DirectoryStats>,
+    pub l2: Option<L2Stats>,
+    pub dram: Option<DramStats>,
+    pub store_buffer: Option<StoreBufferStats>,
 }
 
 pub trait Analyzable {
@@ -37,8 +60,7 @@ impl Analyzer {
              No. Data Cache Hits:               {:<10} ({:.2})\n\
              No. Data Cache Misses:             {:<10} ({:.2})\n\
              Bus Traffic:                       {:?} Bytes\n\
-             No. Bus Invalidations or Updates:  {:?}\n\n\
-             Core Statistics:\n",
+             No. Bus Invalidations or Updates:  {:?}\n\n",
             self.stats.exec_cycles,
             self.stats.cache.num_private_data_access,
             self.stats.cache.num_shared_data_access,
@@ -49,8 +71,172 @@ impl Analyzer {
             (self.stats.cache.num_data_cache_misses as f64
                 / self.stats.cores.iter().map(|c| c.mem_ops).sum::<usize>() as f64),
             self.stats.bus_traffic,
-            self.stats.bus_num_invalid_or_upd
+            self.stats.bus_num_invalid_or_upd,
         ));
+        if self.stats.arbitration != ArbitrationKind::default() {
+            s.push_str(&format!(
+                "Arbitration Fairness (Jain):       {:.2}\n\n",
+                self.stats.arbitration_fairness
+            ));
+        }
+        if self.stats.bus_num_persistent_requests > 0 {
+            s.push_str(&format!(
+                "No. Persistent Requests:           {:?}\n\n",
+                self.stats.bus_num_persistent_requests
+            ));
+        }
+        if self.stats.bus_num_split_transactions > 0 {
+            s.push_str(&format!(
+                "No. Split Transactions:            {:?}\n\
+                 Max. Outstanding Transactions:     {:?}\n\
+                 Bus Utilization:                   {:.2}\n\n",
+                self.stats.bus_num_split_transactions,
+                self.stats.bus_max_outstanding,
+                (self.stats.bus_busy_cycles as f64
+                    / (self.stats.exec_cycles * self.stats.buses.len().max(1)) as f64),
+            ));
+        }
+        if self.stats.buses.len() > 1 {
+            let name = match self.stats.directory {
+                Some(_) => "Home Node",
+                None => "Bus",
+            };
+            for (bus_idx, bus) in self.stats.buses.iter().enumerate() {
+                s.push_str(&format!(
+                    "{:<35}{:?} Bytes\n{:<35}{:.2}\n",
+                    format!("{} {} Traffic:", name, bus_idx),
+                    bus.traffic,
+                    format!("{} {} Utilization:", name, bus_idx),
+                    (bus.busy_cycles as f64 / self.stats.exec_cycles as f64),
+                ));
+            }
+            s.push('\n');
+        }
+        if self.stats.num_stale_reads > 0 {
+            s.push_str(&format!(
+                "No. Stale Reads:                   {:?}\n\n",
+                self.stats.num_stale_reads
+            ));
+        }
+        if let Some(l2) = &self.stats.l2 {
+            let num_accesses = (l2.num_hits + l2.num_misses) as f64;
+            s.push_str(&format!(
+                "No. L2 Hits:                       {:<10} ({:.2})\n\
+                 No. L2 Misses:                     {:<10} ({:.2})\n\n",
+                l2.num_hits,
+                (l2.num_hits as f64 / num_accesses),
+                l2.num_misses,
+                (l2.num_misses as f64 / num_accesses),
+            ));
+        }
+        if let Some(dram) = &self.stats.dram {
+            let num_requests = dram.num_requests() as f64;
+            s.push_str(&format!(
+                "Row Buffer Hit Rate:               {:.2}\n\
+                 No. Row Buffer Misses:             {:?}\n\
+                 No. Row Buffer Conflicts:          {:?}\n\
+                 Avg. Memory Latency:               {:.2} Cycles\n\n",
+                (dram.num_row_hits as f64 / num_requests),
+                dram.num_row_misses,
+                dram.num_row_conflicts,
+                (dram.total_latency as f64 / num_requests),
+            ));
+        }
+        let hierarchy = &self.stats.cache.hierarchy;
+        if !hierarchy.num_hits.is_empty() {
+            let num_loads =
+                (hierarchy.num_hits.iter().sum::<usize>() + hierarchy.num_misses) as f64;
+            for (level, hits) in hierarchy.num_hits.iter().enumerate() {
+                s.push_str(&format!(
+                    "{:<35}{:<10} ({:.2})\n",
+                    format!("No. Upper Level {} Load Hits:", level + 1),
+                    hits,
+                    (*hits as f64 / num_loads),
+                ));
+            }
+            s.push_str(&format!(
+                "No. Upper Level Load Misses:       {:<10} ({:.2})\n\
+                 No. Back-Invalidations:            {:?}\n\
+                 No. Inclusion Victims:             {:?}\n\
+                 No. Snoop Invalidations:           {:?}\n\n",
+                hierarchy.num_misses,
+                (hierarchy.num_misses as f64 / num_loads),
+                hierarchy.num_back_invalidations,
+                hierarchy.num_inclusion_victims,
+                hierarchy.num_snoop_invalidations,
+            ));
+        }
+        if let Some(victim) = &self.stats.cache.victim {
+            s.push_str(&format!(
+                "No. Victim Cache Hits:             {:?}\n\
+                 No. Victim Cache Insertions:       {:?}\n\
+                 No. Victim Cache Invalidations:    {:?}\n\n",
+                victim.num_hits, victim.num_insertions, victim.num_invalidations,
+            ));
+        }
+        if let Some(prefetch) = &self.stats.cache.prefetch {
+            // prefetched blocks replace demand misses
+            let num_misses = prefetch.num_useful + self.stats.cache.num_data_cache_misses;
+            s.push_str(&format!(
+                "No. Prefetches:                    {:?}\n\
+                 No. Useful Prefetches:             {:<10} (Accuracy: {:.2}, Coverage: {:.2})\n\
+                 No. Unused Prefetches Evicted:     {:?}\n\
+                 No. Prefetch Invalidations:        {:?}\n\n",
+                prefetch.num_issued,
+                prefetch.num_useful,
+                (prefetch.num_useful as f64 / prefetch.num_issued as f64),
+                (prefetch.num_useful as f64 / num_misses as f64),
+                prefetch.num_evicted_unused,
+                prefetch.num_invalidated,
+            ));
+        }
+        if let Some(mshr) = &self.stats.cache.mshr {
+            s.push_str(&format!(
+                "No. Primary Misses (MSHR):         {:?}\n\
+                 No. Secondary Misses (merged):     {:?}\n\
+                 No. Hits under Miss:               {:?}\n\n",
+                mshr.num_primary_misses, mshr.num_secondary_misses, mshr.num_hits_under_miss,
+            ));
+        }
+        if let Some(buffer) = &self.stats.store_buffer {
+            s.push_str(&format!(
+                "No. Buffered Stores:               {:?}\n\
+                 No. Forwarded Loads:               {:?}\n\
+                 Max. Store Buffer Occupancy:       {:?}\n\n",
+                buffer.num_buffered_stores, buffer.num_forwarded_loads, buffer.max_occupancy,
+            ));
+        }
+        if let Some(directory) = &self.stats.directory {
+            s.push_str(&format!(
+                "No. Directory Lookups:             {:?}\n\
+                 No. Directory Messages:            {:?}\n\
+                 \tRequests:                  {:?}\n\
+                 \tForwards / Invalidations:  {:?}\n\
+                 \tAcknowledgements:          {:?}\n\
+                 \tReplies:                   {:?}\n\
+                 Avg. Message Latency:              {:.2} Cycles\n\
+                 Directory Indirection Cycles:      {:?}\n\
+                 No. Directory Broadcasts:          {:?}\n\
+                 No. Pointer Overflow Invalidations:{:?}\n\
+                 No. Directory Entry Evictions:     {:?}\n\
+                 \tResulting Invalidations:   {:?}\n\
+                 No. Invalidation Writebacks:       {:?}\n\n",
+                directory.num_lookups,
+                directory.num_messages(),
+                directory.num_requests,
+                directory.num_forwards,
+                directory.num_acks,
+                directory.num_replies,
+                (directory.message_latency as f64 / directory.num_messages() as f64),
+                directory.indirection_cycles,
+                directory.num_broadcasts,
+                directory.num_overflow_invalidations,
+                directory.num_entry_evictions,
+                directory.num_eviction_invalidations,
+                directory.num_invalidation_writebacks,
+            ));
+        }
+        s.push_str("Core Statistics:\n");
         for (id, core) in self.stats.cores.iter().enumerate() {
             s.push_str(&format!(
                 "\tCore {:?} ({:?}):\n\
@@ -62,7 +248,7 @@ impl Analyzer {
                  \t\tNo. Load Instructions:    {:<10} ({:.2})\n\
                  \t\tNo. Store Insutrctions:   {:<10} ({:.2})\n\
                  \t\tNo. Data Cache Hits:      {:<10} ({:.2})\n\
-                 \t\tNo. Data Cache Misses:    {:<10} ({:.2})\n\n",
+                 \t\tNo. Data Cache Misses:    {:<10} ({:.2})\n",
                 id,
                 core.file_name,
                 core.num_instructions,
@@ -81,7 +267,37 @@ impl Analyzer {
                 (core.cache.num_data_cache_hits as f64 / core.mem_ops as f64),
                 core.cache.num_data_cache_misses,
                 (core.cache.num_data_cache_misses as f64 / core.mem_ops as f64),
-            ))
+            ));
+            // after the lines of the blocking default, whose positions scripts rely on
+            if self.stats.cache.mshr.is_some() {
+                s.push_str(&format!(
+                    "\t\tBus Stalls:               {:<10} ({:.2})\n\
+                     \t\tMSHR Full Stalls:         {:<10} ({:.2})\n",
+                    core.bus_stall_cycles,
+                    (core.bus_stall_cycles as f64 / core.exec_cycles as f64),
+                    core.mshr_stall_cycles,
+                    (core.mshr_stall_cycles as f64 / core.exec_cycles as f64),
+                ));
+            }
+            if self.stats.store_buffer.is_some() {
+                s.push_str(&format!(
+                    "\t\tDrain Stalls:             {:<10} ({:.2})\n",
+                    core.drain_stall_cycles,
+                    (core.drain_stall_cycles as f64 / core.exec_cycles as f64),
+                ));
+            }
+            if self.stats.arbitration != ArbitrationKind::default() {
+                s.push_str(&format!(
+                    "\t\tArbitration Waits:        {:<10} ({:.2})\n\
+                     \t\tLongest Wait:             {:?}\n\
+                     \t\tNo. Bus Grants:           {:?}\n",
+                    core.arbitration.wait_cycles,
+                    (core.arbitration.wait_cycles as f64 / core.exec_cycles as f64),
+                    core.arbitration.max_wait_cycles,
+                    core.arbitration.num_grants,
+                ));
+            }
+            s.push('\n');
         }
         s
     }
@@ -0,0 +1,202 @@
+use crate::analyzer::Analyzable;
+use clap::ArgEnum;
+use rand::rngs::mock::StepRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+#[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq, Default)]
+pub enum ArbitrationKind {
+    /// The order of the previous cycle, shuffled by a fixed step sequence (the original order of
+    /// the simulator)
+    #[default]
+    Shuffle,
+    /// Priority rotates to the core after the last one granted the bus
+    RoundRobin,
+    /// Lower core ids always win
+    FixedPriority,
+    /// The core waiting longest for the bus wins (first come, first served)
+    Fcfs,
+    /// Uniformly random winner (seeded)
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArbitrationConfig {
+    pub kind: ArbitrationKind,
+    /// Seed of the random policy
+    pub seed: u64,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct ArbitrationStats {
+    /// Cycles the core wanted the bus, but it was busy or granted to another core
+    pub wait_cycles: usize,
+    /// Longest uninterrupted wait for the bus
+    pub max_wait_cycles: usize,
+    /// Transactions the core was granted the bus for
+    pub num_grants: usize,
+}
+
+/// Decides which core gets the free bus. The cores try to acquire the bus in the order of
+/// `order`, so the first one requesting it wins.
+pub struct Arbiter {
+    kind: ArbitrationKind,
+    rng: StdRng,
+    step_rng: StepRng,
+    // order of the previous cycle of the shuffle policy
+    shuffled: Vec<usize>,
+    last_granted: Option<usize>,
+    // cycles the current request of each core has waited
+    age: Vec<usize>,
+    stats: Vec<ArbitrationStats>,
+}
+
+impl Arbiter {
+    pub fn new(config: ArbitrationConfig, num_cores: usize) -> Self {
+        Arbiter {
+            kind: config.kind,
+            rng: StdRng::seed_from_u64(config.seed),
+            step_rng: StepRng::new(0, 1),
+            shuffled: (0..num_cores).collect(),
+            last_granted: None,
+            age: vec![0; num_cores],
+            stats: vec![ArbitrationStats::default(); num_cores],
+        }
+    }
+
+    /// Priority order of the cores for this cycle (highest priority first).
+    pub fn order(&mut self, cores: &[usize]) -> Vec<usize> {
+        let mut order = cores.to_vec();
+        order.sort_unstable();
+        match self.kind {
+            ArbitrationKind::RoundRobin => {
+                if let Some(last) = self.last_granted {
+                    let first = order.partition_point(|c| *c <= last);
+                    order.rotate_left(first);
+                }
+            }
+            ArbitrationKind::FixedPriority => (),
+            // stable sort: cores that waited equally long are ordered by id
+            ArbitrationKind::Fcfs => order.sort_by_key(|c| std::cmp::Reverse(self.age[*c])),
+            ArbitrationKind::Random => order.shuffle(&mut self.rng),
+            ArbitrationKind::Shuffle => {
+                self.shuffled.retain(|c| cores.contains(c));
+                self.shuffled.shuffle(&mut self.step_rng);
+                order.clone_from(&self.shuffled);
+            }
+        }
+        order
+    }
+
+    /// Record the outcome of the cycle: the cores that were denied the bus and the cores that were
+    /// granted a bus, in the order they were granted it.
+    pub fn update(&mut self, denied: &[usize], granted: &[usize]) {
+        for (core_id, age) in self.age.iter_mut().enumerate() {
+            if !granted.contains(&core_id) && denied.contains(&core_id) {
+                *age += 1;
+                let stats = &mut self.stats[core_id];
+                stats.wait_cycles += 1;
+                stats.max_wait_cycles = stats.max_wait_cycles.max(*age);
+            } else {
+                *age = 0;
+            }
+        }
+        for core_id in granted {
+            self.stats[*core_id].num_grants += 1;
+            self.last_granted = Some(*core_id);
+        }
+    }
+}
+
+impl Analyzable for Arbiter {
+    fn report(&self, stats: &mut crate::analyzer::Stats) {
+        for (core_stats, arbitration) in stats.cores.iter_mut().zip(self.stats.iter()) {
+            core_stats.arbitration = arbitration.clone();
+        }
+        let wait_cycles: Vec<usize> = self.stats.iter().map(|s| s.wait_cycles).collect();
+        stats.arbitration = self.kind;
+        stats.arbitration_fairness = fairness(&wait_cycles);
+    }
+}
+
+/// Jain's fairness index of the values: 1 if all are equal, 1/n if a single one is non-zero.
+pub fn fairness(values: &[usize]) -> f64 {
+    let sum = values.iter().sum::<usize>() as f64;
+    let sum_of_squares = values.iter().map(|v| (*v as f64).powi(2)).sum::<f64>();
+    if sum_of_squares == 0.0 {
+        return 1.0;
+    }
+    sum * sum / (values.len() as f64 * sum_of_squares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arbiter(kind: ArbitrationKind) -> Arbiter {
+        Arbiter::new(ArbitrationConfig { kind, seed: 0 }, 4)
+    }
+
+    #[test]
+    fn round_robin_rotates_past_grant() {
+        let mut arbiter = arbiter(ArbitrationKind::RoundRobin);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![0, 1, 2, 3]);
+        arbiter.update(&[2], &[1]);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![2, 3, 0, 1]);
+        // inactive cores are skipped
+        arbiter.update(&[], &[2]);
+        assert_eq!(arbiter.order(&[0, 1]), vec![0, 1]);
+        assert_eq!(arbiter.order(&[3, 1, 0]), vec![3, 0, 1]);
+    }
+
+    #[test]
+    fn shuffle_keeps_previous_order() {
+        let mut arbiter = arbiter(ArbitrationKind::Shuffle);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![1, 2, 3, 0]);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![2, 3, 0, 1]);
+        // inactive cores are dropped before the shuffle
+        assert_eq!(arbiter.order(&[0, 2, 3]), vec![3, 0, 2]);
+    }
+
+    #[test]
+    fn fixed_priority_and_random() {
+        let mut arbiter = arbiter(ArbitrationKind::FixedPriority);
+        arbiter.update(&[], &[0]);
+        assert_eq!(arbiter.order(&[3, 0, 2]), vec![0, 2, 3]);
+
+        let mut first = self::arbiter(ArbitrationKind::Random);
+        let mut second = self::arbiter(ArbitrationKind::Random);
+        for _ in 0..10 {
+            let order = first.order(&[0, 1, 2, 3]);
+            assert_eq!(order, second.order(&[0, 1, 2, 3]));
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, vec![0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn fcfs_prefers_oldest_request() {
+        let mut arbiter = arbiter(ArbitrationKind::Fcfs);
+        arbiter.update(&[3], &[0]);
+        arbiter.update(&[1, 3], &[0]);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![3, 1, 0, 2]);
+        arbiter.update(&[1], &[3]);
+        assert_eq!(arbiter.order(&[0, 1, 2, 3]), vec![1, 0, 2, 3]);
+
+        let stats = &arbiter.stats;
+        assert_eq!(stats[0].num_grants, 2);
+        assert_eq!(stats[1].wait_cycles, 2);
+        assert_eq!(stats[3].wait_cycles, 2);
+        assert_eq!(stats[3].max_wait_cycles, 2);
+        assert_eq!(stats[3].num_grants, 1);
+    }
+
+    #[test]
+    fn jain_fairness() {
+        assert_eq!(fairness(&[0, 0]), 1.0);
+        assert_eq!(fairness(&[5, 5, 5]), 1.0);
+        assert_eq!(fairness(&[8, 0, 0, 0]), 0.25);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::analyzer::Analyzable;
+use crate::memory::Memory;
+use crate::timing::TimingConfig;
 
 // MESI and Dragon bus actions combined
 /// BusAction(address, size_in_bytes)
@@ -53,23 +55,92 @@ impl std::fmt::Debug for BusAction {
     }
 }
 
-#[derive(Default, Debug)]
+/// Cycles of the request (address) phase of a split transaction reading from memory
+pub const REQUEST_CYCLES: usize = 2;
+
+/// Remaining cycles of a transaction the memory controller has not scheduled yet. The controller
+/// sets them every cycle before the bus counts one down, so the transaction never completes.
+const UNSCHEDULED_CYCLES: usize = 2;
+
+/// The bus, optionally made up of several address-interleaved buses. Each of them serves the
+/// transactions to a slice of the blocks (block number modulo number of buses) and is snooped
+/// independently. The queries about a single transaction (`active_task`, `occupied`,
+/// `available`, ...) refer to the selected bus, putting a transaction on the bus selects the bus
+/// serving its block.
+#[derive(Debug)]
 pub struct Bus {
+    slices: Vec<Slice>,
+    // bus the queries about a single transaction refer to
+    selected: usize,
+    // blocks are interleaved over the buses
+    words_per_block: usize,
+    // split-transaction bus: memory transactions release the bus while memory is working
+    split: bool,
+    timing: TimingConfig,
+    num_transactions: usize,
+    // core with an active persistent request (token coherence). The arbiter grants no bus to any
+    // other core until this request was issued.
+    persistent_request: Option<usize>,
+    num_persistent_requests: usize,
+    // cores denied a bus in this cycle and the cores granted one (arbitration)
+    denied: Vec<usize>,
+    granted: Vec<usize>,
+    memory: Memory,
+}
+
+/// State of one of the interleaved buses
+#[derive(Default, Debug)]
+struct Slice {
     task: Option<Task>,
+    // split transactions off the bus (memory phase), in the order they released it
+    outstanding: Vec<Task>,
+    // cycles the active task has held the bus
+    task_cycles: usize,
     stats: BusStats,
 }
 
-#[derive(Default, Debug)]
-struct BusStats {
+#[derive(Default, Debug, Clone)]
+pub struct BusStats {
+    /// Bytes transferred
     pub traffic: usize,
     pub num_invalid_or_upd: usize,
+    /// Cycles in which a transaction occupied the bus
+    pub busy_cycles: usize,
+    pub num_split_transactions: usize,
+    pub max_outstanding: usize,
+}
+
+/// Phase of a bus transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Request and snoop responses (the whole transaction on an atomic bus)
+    Request,
+    /// Off the bus, main memory serves the request (split-transaction bus)
+    Memory,
+    /// Back on the bus, memory returns the data or acknowledges the write
+    Response,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct Task {
+    /// Sequence number of the transaction, unique for each put_on
+    pub id: usize,
     pub issuer_id: usize,
     pub remaining_cycles: usize,
     pub action: BusAction,
+    /// Shared line, raised by snooping caches that hold a copy without supplying the data
+    pub shared: bool,
+    /// Tokens sent to the issuer by the snooping caches (token coherence)
+    pub tokens: usize,
+    /// The owner token is among the tokens sent to the issuer (token coherence)
+    pub owner_token: bool,
+    pub phase: Phase,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::interleaved(TimingConfig::default(), false, 1, 1)
+    }
 }
 
 impl Bus {
@@ -77,84 +148,473 @@ impl Bus {
         Bus::default()
     }
 
-    /// Query number of cycles required for the entered action
-    pub fn price(action: &BusAction) -> usize {
-        match action {
-            BusAction::BusRdMem(_, _) => 100,
-            BusAction::BusRdShared(_, c) => 2 * (c / 4),
-            BusAction::BusRdXMem(_, _) => 100,
-            BusAction::BusRdXShared(_, c) => 2 * (c / 4),
-            BusAction::BusUpdMem(_, _) => 100,
-            BusAction::BusUpdShared(_, c) => 2 * (c / 4),
-            BusAction::Flush(_, _) => 100,
+    /// Bus on which memory transactions are split into a request and a later response phase,
+    /// the bus serves other transactions in between.
+    pub fn split_transaction() -> Self {
+        Bus {
+            split: true,
+            ..Bus::default()
+        }
+    }
+
+    /// Bus pricing its transactions with the timing model (split-transaction bus if split).
+    pub fn with_timing(timing: TimingConfig, split: bool) -> Self {
+        Bus::interleaved(timing, split, 1, 1)
+    }
+
+    /// num_buses buses, the blocks (of words_per_block words) are interleaved over them.
+    pub fn interleaved(
+        timing: TimingConfig,
+        split: bool,
+        num_buses: usize,
+        words_per_block: usize,
+    ) -> Self {
+        Bus {
+            slices: (0..num_buses).map(|_| Slice::default()).collect(),
+            selected: 0,
+            words_per_block,
+            split,
+            timing,
+            num_transactions: 0,
+            persistent_request: None,
+            num_persistent_requests: 0,
+            denied: Vec::new(),
+            granted: Vec::new(),
+            memory: Memory::default(),
         }
     }
 
-    /// Schedule bus transaction
+    pub fn timing(&self) -> TimingConfig {
+        self.timing
+    }
+
+    pub fn num_buses(&self) -> usize {
+        self.slices.len()
+    }
+
+    /// Index of the bus serving the block containing addr
+    pub fn bus_of(&self, addr: u32) -> usize {
+        addr as usize / self.words_per_block % self.slices.len()
+    }
+
+    /// Refer the following queries to the bus serving the block containing addr.
+    pub fn select(&mut self, addr: u32) {
+        self.selected = self.bus_of(addr);
+    }
+
+    /// Refer the following queries to the bus with the index.
+    pub fn select_bus(&mut self, bus_idx: usize) {
+        self.selected = bus_idx;
+    }
+
+    /// Index of the selected bus
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Indices of the buses ordered by the age of their active transaction (idle buses first).
+    /// Snooping the buses in this order reacts to the transactions in the order they were issued.
+    pub fn issue_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.slices.len()).collect();
+        order.sort_by_key(|idx| self.slices[*idx].task.map_or(0, |t| t.id));
+        order
+    }
+
+    fn slice(&self) -> &Slice {
+        &self.slices[self.selected]
+    }
+
+    fn slice_mut(&mut self) -> &mut Slice {
+        &mut self.slices[self.selected]
+    }
+
+    /// Schedule bus transaction (on the bus serving its block)
     pub fn put_on(&mut self, issuer_id: usize, action: BusAction) {
-        assert!(self.task.is_none());
-        self.task = Some(Task {
+        self.select(BusAction::extract_addr(action));
+        assert!(self.available(issuer_id));
+        if self.persistent_request == Some(issuer_id) {
+            self.persistent_request = None;
+        }
+        self.num_transactions += 1;
+        let task = Task {
+            id: self.num_transactions,
             issuer_id,
-            remaining_cycles: Bus::price(&action),
+            remaining_cycles: self.timing.price(&action),
             action,
+            shared: false,
+            tokens: 0,
+            owner_token: false,
+            phase: Phase::Request,
+        };
+        let slice = self.slice_mut();
+        slice.task = Some(task);
+        slice.task_cycles = 0;
+        slice.stats.busy_cycles += 1;
+        self.granted.push(issuer_id);
+    }
+
+    /// Cycles a split transaction holds the bus before memory serves it: the address of reads,
+    /// address and data of writes.
+    fn request_cycles(&self, action: &BusAction) -> usize {
+        match action {
+            BusAction::BusRdMem(_, _) | BusAction::BusRdXMem(_, _) => REQUEST_CYCLES,
+            BusAction::BusUpdMem(_, c) | BusAction::Flush(_, c) => self.timing.transfer_cycles(*c),
+            _ => unreachable!("Transaction is not served by main memory."),
+        }
+    }
+
+    /// Cycles a split transaction holds the bus once memory served it: the data of reads, an
+    /// acknowledgement of writes.
+    fn response_cycles(&self, action: &BusAction) -> usize {
+        match action {
+            BusAction::BusRdMem(_, c) | BusAction::BusRdXMem(_, c) => {
+                self.timing.transfer_cycles(*c)
+            }
+            BusAction::BusUpdMem(_, _) | BusAction::Flush(_, _) => 1,
+            _ => unreachable!("Transaction is not served by main memory."),
+        }
+    }
+
+    /// The active task of the bus leaves it, if its request phase is over and it waits for
+    /// memory.
+    fn release(&mut self, bus_idx: usize) {
+        let task = match self.slices[bus_idx].task {
+            Some(task) if self.split && task.phase == Phase::Request => task,
+            _ => return,
+        };
+        if !matches!(
+            task.action,
+            BusAction::BusRdMem(_, _)
+                | BusAction::BusRdXMem(_, _)
+                | BusAction::BusUpdMem(_, _)
+                | BusAction::Flush(_, _)
+        ) || self.slices[bus_idx].task_cycles < self.request_cycles(&task.action)
+            || task.remaining_cycles <= self.response_cycles(&task.action)
+        {
+            return;
+        }
+        #[cfg(verbose)]
+        println!(
+            "Bus: {:?} by {:?} released the bus",
+            task.action, task.issuer_id
+        );
+        let slice = &mut self.slices[bus_idx];
+        slice.outstanding.push(Task {
+            phase: Phase::Memory,
+            ..task
         });
+        slice.task = None;
+        slice.stats.num_split_transactions += 1;
+        slice.stats.max_outstanding = slice.stats.max_outstanding.max(slice.outstanding.len());
+    }
+
+    /// Advance the split transactions off the bus, until memory served them.
+    fn update_outstanding(&mut self, bus_idx: usize) {
+        for idx in 0..self.slices[bus_idx].outstanding.len() {
+            let task = self.slices[bus_idx].outstanding[idx];
+            if task.remaining_cycles > self.response_cycles(&task.action) {
+                self.slices[bus_idx].outstanding[idx].remaining_cycles -= 1;
+            }
+        }
+    }
+
+    /// The oldest split transaction served by memory returns to the free bus (responses take
+    /// precedence over new requests).
+    fn respond(&mut self, bus_idx: usize) {
+        let slice = &self.slices[bus_idx];
+        if slice.task.is_some() {
+            return;
+        }
+        if let Some(idx) = slice
+            .outstanding
+            .iter()
+            .position(|t| t.remaining_cycles == self.response_cycles(&t.action))
+        {
+            let slice = &mut self.slices[bus_idx];
+            #[cfg(verbose)]
+            println!("Bus: response to {:?}", slice.outstanding[idx].action);
+            slice.task = Some(Task {
+                phase: Phase::Response,
+                ..slice.outstanding.remove(idx)
+            });
+            slice.task_cycles = 0;
+        }
     }
 
     /// Clear current bus transaction
     pub fn clear(&mut self) {
-        self.task = None;
+        self.slice_mut().task = None;
+    }
+
+    /// Transactions on the buses or waiting for memory
+    fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.slices
+            .iter()
+            .flat_map(|s| s.task.iter().chain(s.outstanding.iter()))
+    }
+
+    /// Transaction of the core on a bus or waiting for memory (at most one per core).
+    pub fn in_flight(&self, core_id: usize) -> Option<&Task> {
+        self.tasks().find(|t| t.issuer_id == core_id)
+    }
+
+    /// Transaction with the sequence number, on a bus or waiting for memory.
+    pub fn task(&self, id: usize) -> Option<&Task> {
+        self.tasks().find(|t| t.id == id)
+    }
+
+    /// Main memory (the memory controller) is done with the transaction in cycles, or has not
+    /// scheduled it yet (None) and holds it. The response of a split transaction follows.
+    pub fn serve(&mut self, id: usize, cycles: Option<usize>) {
+        let response = match self.task(id) {
+            Some(task) if self.split => self.response_cycles(&task.action),
+            Some(_) => 0,
+            None => return,
+        };
+        let task = self
+            .slices
+            .iter_mut()
+            .flat_map(|s| s.task.iter_mut().chain(s.outstanding.iter_mut()))
+            .find(|t| t.id == id)
+            .unwrap();
+        task.remaining_cycles = response + cycles.unwrap_or(UNSCHEDULED_CYCLES);
+    }
+
+    /// Returns true if a split transaction to the block containing addr waits for its response.
+    /// Requests for the block have to wait for it.
+    pub fn pending(&self, addr: u32, words_per_block: usize) -> bool {
+        let block = addr / words_per_block as u32;
+        let slice = &self.slices[self.bus_of(addr)];
+        slice
+            .task
+            .iter()
+            .filter(|t| t.phase == Phase::Response)
+            .chain(slice.outstanding.iter())
+            .any(|t| BusAction::extract_addr(t.action) / words_per_block as u32 == block)
     }
 
-    /// Returns true if the bus is currently busy
+    /// Returns true if the selected bus is currently busy
     pub fn occupied(&self) -> bool {
-        self.task.is_some()
+        self.slice().task.is_some()
+    }
+
+    /// Returns true if the core may put a transaction on the selected bus (bus is free and not
+    /// reserved by another core's persistent request). A core has a single transaction in flight,
+    /// it waits for its transaction on another bus.
+    pub fn available(&self, core_id: usize) -> bool {
+        self.available_on(self.selected, core_id)
+    }
+
+    /// Returns true if the core may put a transaction on any of the buses
+    pub fn any_available(&self, core_id: usize) -> bool {
+        (0..self.slices.len()).any(|bus_idx| self.available_on(bus_idx, core_id))
+    }
+
+    fn available_on(&self, bus_idx: usize, core_id: usize) -> bool {
+        self.slices[bus_idx].task.is_none()
+            && self.persistent_request.is_none_or(|c| c == core_id)
+            && self.slices.iter().enumerate().all(|(idx, slice)| {
+                idx == bus_idx
+                    || !slice
+                        .task
+                        .iter()
+                        .chain(slice.outstanding.iter())
+                        .any(|t| t.issuer_id == core_id)
+            })
+    }
+
+    /// Request the selected bus for a transaction of the core. Returns false if it is not
+    /// available, the core then waits for arbitration.
+    pub fn request(&mut self, core_id: usize) -> bool {
+        let available = self.available(core_id);
+        if !available && !self.denied.contains(&core_id) {
+            self.denied.push(core_id);
+        }
+        available
+    }
+
+    /// Cores denied the bus in this cycle
+    pub fn denied(&self) -> &[usize] {
+        &self.denied
+    }
+
+    /// Cores that put a transaction on a bus in this cycle, in the order they were granted it
+    pub fn granted(&self) -> &[usize] {
+        &self.granted
+    }
+
+    /// Reserve the buses for the next transaction of a starving core
+    pub fn activate_persistent_request(&mut self, core_id: usize) {
+        assert!(self.persistent_request.is_none());
+        #[cfg(verbose)]
+        println!("Bus: persistent request of {:?}", core_id);
+        self.persistent_request = Some(core_id);
+        self.num_persistent_requests += 1;
+    }
+
+    /// Core with the currently active persistent request (if any)
+    pub fn persistent_request(&self) -> Option<usize> {
+        self.persistent_request
     }
 
-    /// Advance current bus transaction by one cycle (if any)
+    /// Advance the current bus transactions by one cycle (if any)
     pub fn update(&mut self) {
-        if !self.occupied() {
-            #[cfg(verbose)]
-            println!("Bus: empty");
-            return;
+        self.denied.clear();
+        self.granted.clear();
+        for bus_idx in 0..self.slices.len() {
+            self.update_active(bus_idx);
+            self.update_outstanding(bus_idx);
+            self.release(bus_idx);
+            self.respond(bus_idx);
+            let slice = &mut self.slices[bus_idx];
+            if slice.task.is_some() {
+                slice.stats.busy_cycles += 1;
+            }
         }
-        let task = self.task.as_mut().unwrap();
+    }
+
+    fn update_active(&mut self, bus_idx: usize) {
+        let slice = &mut self.slices[bus_idx];
+        let task = match slice.task.as_mut() {
+            Some(task) => task,
+            None => {
+                #[cfg(verbose)]
+                println!("Bus {:?}: empty", bus_idx);
+                return;
+            }
+        };
         match task.remaining_cycles {
             0 => {
                 match task.action {
                     BusAction::BusUpdMem(_, _)
                     | BusAction::BusUpdShared(_, _)
                     | BusAction::BusRdXMem(_, _)
-                    | BusAction::BusRdXShared(_, _) => self.stats.num_invalid_or_upd += 1,
+                    | BusAction::BusRdXShared(_, _) => slice.stats.num_invalid_or_upd += 1,
                     _ => (),
                 }
-                self.stats.traffic += BusAction::extract_size(task.action);
+                slice.stats.traffic += BusAction::extract_size(task.action);
 
                 #[cfg(verbose)]
-                println!("Bus: empty");
-                self.task = None;
+                println!("Bus {:?}: empty", bus_idx);
+                slice.task = None;
             }
             i => {
                 task.remaining_cycles = i - 1;
+                slice.task_cycles += 1;
                 #[cfg(verbose)]
                 println!(
-                    "Bus: {:?} by {:?}, remaining: {:?}",
-                    self.task.as_ref().unwrap().action,
-                    self.task.as_ref().unwrap().issuer_id,
-                    self.task.as_ref().unwrap().remaining_cycles
+                    "Bus {:?}: {:?} by {:?}, remaining: {:?}",
+                    bus_idx, task.action, task.issuer_id, task.remaining_cycles
                 );
             }
         }
     }
 
-    /// Get currently scheduled bus transaction (if any)
+    /// Main memory (version store) attached to the bus
+    pub fn memory(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Get currently scheduled transaction of the selected bus (if any)
     pub fn active_task(&mut self) -> Option<&mut Task> {
-        self.task.as_mut()
+        self.slice_mut().task.as_mut()
     }
 }
 
 impl Analyzable for Bus {
     fn report(&self, stats: &mut crate::analyzer::Stats) {
-        stats.bus_traffic = self.stats.traffic;
-        stats.bus_num_invalid_or_upd = self.stats.num_invalid_or_upd;
+        stats.buses = self.slices.iter().map(|s| s.stats.clone()).collect();
+        stats.bus_traffic = stats.buses.iter().map(|s| s.traffic).sum();
+        stats.bus_num_invalid_or_upd = stats.buses.iter().map(|s| s.num_invalid_or_upd).sum();
+        stats.bus_num_persistent_requests = self.num_persistent_requests;
+        stats.bus_busy_cycles = stats.buses.iter().map(|s| s.busy_cycles).sum();
+        stats.bus_num_split_transactions =
+            stats.buses.iter().map(|s| s.num_split_transactions).sum();
+        stats.bus_max_outstanding = stats.buses.iter().map(|s| s.max_outstanding).max().unwrap();
+        stats.num_stale_reads = self.memory.num_stale_reads();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 32;
+    const WORDS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+
+    #[test]
+    fn split_transactions_overlap() {
+        let mut bus = Bus::split_transaction();
+        bus.put_on(0, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        for _ in 0..REQUEST_CYCLES {
+            assert!(bus.occupied());
+            bus.update();
+        }
+        // memory serves the read off the bus
+        assert!(!bus.occupied());
+        assert_eq!(bus.in_flight(0).unwrap().phase, Phase::Memory);
+        assert!(bus.pending(0x107, WORDS_PER_BLOCK));
+        assert!(!bus.pending(0x108, WORDS_PER_BLOCK));
+
+        bus.put_on(1, BusAction::BusRdMem(0x200, BLOCK_SIZE));
+        for _ in REQUEST_CYCLES..bus.timing().price(&BusAction::BusRdMem(0, 0)) {
+            bus.update();
+        }
+        // both requests overlap, the first one completes after the latency of the atomic bus
+        let task = *bus.active_task().unwrap();
+        assert_eq!(
+            (task.issuer_id, task.phase, task.remaining_cycles),
+            (0, Phase::Response, 0)
+        );
+        assert_eq!(bus.in_flight(1).unwrap().phase, Phase::Memory);
+
+        // the second response waited for the bus
+        bus.update();
+        let task = *bus.active_task().unwrap();
+        assert_eq!((task.issuer_id, task.phase), (1, Phase::Response));
+        for _ in 0..bus.response_cycles(&task.action) {
+            bus.update();
+        }
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 0);
+        bus.update();
+        assert!(bus.in_flight(0).is_none() && bus.in_flight(1).is_none());
+        assert_eq!(bus.slices[0].stats.num_split_transactions, 2);
+        assert_eq!(bus.slices[0].stats.max_outstanding, 2);
+    }
+
+    #[test]
+    fn responses_take_precedence() {
+        let mut bus = Bus::split_transaction();
+        bus.put_on(0, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        bus.update();
+        bus.update();
+        // a long cache-to-cache transfer holds the bus when memory is done
+        bus.put_on(1, BusAction::BusRdShared(0x200, BLOCK_SIZE));
+        bus.active_task().unwrap().remaining_cycles = 150;
+        for _ in 0..150 {
+            bus.update();
+        }
+        assert_eq!(bus.active_task().unwrap().issuer_id, 1);
+        assert!(!bus.available(2));
+        bus.update();
+        // the response is served before any new request
+        let task = *bus.active_task().unwrap();
+        assert_eq!((task.issuer_id, task.phase), (0, Phase::Response));
+        assert_eq!(task.remaining_cycles, bus.response_cycles(&task.action));
+    }
+
+    #[test]
+    fn atomic_bus_holds_transactions() {
+        let mut bus = Bus::new();
+        bus.put_on(0, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        for _ in 0..bus.timing().price(&BusAction::BusRdMem(0, 0)) {
+            assert!(bus.occupied());
+            assert!(!bus.pending(0x100, WORDS_PER_BLOCK));
+            bus.update();
+        }
+        assert_eq!(bus.active_task().unwrap().phase, Phase::Request);
+        bus.update();
+        assert!(!bus.occupied());
+        assert_eq!(bus.slices[0].stats.num_split_transactions, 0);
     }
 }
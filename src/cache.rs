@@ -1,9 +1,22 @@
 use crate::analyzer::Analyzable;
-use crate::bus::{BusAction, Task};
-use crate::protocol::{ProcessorAction, Protocol, ProtocolBuilder, ProtocolKind};
-use crate::utils::AddressLayout;
+use crate::bus::{BusAction, Phase, Task};
+use crate::hierarchy::{Hierarchy, HierarchyConfig, HierarchyStats, InclusionPolicy};
+use crate::memory::Memory;
+use crate::mshr::{MshrFile, MshrStats};
+use crate::prefetch::{PrefetchConfig, PrefetchStats, PrefetchUnit};
+use crate::protocol::table::ProtocolTable;
+use crate::protocol::transient::Transient;
+use crate::protocol::{Permission, ProcessorAction, Protocol, ProtocolBuilder, ProtocolKind};
+use crate::replacement::{
+    FutureAccesses, ReplacementBuilder, ReplacementConfig, ReplacementPolicy,
+};
+use crate::system::WORD_SIZE;
+use crate::timing::TimingConfig;
+use crate::utils::{AddressLayout, Counter};
+use crate::victim::{VictimCache, VictimStats};
 use crate::Bus;
 use std::collections::VecDeque;
+use std::rc::Rc;
 
 const PLACEHOLDER_TAG: u32 = 0;
 
@@ -13,13 +26,28 @@ pub struct CacheStats {
     pub num_data_cache_hits: isize,
     pub num_private_data_access: usize,
     pub num_shared_data_access: usize,
+    pub hierarchy: HierarchyStats,
+    pub victim: Option<VictimStats>,
+    pub prefetch: Option<PrefetchStats>,
+    pub mshr: Option<MshrStats>,
+}
+
+/// Reason why the cache stalls its core in a cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stall {
+    /// Lookup latency or retry of an access
+    Access,
+    /// Waiting for the bus or an own bus transaction
+    Bus,
+    /// All MSHRs are occupied by outstanding misses (non-blocking caches)
+    MshrFull,
 }
 
 pub struct Cache {
     core_id: usize,
     cache: Vec<Vec<u32>>,
-    lru: Lru,
-    protocol: Box<dyn Protocol>,
+    replacement: Box<dyn ReplacementPolicy>,
+    protocol: Transient,
     addr_layout: AddressLayout,
 
     // size of a block in bytes
@@ -28,15 +56,41 @@ pub struct Cache {
     // Queue of waiting instructions (address, action)
     scheduled_instructions: VecDeque<(u32, ProcessorAction)>,
     stats: CacheStats,
+
+    // versions of the words of each cached block (None if not (yet) supplied)
+    data: Vec<Vec<Option<Vec<u64>>>>,
+    // access waiting for the data of its bus transaction
+    pending_access: Option<(u32, ProcessorAction)>,
+    // dirty blocks dropped by invalidations, to be written to memory
+    writebacks: Vec<(u32, Vec<u64>)>,
+
+    // private levels in front of this (coherent) cache and the latency of the current access
+    hierarchy: Hierarchy,
+    victim: Option<VictimCache>,
+    latency: Counter,
+
+    prefetch: Option<PrefetchUnit>,
+    // outstanding load misses of a non-blocking cache (None: blocking cache)
+    mshrs: Option<MshrFile>,
+    // addresses of the processor accesses of the current cycle
+    accessed: Vec<u32>,
 }
 
 impl Cache {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         core_id: usize,
         cache_size: usize,
         associativity: usize,
         block_size: usize,
         kind: &ProtocolKind,
+        table: Option<&Rc<ProtocolTable>>,
+        hierarchy: &HierarchyConfig,
+        timing: &TimingConfig,
+        replacement: ReplacementConfig,
+        future: Option<FutureAccesses>,
+        prefetch: Option<PrefetchConfig>,
+        mshrs: Option<usize>,
     ) -> Self {
         let set_size = associativity * block_size;
         let num_sets = cache_size / set_size;
@@ -62,75 +116,228 @@ impl Cache {
             block_size,
 
             cache: vec![vec![PLACEHOLDER_TAG; associativity]; num_sets],
-            lru: Lru::new(num_sets, associativity),
-            protocol: ProtocolBuilder::create(
+            replacement: ReplacementBuilder::create(
+                replacement,
                 core_id,
-                kind,
-                cache_size,
-                block_size,
+                num_sets,
                 associativity,
-                &addr_layout,
+                future,
+            ),
+            protocol: Transient::new(
+                core_id,
+                ProtocolBuilder::create(
+                    core_id,
+                    kind,
+                    cache_size,
+                    block_size,
+                    associativity,
+                    &addr_layout,
+                    table,
+                ),
+                block_size / WORD_SIZE,
             ),
             addr_layout,
             scheduled_instructions: VecDeque::new(),
             stats: CacheStats::default(),
+            data: vec![vec![None; associativity]; num_sets],
+            pending_access: None,
+            writebacks: Vec::new(),
+            hierarchy: Hierarchy::new(
+                hierarchy,
+                timing.hit_latency,
+                block_size,
+                block_size / WORD_SIZE,
+            ),
+            victim: hierarchy
+                .victim
+                .map(|config| VictimCache::new(config, block_size / WORD_SIZE)),
+            latency: Counter::new(),
+            prefetch: prefetch.map(PrefetchUnit::new),
+            mshrs: mshrs.map(MshrFile::new),
+            accessed: Vec::new(),
         }
     }
 
     /// Simulate a memory load operation.
     pub fn load(&mut self, addr: u32) {
+        self.replacement.access(addr);
         self.scheduled_instructions
             .push_back((addr, ProcessorAction::Read));
     }
 
     /// Simualate a memory store operation.
     pub fn store(&mut self, addr: u32) {
+        self.replacement.access(addr);
         self.scheduled_instructions
             .push_back((addr, ProcessorAction::Write));
     }
 
+    /// A load was forwarded from the store buffer, it does not reach the cache.
+    pub fn forwarded(&mut self, addr: u32) {
+        // it still takes its place in the access sequence (Belady)
+        self.replacement.access(addr);
+    }
+
     /// Advance internal counters.
-    /// Returns true iff the cache stalls.
-    pub fn update(&mut self, bus: &mut Bus) -> bool {
+    /// Returns the reason iff the cache stalls.
+    pub fn update(&mut self, bus: &mut Bus) -> Option<Stall> {
+        self.issue_miss(bus);
+        // lookup latency of the private levels
+        if self.latency.update() {
+            return Some(Stall::Access);
+        }
         // we currently write to the bus => better back off until this is finished
-        if bus
-            .active_task()
-            .map_or(false, |t| t.issuer_id == self.core_id)
-        {
-            return true;
+        if let Some(task) = bus.in_flight(self.core_id) {
+            // only an access to the block of an own prefetch has to wait for it, non-blocking
+            // caches only wait for blocks without data
+            let task_id = task.id;
+            let waits = match self.prefetch.as_ref().and_then(PrefetchUnit::in_flight) {
+                Some((prefetch_id, block)) if prefetch_id == task_id => self
+                    .scheduled_instructions
+                    .front()
+                    .is_some_and(|(addr, _)| self.block(*addr) == block),
+                _ => true,
+            };
+            if waits && self.mshrs.is_none() {
+                return Some(Stall::Bus);
+            }
+        } else if let Some(prefetch) = self.prefetch.as_mut() {
+            prefetch.completed();
         }
 
         if let Some((addr, action)) = self.scheduled_instructions.pop_front() {
             // loads are always stored as next instruction, stores might place loads in front and
             // are therefore always the last instruction.
-            match action {
+            let stall = match action {
                 ProcessorAction::Read => {
-                    if !self.internal_load(addr, bus) {
+                    let stall = self.internal_load(addr, bus);
+                    if stall.is_some() {
                         self.scheduled_instructions
                             .push_front((addr, ProcessorAction::Read));
                     }
+                    stall
                 }
                 ProcessorAction::Write => {
-                    if !self.internal_store(addr, bus) {
+                    let stall = self.internal_store(addr, bus);
+                    if stall.is_some() {
                         self.scheduled_instructions
                             .push_back((addr, ProcessorAction::Write));
                     }
+                    stall
                 }
-            }
+            };
             assert!(self.scheduled_instructions.len() < 3);
-            return true;
+            return Some(stall.unwrap_or(Stall::Access));
+        }
+        None
+    }
+
+    /// Put the transaction of the next outstanding miss on the bus (non-blocking caches).
+    pub fn issue_miss(&mut self, bus: &mut Bus) {
+        let addr = match self.mshrs.as_ref().and_then(MshrFile::next_to_issue) {
+            Some(addr) => addr,
+            None => return,
+        };
+        bus.select(addr);
+        if !bus.request(self.core_id) {
+            return;
+        }
+        // the core already paid the lookup latency when the miss allocated its MSHR
+        let latency = self.latency.value;
+        if self.access(addr, bus, ProcessorAction::Read) {
+            self.mshrs.as_mut().unwrap().issued();
+            if self.pending_access.is_none() {
+                // served without a bus transaction
+                self.complete_mshr(bus.memory());
+            }
+        }
+        self.latency.value = latency;
+    }
+
+    /// Misses of a non-blocking cache or an access waiting for its transaction are still
+    /// outstanding.
+    pub fn outstanding(&self) -> bool {
+        self.mshrs.as_ref().is_some_and(|mshrs| !mshrs.is_empty()) || self.pending_access.is_some()
+    }
+
+    /// Issue the next prefetch candidate, if neither the bus nor the cache is busy.
+    pub fn prefetch(&mut self, bus: &mut Bus) {
+        if self.prefetch.is_none()
+            || !self.scheduled_instructions.is_empty()
+            || self.pending_access.is_some()
+            || self.outstanding()
+            || !bus.any_available(self.core_id)
+            || bus.in_flight(self.core_id).is_some()
+        {
+            return;
+        }
+        while let Some(block) = self.prefetch.as_mut().unwrap().next() {
+            let addr = block * self.words_per_block() as u32;
+            if self.addr_layout.tag(addr) == PLACEHOLDER_TAG
+                || self.search(addr).is_some()
+                || bus.pending(addr, self.words_per_block())
+                || self.victim.as_ref().is_some_and(|v| v.contains(addr))
+            {
+                continue;
+            }
+            // prefetches never write back the block they replace
+            let (evict_set, evict_block) = self.get_evict_index(addr);
+            let evict_tag = self.cache[evict_set][evict_block];
+            let flat_evict_idx = self.addr_layout.nested_to_flat(evict_set, evict_block);
+            if evict_tag != PLACEHOLDER_TAG
+                && self.protocol.writeback_required(flat_evict_idx, evict_tag)
+            {
+                continue;
+            }
+            // with interleaved buses, the bus of the block might be busy
+            bus.select(addr);
+            if !bus.available(self.core_id) {
+                return;
+            }
+
+            let action = self
+                .protocol
+                .read(addr, None, flat_evict_idx, false, bus)
+                .expect("Prefetch of an uncached block without bus transaction.");
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Prefetch of addr {:#x} ({:?})",
+                self.core_id, addr, action
+            );
+            bus.put_on(self.core_id, action);
+            self.insert_and_evict(addr);
+            self.protocol.await_response(addr, true);
+            let task_id = bus.active_task().unwrap().id;
+            self.prefetch.as_mut().unwrap().issued(task_id, block);
+            return;
         }
-        false
     }
 
     pub fn snoop(&mut self, bus: &mut Bus) {
         if let Some(task) = self.protocol.snoop(bus) {
             *bus.active_task().unwrap() = task
         }
+        if let Some(task) = bus.active_task() {
+            let addr = BusAction::extract_addr(task.action);
+            self.drop_invalid(addr);
+            // upper copies without a valid line in this cache cannot respond to the transaction
+            if task.issuer_id != self.core_id
+                && self.hierarchy.contains(addr)
+                && self.protocol.line_state(addr).0 == Permission::Invalid
+            {
+                self.hierarchy.snooped(addr);
+            }
+            if let (Some(victim), true) = (self.victim.as_mut(), task.issuer_id != self.core_id) {
+                victim.snooped(addr);
+            }
+        }
     }
 
     pub fn after_snoop(&mut self, bus: &mut Bus) {
         self.protocol.after_snoop(bus);
+        if let Some(task) = bus.active_task() {
+            self.drop_invalid(BusAction::extract_addr(task.action));
+        }
         if bus.active_task().map_or(
             false,
             |Task {
@@ -139,10 +346,20 @@ impl Cache {
                  ..
              }| *issuer_id == self.core_id && *remaining_cycles == 0,
         ) {
-            let action = bus.active_task().unwrap().action;
-            if let BusAction::Flush(_, _) = action {
+            let task = *bus.active_task().unwrap();
+            if let BusAction::Flush(_, _) = task.action {
+                return;
+            }
+            // prefetches are no data accesses
+            if self
+                .prefetch
+                .as_ref()
+                .and_then(PrefetchUnit::in_flight)
+                .is_some_and(|(task_id, _)| task_id == task.id)
+            {
                 return;
             }
+            let action = task.action;
             let addr = BusAction::extract_addr(action);
             if self.protocol.is_shared(std::usize::MAX, addr) {
                 self.stats.num_shared_data_access += 1;
@@ -156,9 +373,278 @@ impl Cache {
         self.protocol.read_broadcast(bus);
     }
 
+    /// Permission and protocol state name of the block containing addr. Lines waiting for the
+    /// response of their transaction are in a transient state.
+    pub fn line_state(&self, addr: u32) -> (Permission, String) {
+        self.protocol.line_state(addr)
+    }
+
+    /// The response of the own transaction for the block containing addr arrived.
+    pub fn response(&mut self, addr: u32) {
+        self.protocol.response(addr);
+    }
+
+    /// Permission of the cache for the block containing addr. Copies of the upper levels and
+    /// the victim cache are read-only.
+    pub fn permission(&self, addr: u32) -> Permission {
+        if self.search(addr).is_some() {
+            let (permission, _) = self.protocol.line_state(addr);
+            if permission != Permission::Invalid {
+                return permission;
+            }
+        }
+        if self.hierarchy.contains(addr) || self.victim.as_ref().is_some_and(|v| v.contains(addr)) {
+            Permission::Read
+        } else {
+            Permission::Invalid
+        }
+    }
+
+    /// Start a new cycle: forget the accesses of the last one.
+    pub fn clear_accessed(&mut self) {
+        self.accessed.clear();
+    }
+
+    /// Addresses the processor accessed this cycle.
+    pub fn accessed(&self) -> &[u32] {
+        &self.accessed
+    }
+
+    /// Drop the block containing addr from the cache (e.g. invalidation by the directory).
+    /// Returns whether the block was dirty and had to be written back, or None if the cache held
+    /// no copy of it.
+    pub fn invalidate_block(&mut self, addr: u32) -> Option<bool> {
+        let (set_idx, block_idx) = self.search(addr)?;
+        let held = self.protocol.is_transient(addr)
+            || self.protocol.line_state(addr).0 != Permission::Invalid;
+        let tag = self.cache[set_idx][block_idx];
+        let flat_idx = self.addr_layout.nested_to_flat(set_idx, block_idx);
+        let dirty = self.protocol.writeback_required(flat_idx, tag);
+
+        #[cfg(verbose)]
+        println!(
+            "({:?}) Tag {:#x} invalidated (dirty: {:?}).",
+            self.core_id, tag, dirty
+        );
+
+        if let Some(data) = self.data[set_idx][block_idx].take() {
+            if dirty {
+                self.writebacks
+                    .push((self.addr_layout.addr(tag, set_idx), data));
+            }
+        }
+        self.protocol.invalidate(flat_idx, tag);
+        self.cache[set_idx][block_idx] = PLACEHOLDER_TAG;
+        self.replacement.demote(set_idx, block_idx);
+        self.hierarchy.back_invalidate(addr);
+        let block = self.block(addr);
+        if let Some(prefetch) = self.prefetch.as_mut() {
+            prefetch.invalidated(block);
+        }
+        held.then_some(dirty)
+    }
+
+    pub fn words_per_block(&self) -> usize {
+        self.block_size / WORD_SIZE
+    }
+
+    fn block(&self, addr: u32) -> u32 {
+        addr / self.words_per_block() as u32
+    }
+
+    /// (dirty, word versions) of the valid block containing addr.
+    pub fn block_data(&self, addr: u32) -> Option<(bool, Vec<u64>)> {
+        let (set_idx, block_idx) = self.search(addr)?;
+        if self.protocol.line_state(addr).0 == Permission::Invalid {
+            return None;
+        }
+        let data = self.data[set_idx][block_idx].clone()?;
+        let dirty = self.protocol.writeback_required(
+            self.addr_layout.nested_to_flat(set_idx, block_idx),
+            self.cache[set_idx][block_idx],
+        );
+        Some((dirty, data))
+    }
+
+    /// Supply the data of the block containing addr, if it is valid but holds none yet.
+    pub fn fill(&mut self, addr: u32, data: &[u64]) {
+        if let Some((set_idx, block_idx)) = self.search(addr) {
+            if self.data[set_idx][block_idx].is_none()
+                && self.protocol.line_state(addr).0 != Permission::Invalid
+            {
+                self.data[set_idx][block_idx] = Some(data.to_vec());
+            }
+        }
+    }
+
+    /// Version of the word at addr (if cached with data).
+    pub fn version(&self, addr: u32) -> Option<u64> {
+        let (set_idx, block_idx) = self.search(addr)?;
+        let data = self.data[set_idx][block_idx].as_ref()?;
+        Some(data[addr as usize % self.words_per_block()])
+    }
+
+    /// Pick up a written word from the bus (update protocols).
+    pub fn update_word(&mut self, addr: u32, version: u64) {
+        if let Some((set_idx, block_idx)) = self.search(addr) {
+            let word = addr as usize % self.words_per_block();
+            if let Some(data) = self.data[set_idx][block_idx].as_mut() {
+                data[word] = version;
+            }
+        }
+        self.hierarchy.update_word(addr, version);
+    }
+
+    /// Perform the access that waited for its bus transaction.
+    /// Returns the address of the written word (stores only).
+    pub fn complete_access(&mut self, memory: &mut Memory) -> Option<u32> {
+        let (addr, action) = self.pending_access.take()?;
+        let stored = self.perform(addr, action, memory);
+        self.complete_mshr(memory);
+        stored
+    }
+
+    /// Perform the loads merged into the MSHR of the completed miss.
+    fn complete_mshr(&mut self, memory: &mut Memory) {
+        if let Some(loads) = self.mshrs.as_mut().and_then(MshrFile::complete) {
+            for addr in loads {
+                self.perform(addr, ProcessorAction::Read, memory);
+            }
+        }
+    }
+
+    /// The access needs no bus transaction: the line (or the victim cache for loads) grants it.
+    fn permitted(&self, addr: u32, access_type: &ProcessorAction) -> bool {
+        if self.hierarchy.contains(addr) && *access_type == ProcessorAction::Read {
+            return true;
+        }
+        match (self.protocol.line_state(addr).0, access_type) {
+            (Permission::Write, _) | (Permission::Read, ProcessorAction::Read) => {
+                self.search(addr).is_some()
+            }
+            (_, ProcessorAction::Read) => self.victim.as_ref().is_some_and(|v| v.contains(addr)),
+            _ => false,
+        }
+    }
+
+    /// The block containing addr waits for the response of an own transaction.
+    fn awaiting_data(&self, addr: u32) -> bool {
+        self.protocol.is_transient(addr)
+            || self
+                .search(addr)
+                .is_some_and(|(set_idx, block_idx)| self.data[set_idx][block_idx].is_none())
+                && self.protocol.line_state(addr).0 != Permission::Invalid
+    }
+
+    /// Dirty blocks dropped by invalidations since the last call.
+    pub fn take_writebacks(&mut self) -> Vec<(u32, Vec<u64>)> {
+        std::mem::take(&mut self.writebacks)
+    }
+
+    /// Read or write the version of the accessed word. Loads are checked against the latest
+    /// committed version, stores to clean lines are written through.
+    fn perform(&mut self, addr: u32, action: ProcessorAction, memory: &mut Memory) -> Option<u32> {
+        let (set_idx, block_idx) = self.search(addr)?;
+        let word = addr as usize % self.words_per_block();
+        let data = self.data[set_idx][block_idx]
+            .as_mut()
+            .expect("Accessed block holds no data.");
+        match action {
+            ProcessorAction::Read => {
+                if !memory.check_load(addr, data[word]) {
+                    #[cfg(verbose)]
+                    println!(
+                        "({:?}) Stale read of addr {:#x} (version {:?}).",
+                        self.core_id, addr, data[word]
+                    );
+                }
+                let data = data.clone();
+                self.hierarchy.fill(addr, &data);
+                if self.hierarchy.policy() == InclusionPolicy::Exclusive {
+                    // the block moved up, make it the next victim of this level
+                    self.replacement.demote(set_idx, block_idx);
+                }
+                None
+            }
+            ProcessorAction::Write => {
+                let version = memory.new_version(addr);
+                data[word] = version;
+                let dirty = self.protocol.writeback_required(
+                    self.addr_layout.nested_to_flat(set_idx, block_idx),
+                    self.cache[set_idx][block_idx],
+                );
+                if !dirty {
+                    memory.write_word(addr, version);
+                }
+                self.hierarchy.update_word(addr, version);
+                Some(addr)
+            }
+        }
+    }
+
+    /// Forget the data of the block containing addr once the protocol invalidated it.
+    fn drop_invalid(&mut self, addr: u32) {
+        if let Some((set_idx, block_idx)) = self.search(addr) {
+            if self.protocol.line_state(addr).0 != Permission::Invalid
+                || self.protocol.is_transient(addr)
+            {
+                return;
+            }
+            if self.data[set_idx][block_idx].take().is_some() {
+                self.hierarchy.back_invalidate(addr);
+            }
+            let block = self.block(addr);
+            if let Some(prefetch) = self.prefetch.as_mut() {
+                prefetch.invalidated(block);
+            }
+        }
+    }
+
     /// Returns true if the access operation could be completed / scheduled
     fn access(&mut self, addr: u32, bus: &mut Bus, access_type: ProcessorAction) -> bool {
+        self.accessed.push(addr);
+        bus.select(addr);
+        // split-transaction bus: requests for the block wait for the response of its transaction,
+        // hits of the other caches proceed
+        if bus.pending(addr, self.words_per_block())
+            && (self.protocol.is_transient(addr) || !self.permitted(addr, &access_type))
+        {
+            return false;
+        }
+        // the bus is free, but a cache has one transaction in flight at a time. Meanwhile, only
+        // accesses the line grants without a bus transaction proceed.
+        if bus
+            .in_flight(self.core_id)
+            .is_some_and(|t| t.phase == Phase::Memory)
+            && !self.permitted(addr, &access_type)
+        {
+            return false;
+        }
+        if access_type == ProcessorAction::Read {
+            if let Some(latency) = self.hierarchy.load(addr, bus.memory()) {
+                #[cfg(verbose)]
+                println!("({:?}) Hit in the upper levels.", self.core_id);
+                self.latency.value = latency as u32 - 1;
+                return true;
+            }
+        }
+        self.drop_invalid(addr);
         let store_idx = self.search(addr);
+        let block = self.block(addr);
+        if let Some(prefetch) = self.prefetch.as_mut() {
+            prefetch.demand(block, store_idx.is_none());
+        }
+        if let (None, &ProcessorAction::Read, Some(victim)) =
+            (store_idx, &access_type, self.victim.as_mut())
+        {
+            if victim.load(addr, bus.memory()) {
+                #[cfg(verbose)]
+                println!("({:?}) Hit in the victim cache.", self.core_id);
+                self.latency.value =
+                    (self.hierarchy.miss_latency() + victim.hit_latency()) as u32 - 1;
+                return true;
+            }
+        }
         let (evict_set, evict_block) = self.get_evict_index(addr);
         let evict_tag = self.cache[evict_set][evict_block];
         let flat_store_idx = store_idx
@@ -184,19 +670,32 @@ impl Cache {
                     self.core_id
                 );
 
-                if bus.occupied() {
+                let evict_addr = self.addr_layout.addr(evict_tag, evict_set);
+                bus.select(evict_addr);
+                if !bus.request(self.core_id) {
                     #[cfg(verbose)]
                     println!("({:?}) Bus is busy, write back postponed", self.core_id);
 
                     return false;
                 }
-                bus.put_on(self.core_id, BusAction::Flush(evict_tag, self.block_size));
+                bus.put_on(self.core_id, BusAction::Flush(evict_addr, self.block_size));
                 self.protocol.invalidate(flat_evict_idx, evict_tag);
+                if let Some(data) = self.data[evict_set][evict_block].take() {
+                    bus.memory().write_block(evict_addr, &data);
+                    if let Some(victim) = self.victim.as_mut() {
+                        victim.insert(evict_addr, data);
+                    }
+                }
+                self.hierarchy.evicted(evict_addr);
+                let evicted_block = self.block(evict_addr);
+                if let Some(prefetch) = self.prefetch.as_mut() {
+                    prefetch.evicted(evicted_block);
+                }
 
                 // clear cache for later insert
                 self.cache[evict_set][evict_block] = PLACEHOLDER_TAG;
-                // set Lru to low value, so this cell will be evicted next.
-                self.lru.eliminate(evict_set, evict_block);
+                // this cell will be evicted next
+                self.replacement.demote(evict_set, evict_block);
 
                 #[cfg(verbose)]
                 println!("({:?}) Writeback commissioned.", self.core_id);
@@ -222,7 +721,7 @@ impl Cache {
         };
 
         if let Some(action) = bus_action {
-            if bus.occupied() {
+            if !bus.request(self.core_id) {
                 #[cfg(verbose)]
                 println!(
                     "({:?}) Cache access required the bus ({:?}), which is busy.",
@@ -257,6 +756,17 @@ impl Cache {
             self.stats.num_data_cache_misses += 1;
         }
 
+        if bus_action.is_some() {
+            // completed once the transaction supplied the data
+            let (set_idx, block_idx) = self.search(addr).unwrap();
+            let data = self.data[set_idx][block_idx].is_none();
+            self.protocol.await_response(addr, data);
+            self.pending_access = Some((addr, access_type));
+        } else {
+            self.perform(addr, access_type, bus.memory());
+        }
+        self.latency.value = self.hierarchy.miss_latency() as u32 - 1;
+
         #[cfg(verbose)]
         println!(
             "({:?}) Cache access of addr {:#x} successfully completed.",
@@ -265,24 +775,66 @@ impl Cache {
         true
     }
 
-    /// Returns true if the operation could be completed / scheduled
-    fn internal_load(&mut self, addr: u32, bus: &mut Bus) -> bool {
+    /// Returns the reason if the operation could not be completed / scheduled
+    fn internal_load(&mut self, addr: u32, bus: &mut Bus) -> Option<Stall> {
         #[cfg(verbose)]
         println!(
             "({:?}) Load of addr {:#x} requested (cache).",
             self.core_id, addr
         );
-        self.access(addr, bus, ProcessorAction::Read)
+        if self.mshrs.is_some() {
+            return self.non_blocking_load(addr, bus);
+        }
+        (!self.access(addr, bus, ProcessorAction::Read)).then_some(Stall::Bus)
     }
 
-    /// Returns true if the operation could be completed / scheduled
-    fn internal_store(&mut self, addr: u32, bus: &mut Bus) -> bool {
+    /// Returns the reason if the operation could not be completed / scheduled
+    fn internal_store(&mut self, addr: u32, bus: &mut Bus) -> Option<Stall> {
         #[cfg(verbose)]
         println!(
             "({:?}) Store to addr {:#x} requested (cache).",
             self.core_id, addr
         );
-        self.access(addr, bus, ProcessorAction::Write)
+        // stores wait for outstanding misses to their block
+        let block = self.block(addr);
+        if self
+            .mshrs
+            .as_ref()
+            .is_some_and(|mshrs| mshrs.contains(block) || self.awaiting_data(addr))
+        {
+            return Some(Stall::Bus);
+        }
+        (!self.access(addr, bus, ProcessorAction::Write)).then_some(Stall::Bus)
+    }
+
+    /// Hits are served under outstanding misses, misses are merged into the MSHR of their block
+    /// or allocate a new one.
+    fn non_blocking_load(&mut self, addr: u32, bus: &mut Bus) -> Option<Stall> {
+        let block = self.block(addr);
+        if self.mshrs.as_mut().unwrap().merge(addr, block) {
+            #[cfg(verbose)]
+            println!("({:?}) Load merged into outstanding miss.", self.core_id);
+            return None;
+        }
+        let hit = self.hierarchy.contains(addr)
+            || self.victim.as_ref().is_some_and(|v| v.contains(addr))
+            || (self.search(addr).is_some()
+                && !self.awaiting_data(addr)
+                && self.protocol.line_state(addr).0 != Permission::Invalid);
+        if hit {
+            if !self.access(addr, bus, ProcessorAction::Read) {
+                return Some(Stall::Bus);
+            }
+            self.mshrs.as_mut().unwrap().hit_under_miss();
+            return None;
+        }
+        if !self.mshrs.as_mut().unwrap().allocate(addr, block) {
+            return Some(Stall::MshrFull);
+        }
+        #[cfg(verbose)]
+        println!("({:?}) Miss, MSHR allocated.", self.core_id);
+        self.latency.value = self.hierarchy.miss_latency() as u32 - 1;
+        None
     }
 
     fn search_cache_set(&self, addr: u32, cache_set: &[u32]) -> Option<usize> {
@@ -316,34 +868,66 @@ impl Cache {
     fn log_access(&mut self, set_idx: usize, block_idx: usize) {
         #[cfg(verbose)]
         println!(
-            "({:?}) Tag {:#x} accessed, updating LRU",
+            "({:?}) Tag {:#x} accessed, updating replacement state",
             self.core_id, self.cache[set_idx][block_idx]
         );
 
-        self.lru.update(set_idx, block_idx);
+        self.replacement.touch(set_idx, block_idx);
     }
 
     fn get_evict_index(&self, addr_to_load: u32) -> (usize, usize) {
         let set_idx = self.addr_layout.index(addr_to_load);
-        self.lru.get_lru_idx(set_idx)
+        (set_idx, self.replacement.victim(set_idx))
     }
 
     fn insert_and_evict(&mut self, addr_to_load: u32) {
         let new_tag = self.addr_layout.tag(addr_to_load);
         let set_idx = self.addr_layout.index(addr_to_load);
         let evict_idx = self.get_evict_index(addr_to_load).1;
+        let old_tag = self.cache[set_idx][evict_idx];
+        if old_tag != PLACEHOLDER_TAG {
+            let old_addr = self.addr_layout.addr(old_tag, set_idx);
+            self.hierarchy.evicted(old_addr);
+            let old_block = self.block(old_addr);
+            if let Some(prefetch) = self.prefetch.as_mut() {
+                prefetch.evicted(old_block);
+            }
+            // only valid lines hold data
+            if let (Some(victim), Some(data)) =
+                (self.victim.as_mut(), self.data[set_idx][evict_idx].take())
+            {
+                victim.insert(old_addr, data);
+            }
+        }
+        if let Some(victim) = self.victim.as_mut() {
+            victim.remove(addr_to_load);
+        }
         let cache_set = &mut self.cache[set_idx];
 
-        #[cfg(verbose)]
-        let old_tag = cache_set[evict_idx];
         #[cfg(verbose)]
         println!(
             "({:?}) Tag {:#x} evicted from cache, tag {:#x} loaded. (Set {:?}, Block {:?})",
             self.core_id, old_tag, new_tag, set_idx, evict_idx
         );
 
-        self.lru.update(set_idx, evict_idx);
+        self.replacement.insert(set_idx, evict_idx, addr_to_load);
         cache_set[evict_idx] = new_tag;
+        self.data[set_idx][evict_idx] = None;
+    }
+
+    /// (debugging only) (block address, tokens, owner token) of all cached lines that hold tokens
+    #[cfg(sanity_check)]
+    pub fn tokens(&self) -> Vec<(u32, usize, bool)> {
+        let mut tokens = Vec::new();
+        for (set_idx, set) in self.cache.iter().enumerate() {
+            for block in set.iter().filter(|b| **b != PLACEHOLDER_TAG) {
+                let addr = self.addr_layout.addr(*block, set_idx);
+                if let Some((num_tokens, owner)) = self.protocol.tokens(addr) {
+                    tokens.push((addr, num_tokens, owner));
+                }
+            }
+        }
+        tokens
     }
 
     #[cfg(sanity_check)]
@@ -361,41 +945,6 @@ impl Cache {
     }
 }
 
-struct Lru {
-    storage: Vec<Vec<usize>>,
-    cnt: usize,
-}
-
-impl Lru {
-    pub fn new(num_sets: usize, associativity: usize) -> Lru {
-        Lru {
-            storage: vec![vec![0; associativity]; num_sets],
-            cnt: 1,
-        }
-    }
-
-    pub fn update(&mut self, set_idx: usize, block_idx: usize) {
-        self.storage[set_idx][block_idx] = self.cnt;
-        self.cnt += 1;
-    }
-
-    pub fn eliminate(&mut self, set_idx: usize, block_idx: usize) {
-        self.storage[set_idx][block_idx] = 0;
-    }
-
-    pub fn get_lru_idx(&self, set_idx: usize) -> (usize, usize) {
-        (
-            set_idx,
-            self.storage[set_idx]
-                .iter()
-                .enumerate()
-                .min_by(|(_, i1), (_, i2)| i1.cmp(i2))
-                .unwrap()
-                .0,
-        )
-    }
-}
-
 impl Analyzable for Cache {
     fn report(&self, stats: &mut crate::analyzer::Stats) {
         let c_stats = &mut stats.cores[self.core_id];
@@ -408,5 +957,48 @@ impl Analyzable for Cache {
         stats.cache.num_data_cache_misses += self.stats.num_data_cache_misses;
         stats.cache.num_private_data_access += self.stats.num_private_data_access;
         stats.cache.num_shared_data_access += self.stats.num_shared_data_access;
+
+        let hierarchy = self.hierarchy.stats();
+        c_stats.cache.hierarchy = hierarchy.clone();
+        let total = &mut stats.cache.hierarchy;
+        total.num_hits.resize(hierarchy.num_hits.len(), 0);
+        for (sum, hits) in total.num_hits.iter_mut().zip(hierarchy.num_hits.iter()) {
+            *sum += hits;
+        }
+        total.num_misses += hierarchy.num_misses;
+        total.num_back_invalidations += hierarchy.num_back_invalidations;
+        total.num_inclusion_victims += hierarchy.num_inclusion_victims;
+        total.num_snoop_invalidations += hierarchy.num_snoop_invalidations;
+
+        if let Some(victim) = &self.victim {
+            let victim = victim.stats();
+            c_stats.cache.victim = Some(victim.clone());
+            let total = stats.cache.victim.get_or_insert_with(VictimStats::default);
+            total.num_hits += victim.num_hits;
+            total.num_insertions += victim.num_insertions;
+            total.num_invalidations += victim.num_invalidations;
+        }
+
+        if let Some(prefetch) = &self.prefetch {
+            let prefetch = prefetch.stats();
+            c_stats.cache.prefetch = Some(prefetch.clone());
+            let total = stats
+                .cache
+                .prefetch
+                .get_or_insert_with(PrefetchStats::default);
+            total.num_issued += prefetch.num_issued;
+            total.num_useful += prefetch.num_useful;
+            total.num_evicted_unused += prefetch.num_evicted_unused;
+            total.num_invalidated += prefetch.num_invalidated;
+        }
+
+        if let Some(mshrs) = &self.mshrs {
+            let mshrs = mshrs.stats();
+            c_stats.cache.mshr = Some(mshrs.clone());
+            let total = stats.cache.mshr.get_or_insert_with(MshrStats::default);
+            total.num_primary_misses += mshrs.num_primary_misses;
+            total.num_secondary_misses += mshrs.num_secondary_misses;
+            total.num_hits_under_miss += mshrs.num_hits_under_miss;
+        }
     }
 }
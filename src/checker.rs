@@ -0,0 +1,86 @@
+use crate::bus::{Bus, BusAction};
+use crate::core::Core;
+use crate::protocol::Permission;
+use std::collections::VecDeque;
+
+// number of bus transactions included in a violation report
+const HISTORY_LEN: usize = 8;
+
+struct Transaction {
+    clk: usize,
+    id: usize,
+    issuer_id: usize,
+    action: BusAction,
+}
+
+/// Single-writer/multiple-reader check across all caches, performed after every cycle for the
+/// blocks the cycle touched.
+pub struct CoherenceChecker {
+    history: VecDeque<Transaction>,
+}
+
+impl CoherenceChecker {
+    pub fn new() -> Self {
+        CoherenceChecker {
+            history: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    /// Remember the active bus transaction (snooping caches may have replaced its action).
+    /// Returns the address it accesses.
+    pub fn record(&mut self, clk: usize, bus: &mut Bus) -> Option<u32> {
+        let task = *bus.active_task()?;
+        // responses of split transactions return to the bus after other transactions
+        match self.history.iter_mut().find(|t| t.id == task.id) {
+            Some(last) => last.action = task.action,
+            None => {
+                if self.history.len() == HISTORY_LEN {
+                    self.history.pop_front();
+                }
+                self.history.push_back(Transaction {
+                    clk,
+                    id: task.id,
+                    issuer_id: task.issuer_id,
+                    action: task.action,
+                });
+            }
+        }
+        Some(BusAction::extract_addr(task.action))
+    }
+
+    /// Panic with a report if a block is writable in one cache while another one holds a copy.
+    /// Only the blocks touched this cycle can change their permissions: the blocks of the active
+    /// bus transactions and of the accesses of the cores.
+    pub fn check(&self, clk: usize, bus_addrs: &[u32], cores: &[Core]) {
+        let accessed = cores.iter().flat_map(|core| core.accessed().iter());
+        for addr in bus_addrs.iter().chain(accessed) {
+            let permissions: Vec<Permission> =
+                cores.iter().map(|core| core.permission(*addr)).collect();
+            let holders = permissions
+                .iter()
+                .filter(|p| **p != Permission::Invalid)
+                .count();
+            if holders > 1 && permissions.contains(&Permission::Write) {
+                panic!("{}", self.report(clk, *addr, cores));
+            }
+        }
+    }
+
+    fn report(&self, clk: usize, addr: u32, cores: &[Core]) -> String {
+        let mut report = format!(
+            "Coherence violation in cycle {} for block {:#x}.\nCore states:",
+            clk, addr
+        );
+        for (core_id, core) in cores.iter().enumerate() {
+            report += &format!("\n  core {}: {}", core_id, core.line_state(addr));
+        }
+        report += "\nLast bus transactions:";
+        for t in self.history.iter() {
+            report += &format!(
+                "\n  cycle {}: {:?} by core {}",
+                t.clk, t.action, t.issuer_id
+            );
+        }
+        report
+    }
+}
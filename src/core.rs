@@ -1,10 +1,19 @@
 use crate::analyzer::Analyzable;
+use crate::arbiter::ArbitrationStats;
 use crate::bus::Bus;
-use crate::cache::{Cache, CacheStats};
-use crate::protocol::ProtocolKind;
+use crate::cache::{Cache, CacheStats, Stall};
+use crate::hierarchy::HierarchyConfig;
+use crate::prefetch::PrefetchConfig;
+use crate::protocol::table::ProtocolTable;
+use crate::protocol::{Permission, ProtocolKind};
 use crate::record::{Label, RecordStream};
+use crate::replacement::{FutureAccesses, ReplacementConfig, ReplacementKind};
+use crate::store_buffer::{StoreBuffer, StoreBufferStats};
+use crate::system::WORD_SIZE;
+use crate::timing::TimingConfig;
 use crate::utils::Counter;
 use indicatif::*;
+use std::rc::Rc;
 
 #[derive(Default, Clone, Debug)]
 pub struct CoreStats {
@@ -13,15 +22,32 @@ pub struct CoreStats {
     pub compute_cycles: usize,
     pub mem_ops: usize,
     pub idle_cycles: usize,
+    /// Idle cycles waiting for the bus or an own bus transaction
+    pub bus_stall_cycles: usize,
+    /// Idle cycles waiting for a free MSHR (non-blocking caches)
+    pub mshr_stall_cycles: usize,
+    /// Idle cycles waiting for the store buffer to drain
+    pub drain_stall_cycles: usize,
+    /// Bus arbitration of the core (reported by the arbiter)
+    pub arbitration: ArbitrationStats,
     pub num_instructions: usize,
     pub load_instructions: usize,
     pub store_instructions: usize,
     pub cache: CacheStats,
+    pub store_buffer: Option<StoreBufferStats>,
 }
 
 pub struct Core {
     cache: Cache,
     alu: Counter,
+    // retired stores not yet written to the cache (None: stores wait for the cache)
+    store_buffer: Option<StoreBuffer>,
+    // store that found the store buffer full
+    blocked_store: Option<u32>,
+    // the oldest buffered store is in the cache
+    draining: bool,
+    // the core waits for a load in the cache
+    awaits_load: bool,
     records: RecordStream,
     id: usize,
     progress_bar: ProgressBar,
@@ -29,14 +55,22 @@ pub struct Core {
 }
 
 impl Core {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         protocol: &ProtocolKind,
+        table: Option<&Rc<ProtocolTable>>,
         cache_size: usize,
         associativity: usize,
         block_size: usize,
-        records: RecordStream,
+        mut records: RecordStream,
         id: usize,
         mp_bar: &MultiProgress,
+        hierarchy: &HierarchyConfig,
+        timing: &TimingConfig,
+        replacement: ReplacementConfig,
+        prefetch: Option<PrefetchConfig>,
+        mshrs: Option<usize>,
+        store_buffer: Option<usize>,
     ) -> Self {
         println!("({:?}) loaded {:?}", id, records.file_name);
 
@@ -50,9 +84,29 @@ impl Core {
             .unwrap()
             .progress_chars("=>-"),
         );
+        // Belady replacement needs a first pass over the trace
+        let future = (replacement.kind == ReplacementKind::Belady)
+            .then(|| FutureAccesses::scan(&mut records, block_size / WORD_SIZE));
         Core {
-            cache: Cache::new(id, cache_size, associativity, block_size, protocol),
+            cache: Cache::new(
+                id,
+                cache_size,
+                associativity,
+                block_size,
+                protocol,
+                table,
+                hierarchy,
+                timing,
+                replacement,
+                future,
+                prefetch,
+                mshrs,
+            ),
             alu: Counter::new(),
+            store_buffer: store_buffer.map(StoreBuffer::new),
+            blocked_store: None,
+            draining: false,
+            awaits_load: false,
             progress_bar: pb,
             id,
             stats: CoreStats {
@@ -65,13 +119,43 @@ impl Core {
 
     /// Simulate one cycle. Return false if no more instructions are left to process.
     pub fn step(&mut self, bus: &mut Bus, clk: usize) -> bool {
+        self.cache.clear_accessed();
         // stall, if required. Remember: if they return false, then they didn't work yet.
         if self.alu.update() {
+            // the cache issues outstanding misses and prefetches while the core computes
+            self.cache.issue_miss(bus);
+            // buffered stores drain while the core computes
+            if self.store_buffer.is_some() && self.cache.update(bus).is_none() {
+                self.drain_store();
+            }
+            self.cache.prefetch(bus);
             return true;
         }
-        if self.cache.update(bus) {
-            self.stats.idle_cycles += 1;
-            return true;
+        let cache_idle = match self.cache.update(bus) {
+            // a draining store occupies the cache, but does not stall the core
+            Some(_) if self.draining && !self.awaits_load => false,
+            Some(stall) => {
+                self.stats.idle_cycles += 1;
+                match stall {
+                    Stall::Bus => self.stats.bus_stall_cycles += 1,
+                    Stall::MshrFull => self.stats.mshr_stall_cycles += 1,
+                    Stall::Access => (),
+                }
+                return true;
+            }
+            None => {
+                self.awaits_load = false;
+                self.drain_store();
+                true
+            }
+        };
+        if let Some(addr) = self.blocked_store {
+            if !self.store_buffer.as_mut().unwrap().push(addr) {
+                self.stats.idle_cycles += 1;
+                self.stats.drain_stall_cycles += 1;
+                return true;
+            }
+            self.blocked_store = None;
         }
 
         if let Some(record) = self.records.next() {
@@ -96,14 +180,46 @@ impl Core {
 
             self.progress_bar.inc(1);
 
+            let forwarded = record.label == Label::Load
+                && self
+                    .store_buffer
+                    .as_mut()
+                    .is_some_and(|buffer| buffer.forward(record.value));
             match (&record.label, record.value) {
-                (Label::Load, ref value) => self.cache.load(*value),
-                (Label::Store, ref value) => self.cache.store(*value),
-                (Label::Other, ref value) => self.alu.value = *value,
+                (Label::Load, value) if forwarded => {
+                    #[cfg(verbose)]
+                    println!("({:?}) Load forwarded from the store buffer.", self.id);
+                    self.cache.forwarded(value);
+                }
+                (Label::Load, value) => {
+                    self.cache.load(value);
+                    self.awaits_load = true;
+                }
+                (Label::Store, value) => match self.store_buffer.as_mut() {
+                    Some(buffer) => {
+                        if !buffer.push(value) {
+                            self.blocked_store = Some(value);
+                        }
+                    }
+                    None => self.cache.store(value),
+                },
+                (Label::Other, value) => self.alu.value = value,
             }
             // they still have a free step in this cycle!
             self.alu.update();
-            self.cache.update(bus);
+            if cache_idle {
+                self.cache.update(bus);
+            }
+            true
+        } else if self.cache.outstanding() {
+            // wait for the last misses
+            self.stats.idle_cycles += 1;
+            self.stats.bus_stall_cycles += 1;
+            true
+        } else if self.store_buffer.as_ref().is_some_and(|b| !b.is_empty()) {
+            // wait for the last buffered stores
+            self.stats.idle_cycles += 1;
+            self.stats.drain_stall_cycles += 1;
             true
         } else {
             self.stats.exec_cycles = clk;
@@ -112,6 +228,23 @@ impl Core {
         }
     }
 
+    /// Retire the oldest buffered store once the cache performed it and hand the next one to
+    /// the cache.
+    fn drain_store(&mut self) {
+        let buffer = match self.store_buffer.as_mut() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        if self.draining {
+            buffer.pop();
+            self.draining = false;
+        }
+        if let Some(addr) = buffer.front() {
+            self.cache.store(addr);
+            self.draining = true;
+        }
+    }
+
     pub fn snoop(&mut self, bus: &mut Bus) {
         self.cache.snoop(bus);
     }
@@ -125,9 +258,38 @@ impl Core {
         self.cache.sanity_check();
     }
 
+    #[cfg(sanity_check)]
+    pub fn tokens(&self) -> Vec<(u32, usize, bool)> {
+        self.cache.tokens()
+    }
+
     pub fn read_broadcast(&mut self, bus: &mut Bus) {
         self.cache.read_broadcast(bus);
     }
+
+    /// Permission of the cache for the block containing addr.
+    pub fn permission(&self, addr: u32) -> Permission {
+        self.cache.permission(addr)
+    }
+
+    /// Addresses the core accessed this cycle.
+    pub fn accessed(&self) -> &[u32] {
+        self.cache.accessed()
+    }
+
+    /// Protocol state name of the line holding addr.
+    pub fn line_state(&self, addr: u32) -> String {
+        self.cache.line_state(addr).1
+    }
+
+    pub fn cache_mut(&mut self) -> &mut Cache {
+        &mut self.cache
+    }
+
+    /// Returns whether the invalidated block was dirty, None if the core held no copy.
+    pub fn invalidate(&mut self, addr: u32) -> Option<bool> {
+        self.cache.invalidate_block(addr)
+    }
 }
 
 impl Analyzable for Core {
@@ -139,11 +301,25 @@ impl Analyzable for Core {
         c_stats.compute_cycles = self.stats.compute_cycles;
         c_stats.mem_ops = self.stats.mem_ops;
         c_stats.idle_cycles = self.stats.idle_cycles;
+        c_stats.bus_stall_cycles = self.stats.bus_stall_cycles;
+        c_stats.mshr_stall_cycles = self.stats.mshr_stall_cycles;
+        c_stats.drain_stall_cycles = self.stats.drain_stall_cycles;
 
         c_stats.num_instructions = self.stats.num_instructions;
         c_stats.load_instructions = self.stats.load_instructions;
         c_stats.store_instructions = self.stats.store_instructions;
 
+        if let Some(buffer) = &self.store_buffer {
+            let buffer = buffer.stats();
+            c_stats.store_buffer = Some(buffer.clone());
+            let total = stats
+                .store_buffer
+                .get_or_insert_with(StoreBufferStats::default);
+            total.num_buffered_stores += buffer.num_buffered_stores;
+            total.num_forwarded_loads += buffer.num_forwarded_loads;
+            total.max_occupancy = total.max_occupancy.max(buffer.max_occupancy);
+        }
+
         self.cache.report(stats);
     }
 }
@@ -0,0 +1,641 @@
+use crate::analyzer::Analyzable;
+use crate::bus::{Bus, BusAction};
+use crate::system::WORD_SIZE;
+use crate::utils::LruSets;
+use clap::ArgEnum;
+use std::collections::HashMap;
+
+/// Organization of the sharer information of a directory entry
+#[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq)]
+pub enum DirectoryKind {
+    /// One presence bit per core
+    FullMap,
+    /// Dir_i B: i pointers, requests are broadcast once the pointers overflow
+    LimitedBroadcast,
+    /// Dir_i NB: i pointers, a sharer is invalidated once the pointers overflow
+    LimitedNoBroadcast,
+}
+
+/// Organization and latencies (in cycles) of the directory
+#[derive(Debug, Clone, Copy)]
+pub struct DirectoryConfig {
+    pub kind: DirectoryKind,
+    /// Number of sharer pointers per entry (limited-pointer directories only)
+    pub num_pointers: usize,
+    /// Number of directory entries of a sparse directory, unlimited if None
+    pub num_entries: Option<usize>,
+    /// Associativity of a sparse directory
+    pub associativity: usize,
+    /// Cycles for one lookup in the home directory
+    pub lookup_latency: usize,
+    /// Cycles for one point-to-point message between two nodes
+    pub hop_latency: usize,
+}
+
+impl Default for DirectoryConfig {
+    fn default() -> Self {
+        DirectoryConfig {
+            kind: DirectoryKind::FullMap,
+            num_pointers: 4,
+            num_entries: None,
+            associativity: 8,
+            lookup_latency: 5,
+            hop_latency: 10,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct DirectoryStats {
+    pub num_lookups: usize,
+    /// Requests (and writebacks) sent to the home node
+    pub num_requests: usize,
+    /// Forwarded requests and invalidations sent to sharers
+    pub num_forwards: usize,
+    /// Acknowledgements and data responses of sharers
+    pub num_acks: usize,
+    /// Replies of the home node
+    pub num_replies: usize,
+    /// Sum of the latencies of all messages
+    pub message_latency: usize,
+    /// Cycles added to transactions by the indirection through the home node
+    pub indirection_cycles: usize,
+    /// Requests that were broadcast because the sharer pointers overflowed (Dir_i B)
+    pub num_broadcasts: usize,
+    /// Sharers invalidated because the sharer pointers overflowed (Dir_i NB)
+    pub num_overflow_invalidations: usize,
+    /// Entries evicted from a sparse directory
+    pub num_entry_evictions: usize,
+    /// Sharers invalidated because their directory entry was evicted
+    pub num_eviction_invalidations: usize,
+    /// Directory-induced invalidations of dirty lines that had to be written back
+    pub num_invalidation_writebacks: usize,
+}
+
+impl DirectoryStats {
+    pub fn num_messages(&self) -> usize {
+        self.num_requests + self.num_forwards + self.num_acks + self.num_replies
+    }
+}
+
+/// Full-map sharer bit vector, one presence bit per core
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct SharerVector {
+    bits: Vec<u64>,
+}
+
+impl SharerVector {
+    pub fn new(num_cores: usize) -> Self {
+        SharerVector {
+            bits: vec![0; num_cores.div_ceil(64)],
+        }
+    }
+
+    pub fn set(&mut self, core_id: usize) {
+        self.bits[core_id / 64] |= 1 << (core_id % 64);
+    }
+
+    pub fn clear(&mut self, core_id: usize) {
+        self.bits[core_id / 64] &= !(1 << (core_id % 64));
+    }
+
+    pub fn contains(&self, core_id: usize) -> bool {
+        self.bits[core_id / 64] & (1 << (core_id % 64)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|b| *b == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.bits.len() * 64).filter(|&i| self.contains(i))
+    }
+}
+
+/// Sharer information of one directory entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Sharers {
+    Map(SharerVector),
+    /// Sharer pointers (oldest first). The broadcast bit is set once the pointers overflowed in a
+    /// Dir_i B directory, the sharers are unknown from then on.
+    Pointers {
+        pointers: Vec<usize>,
+        broadcast: bool,
+    },
+}
+
+impl Sharers {
+    fn new(kind: DirectoryKind, num_cores: usize) -> Self {
+        match kind {
+            DirectoryKind::FullMap => Sharers::Map(SharerVector::new(num_cores)),
+            _ => Sharers::Pointers {
+                pointers: Vec::new(),
+                broadcast: false,
+            },
+        }
+    }
+
+    /// All cores that might hold the block
+    fn holders(&self, num_cores: usize) -> Vec<usize> {
+        match self {
+            Sharers::Map(sharers) => sharers.iter().collect(),
+            Sharers::Pointers {
+                broadcast: true, ..
+            } => (0..num_cores).collect(),
+            Sharers::Pointers { pointers, .. } => pointers.clone(),
+        }
+    }
+
+    fn is_broadcast(&self) -> bool {
+        matches!(
+            self,
+            Sharers::Pointers {
+                broadcast: true,
+                ..
+            }
+        )
+    }
+
+    /// Register a new sharer. Returns the sharer that has to be invalidated because the pointers
+    /// overflowed (Dir_i NB only).
+    fn add(&mut self, core_id: usize, kind: DirectoryKind, num_pointers: usize) -> Option<usize> {
+        match self {
+            Sharers::Map(sharers) => sharers.set(core_id),
+            Sharers::Pointers {
+                pointers,
+                broadcast,
+            } => {
+                if *broadcast || pointers.contains(&core_id) {
+                    return None;
+                }
+                if pointers.len() < num_pointers {
+                    pointers.push(core_id);
+                } else if kind == DirectoryKind::LimitedBroadcast {
+                    pointers.clear();
+                    *broadcast = true;
+                } else {
+                    pointers.push(core_id);
+                    return Some(pointers.remove(0));
+                }
+            }
+        }
+        None
+    }
+
+    fn remove(&mut self, core_id: usize) {
+        match self {
+            Sharers::Map(sharers) => sharers.clear(core_id),
+            // the remaining sharers are unknown, stay in broadcast mode
+            Sharers::Pointers {
+                broadcast: true, ..
+            } => (),
+            Sharers::Pointers { pointers, .. } => pointers.retain(|c| *c != core_id),
+        }
+    }
+
+    /// The core becomes the only sharer
+    fn reset(&mut self, core_id: usize) {
+        match self {
+            Sharers::Map(sharers) => {
+                sharers
+                    .iter()
+                    .collect::<Vec<usize>>()
+                    .into_iter()
+                    .for_each(|c| sharers.clear(c));
+                sharers.set(core_id);
+            }
+            Sharers::Pointers {
+                pointers,
+                broadcast,
+            } => {
+                *pointers = vec![core_id];
+                *broadcast = false;
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Sharers::Map(sharers) => sharers.is_empty(),
+            Sharers::Pointers {
+                pointers,
+                broadcast,
+            } => !broadcast && pointers.is_empty(),
+        }
+    }
+}
+
+/// Transaction of a bus the directory forwards
+#[derive(Default)]
+struct Route {
+    id: usize,
+    targets: Vec<usize>,
+}
+
+/// Home-node directory that replaces the broadcast of bus transactions. The blocks are
+/// interleaved over one home node per core, modelled as one slice of the interconnect each: a home
+/// node serializes the transactions to its blocks, while the transactions of different home nodes
+/// proceed in parallel. A request is only forwarded to the caches that are registered as sharers
+/// of the block. The indirection through the home node is added to the latency of the
+/// transaction.
+pub struct Directory {
+    config: DirectoryConfig,
+    num_cores: usize,
+    words_per_block: u32,
+    entries: HashMap<u32, Sharers>,
+    sparse: Option<LruSets>,
+    // currently served transaction of each bus: id and cores that receive a forward /
+    // invalidation
+    routes: Vec<Route>,
+    // true in the cycle a new transaction arrived at the home node
+    fresh: bool,
+    stats: DirectoryStats,
+}
+
+impl Directory {
+    pub fn new(config: DirectoryConfig, num_cores: usize, block_size: usize) -> Self {
+        Directory {
+            config,
+            num_cores,
+            words_per_block: (block_size / WORD_SIZE) as u32,
+            entries: HashMap::new(),
+            sparse: config
+                .num_entries
+                .map(|num_entries| LruSets::new(num_entries, config.associativity)),
+            routes: Vec::new(),
+            fresh: false,
+            stats: DirectoryStats::default(),
+        }
+    }
+
+    /// Look up a new bus transaction in the directory.
+    /// Returns the cores that have to snoop the active transaction.
+    pub fn route(&mut self, bus: &mut Bus) -> &[usize] {
+        let bus_idx = bus.selected();
+        if self.routes.len() < bus.num_buses() {
+            self.routes.resize_with(bus.num_buses(), Route::default);
+        }
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => {
+                self.routes[bus_idx].targets.clear();
+                return &self.routes[bus_idx].targets;
+            }
+        };
+        if task.id != self.routes[bus_idx].id {
+            self.routes[bus_idx].id = task.id;
+            self.fresh = true;
+            self.stats.num_lookups += 1;
+
+            let block = self.block(BusAction::extract_addr(task.action));
+            self.routes[bus_idx].targets = match (task.action, self.entries.get(&block)) {
+                // writebacks only update the directory
+                (BusAction::Flush(_, _), _) | (_, None) => Vec::new(),
+                (_, Some(sharers)) => {
+                    if sharers.is_broadcast() {
+                        self.stats.num_broadcasts += 1;
+                    }
+                    sharers
+                        .holders(self.num_cores)
+                        .into_iter()
+                        .filter(|c| *c != task.issuer_id)
+                        .collect()
+                }
+            };
+            #[cfg(verbose)]
+            println!(
+                "Directory: {:?} by {:?}, sharers: {:?}",
+                task.action, task.issuer_id, self.routes[bus_idx].targets
+            );
+        }
+        &self.routes[bus_idx].targets
+    }
+
+    /// Update the sharers of the block and charge the indirection latency to the transaction.
+    /// Sharers that lose their copy because of the directory organization are invalidated via
+    /// `invalidate(core_id, addr)`, which returns whether the line had to be written back, or
+    /// None if the core had already dropped its copy silently.
+    /// Only has an effect in the cycle the transaction was looked up.
+    pub fn after_snoop<F>(&mut self, bus: &mut Bus, mut invalidate: F)
+    where
+        F: FnMut(usize, u32) -> Option<bool>,
+    {
+        let timing = bus.timing();
+        if !self.fresh {
+            return;
+        }
+        self.fresh = false;
+        // the transaction might have been dropped during snooping (e.g. Dragon update without
+        // sharers)
+        let route = &self.routes[bus.selected()];
+        let (active_id, num_targets) = (route.id, route.targets.len());
+        let task = match bus.active_task() {
+            Some(t) if t.id == active_id => t,
+            _ => return,
+        };
+
+        let num_messages = self.stats.num_messages();
+        let block = self.block(BusAction::extract_addr(task.action));
+        let (kind, num_cores) = (self.config.kind, self.num_cores);
+        let sharers = self
+            .entries
+            .entry(block)
+            .or_insert_with(|| Sharers::new(kind, num_cores));
+
+        // (core, block, pointer overflow) triples that lose their copy due to the directory
+        // organization
+        let mut invalidations: Vec<(usize, u32, bool)> = Vec::new();
+        let hops = match task.action {
+            // writeback: single message to the home node
+            BusAction::Flush(_, _) => {
+                sharers.remove(task.issuer_id);
+                self.stats.num_requests += 1;
+                1
+            }
+            action => {
+                match action {
+                    // all other copies are invalidated
+                    BusAction::BusRdXMem(_, _) | BusAction::BusRdXShared(_, _) => {
+                        sharers.reset(task.issuer_id)
+                    }
+                    _ => {
+                        if let Some(victim) =
+                            sharers.add(task.issuer_id, kind, self.config.num_pointers)
+                        {
+                            invalidations.push((victim, block, true));
+                        }
+                    }
+                }
+
+                self.stats.num_requests += 1;
+                if num_targets == 0 {
+                    // request -> home -> reply
+                    self.stats.num_replies += 1;
+                    2
+                } else {
+                    // request -> home -> forward to sharers -> ack / data to requester
+                    self.stats.num_forwards += num_targets;
+                    self.stats.num_acks += num_targets;
+                    3
+                }
+            }
+        };
+
+        if sharers.is_empty() {
+            self.entries.remove(&block);
+            if let Some(sparse) = self.sparse.as_mut() {
+                sparse.remove(block);
+            }
+        } else if let Some(evicted) = self.sparse.as_mut().and_then(|s| s.touch(block)) {
+            // no room for the entry: all sharers of the evicted entry lose their copy
+            let evicted_sharers = self.entries.remove(&evicted).unwrap();
+            self.stats.num_entry_evictions += 1;
+            for core_id in evicted_sharers.holders(self.num_cores) {
+                invalidations.push((core_id, evicted, false));
+            }
+        }
+
+        let mut latency = self.config.lookup_latency + hops * self.config.hop_latency;
+        if !invalidations.is_empty() {
+            // invalidations are sent in parallel: one hop there, one hop back (+ writeback)
+            let mut writeback = false;
+            for (core_id, block, overflow) in invalidations.iter() {
+                // clean copies may have been replaced without notifying the directory
+                let dirty = match invalidate(*core_id, block * self.words_per_block) {
+                    Some(dirty) => dirty,
+                    None => continue,
+                };
+                if *overflow {
+                    self.stats.num_overflow_invalidations += 1;
+                } else {
+                    self.stats.num_eviction_invalidations += 1;
+                }
+                if dirty {
+                    self.stats.num_invalidation_writebacks += 1;
+                    writeback = true;
+                }
+            }
+            self.stats.num_forwards += invalidations.len();
+            self.stats.num_acks += invalidations.len();
+            latency += 2 * self.config.hop_latency;
+            if writeback {
+                latency += timing.writeback_latency;
+            }
+            #[cfg(verbose)]
+            println!("Directory: invalidated {:?}", invalidations);
+        }
+
+        task.remaining_cycles += latency;
+        self.stats.indirection_cycles += latency;
+        self.stats.message_latency +=
+            (self.stats.num_messages() - num_messages) * self.config.hop_latency;
+    }
+
+    fn block(&self, addr: u32) -> u32 {
+        addr / self.words_per_block
+    }
+}
+
+impl Analyzable for Directory {
+    fn report(&self, stats: &mut crate::analyzer::Stats) {
+        stats.directory = Some(self.stats.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUM_CORES: usize = 4;
+    const BLOCK_SIZE: usize = 16;
+
+    #[test]
+    fn sharer_vector() {
+        let mut sharers = SharerVector::new(130);
+        assert!(sharers.is_empty());
+        sharers.set(0);
+        sharers.set(64);
+        sharers.set(129);
+        assert!(sharers.contains(64));
+        assert_eq!(sharers.iter().collect::<Vec<usize>>(), vec![0, 64, 129]);
+        sharers.clear(64);
+        assert!(!sharers.contains(64));
+        assert_eq!(sharers.iter().collect::<Vec<usize>>(), vec![0, 129]);
+    }
+
+    #[test]
+    fn read_without_sharers() {
+        let config = DirectoryConfig::default();
+        let mut directory = Directory::new(config, NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        bus.put_on(0, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert!(directory.route(&mut bus).is_empty());
+        directory.after_snoop(&mut bus, |_, _| None);
+
+        assert_eq!(
+            bus.active_task().unwrap().remaining_cycles,
+            100 + config.lookup_latency + 2 * config.hop_latency
+        );
+        assert_eq!(directory.stats.num_lookups, 1);
+        assert_eq!(directory.stats.num_messages(), 2);
+
+        // later cycles of the same transaction are neither looked up nor charged again
+        assert!(directory.route(&mut bus).is_empty());
+        directory.after_snoop(&mut bus, |_, _| None);
+        assert_eq!(directory.stats.num_lookups, 1);
+        assert_eq!(
+            bus.active_task().unwrap().remaining_cycles,
+            100 + config.lookup_latency + 2 * config.hop_latency
+        );
+    }
+
+    #[test]
+    fn forward_to_sharers() {
+        let config = DirectoryConfig::default();
+        let mut directory = Directory::new(config, NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        for core_id in 0..2 {
+            bus.put_on(core_id, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+            directory.route(&mut bus);
+            directory.after_snoop(&mut bus, |_, _| None);
+            bus.clear();
+        }
+
+        // same block, different word
+        bus.put_on(2, BusAction::BusRdXMem(0x101, BLOCK_SIZE));
+        assert_eq!(directory.route(&mut bus), &[0, 1]);
+        directory.after_snoop(&mut bus, |_, _| None);
+        bus.clear();
+        // the second read was already forwarded to core 0
+        assert_eq!(directory.stats.num_forwards, 1 + 2);
+        assert_eq!(directory.stats.num_acks, 1 + 2);
+
+        // all other copies are invalidated
+        bus.put_on(3, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert_eq!(directory.route(&mut bus), &[2]);
+        directory.after_snoop(&mut bus, |_, _| None);
+        assert_eq!(
+            bus.active_task().unwrap().remaining_cycles,
+            100 + config.lookup_latency + 3 * config.hop_latency
+        );
+    }
+
+    #[test]
+    fn writeback_removes_sharer() {
+        let mut directory = Directory::new(DirectoryConfig::default(), NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        bus.put_on(0, BusAction::BusRdXMem(0x100, BLOCK_SIZE));
+        directory.route(&mut bus);
+        directory.after_snoop(&mut bus, |_, _| None);
+        bus.clear();
+
+        bus.put_on(0, BusAction::Flush(0x100, BLOCK_SIZE));
+        assert!(directory.route(&mut bus).is_empty());
+        directory.after_snoop(&mut bus, |_, _| None);
+        bus.clear();
+        assert!(directory.entries.is_empty());
+
+        bus.put_on(1, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert!(directory.route(&mut bus).is_empty());
+    }
+
+    #[test]
+    fn limited_pointers_broadcast() {
+        let config = DirectoryConfig {
+            kind: DirectoryKind::LimitedBroadcast,
+            num_pointers: 2,
+            ..Default::default()
+        };
+        let mut directory = Directory::new(config, NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        for core_id in 0..3 {
+            bus.put_on(core_id, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+            directory.route(&mut bus);
+            directory.after_snoop(&mut bus, |_, _| panic!("Dir_i B never invalidates"));
+            bus.clear();
+        }
+        assert_eq!(directory.stats.num_broadcasts, 0);
+
+        // pointers overflowed: the request is sent to all other cores
+        bus.put_on(3, BusAction::BusRdXMem(0x100, BLOCK_SIZE));
+        assert_eq!(directory.route(&mut bus), &[0, 1, 2]);
+        directory.after_snoop(&mut bus, |_, _| None);
+        bus.clear();
+        assert_eq!(directory.stats.num_broadcasts, 1);
+
+        // exclusive access resets the entry
+        bus.put_on(0, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert_eq!(directory.route(&mut bus), &[3]);
+    }
+
+    #[test]
+    fn limited_pointers_no_broadcast() {
+        let config = DirectoryConfig {
+            kind: DirectoryKind::LimitedNoBroadcast,
+            num_pointers: 2,
+            ..Default::default()
+        };
+        let mut directory = Directory::new(config, NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        let mut invalidated = Vec::new();
+        for core_id in 0..3 {
+            bus.put_on(core_id, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+            directory.route(&mut bus);
+            directory.after_snoop(&mut bus, |core_id, addr| {
+                invalidated.push((core_id, addr));
+                Some(false)
+            });
+            bus.clear();
+        }
+
+        // the oldest sharer made room for core 2
+        assert_eq!(invalidated, vec![(0, 0x100)]);
+        assert_eq!(directory.stats.num_overflow_invalidations, 1);
+        bus.put_on(3, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert_eq!(directory.route(&mut bus), &[1, 2]);
+    }
+
+    #[test]
+    fn sparse_eviction() {
+        let config = DirectoryConfig {
+            num_entries: Some(2),
+            associativity: 2,
+            ..Default::default()
+        };
+        let mut directory = Directory::new(config, NUM_CORES, BLOCK_SIZE);
+        let mut bus = Bus::new();
+
+        let mut invalidated = Vec::new();
+        for (core_id, addr) in [(0, 0x100), (1, 0x100), (0, 0x200), (2, 0x300)] {
+            bus.put_on(core_id, BusAction::BusRdMem(addr, BLOCK_SIZE));
+            directory.route(&mut bus);
+            // core 0 silently dropped its clean copy of 0x100, core 1 holds it dirty
+            directory.after_snoop(&mut bus, |core_id, addr| {
+                invalidated.push((core_id, addr));
+                (core_id == 1).then_some(true)
+            });
+            let remaining_cycles = bus.active_task().unwrap().remaining_cycles;
+            bus.clear();
+            if addr == 0x300 {
+                // invalidation round trip and writeback of the dirty copy
+                assert_eq!(
+                    remaining_cycles,
+                    100 + config.lookup_latency + 4 * config.hop_latency + 100
+                );
+            }
+        }
+
+        // the least recently used entry (0x100) was evicted
+        assert_eq!(invalidated, vec![(0, 0x100), (1, 0x100)]);
+        assert_eq!(directory.stats.num_entry_evictions, 1);
+        assert_eq!(directory.stats.num_eviction_invalidations, 1);
+        assert_eq!(directory.stats.num_invalidation_writebacks, 1);
+        bus.put_on(3, BusAction::BusRdMem(0x100, BLOCK_SIZE));
+        assert!(directory.route(&mut bus).is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::analyzer::Analyzable;
+use crate::bus::{Bus, BusAction};
+use crate::system::WORD_SIZE;
+
+/// Organization and timing (in cycles) of the DRAM behind the memory controller
+#[derive(Debug, Clone, Copy)]
+pub struct DramConfig {
+    pub channels: usize,
+    /// Banks per channel
+    pub banks: usize,
+    /// Row (page) size in bytes
+    pub row_size: usize,
+    /// Column access to the open row
+    pub t_cas: usize,
+    /// Activation of a row
+    pub t_rcd: usize,
+    /// Precharge of the open row
+    pub t_rp: usize,
+    /// Data burst on the channel
+    pub t_burst: usize,
+}
+
+impl Default for DramConfig {
+    fn default() -> Self {
+        DramConfig {
+            channels: 1,
+            banks: 8,
+            row_size: 2048,
+            t_cas: 44,
+            t_rcd: 44,
+            t_rp: 44,
+            t_burst: 8,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct DramStats {
+    /// Accesses to the open row of the bank
+    pub num_row_hits: usize,
+    /// Accesses to a bank without an open row
+    pub num_row_misses: usize,
+    /// Accesses that had to close another open row first
+    pub num_row_conflicts: usize,
+    /// Cycles from the arrival of the requests at the controller until their data burst ended
+    pub total_latency: usize,
+}
+
+impl DramStats {
+    pub fn num_requests(&self) -> usize {
+        self.num_row_hits + self.num_row_misses + self.num_row_conflicts
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    task_id: usize,
+    // cycle the request reaches the controller
+    arrival: usize,
+    channel: usize,
+    // bank index over all channels
+    bank: usize,
+    row: usize,
+    // cycle the data burst ends, once scheduled
+    done_at: Option<usize>,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+struct Bank {
+    open_row: Option<usize>,
+    busy_until: usize,
+}
+
+/// Memory controller in front of the DRAM. It replaces the flat memory latency of the
+/// transactions served by main memory (that no L2 served) by the latency of their DRAM access:
+/// the requests queue per bank, each bank keeps its last row open (open-page policy) and the
+/// controller schedules first-ready, first-come-first-served (FR-FCFS): requests to the open row
+/// go first, otherwise the oldest request.
+pub struct MemoryController {
+    config: DramConfig,
+    banks: Vec<Bank>,
+    // cycle each channel's data bus becomes free
+    channel_free: Vec<usize>,
+    queue: Vec<Request>,
+    // id of the last transaction that was seen
+    last_task_id: usize,
+    stats: DramStats,
+}
+
+impl MemoryController {
+    pub fn new(config: DramConfig) -> Self {
+        MemoryController {
+            banks: vec![Bank::default(); config.channels * config.banks],
+            channel_free: vec![0; config.channels],
+            config,
+            queue: Vec::new(),
+            last_task_id: 0,
+            stats: DramStats::default(),
+        }
+    }
+
+    /// (channel, bank over all channels, row) of the (word) address. Consecutive rows are
+    /// interleaved over the channels first, then over the banks.
+    fn locate(&self, addr: u32) -> (usize, usize, usize) {
+        let row_idx = addr as usize * WORD_SIZE / self.config.row_size;
+        let channel = row_idx % self.config.channels;
+        let bank = row_idx / self.config.channels % self.config.banks;
+        let row = row_idx / (self.config.channels * self.config.banks);
+        (channel, channel * self.config.banks + bank, row)
+    }
+
+    /// Queue a newly issued transaction served by main memory (unless served_above, e.g. by the
+    /// L2), schedule the waiting requests and tell the bus when memory is done with them.
+    pub fn update(&mut self, bus: &mut Bus, clk: usize, served_above: bool) {
+        let timing = bus.timing();
+        if let Some(task) = bus.active_task() {
+            if task.id > self.last_task_id {
+                self.last_task_id = task.id;
+                let memory = matches!(
+                    task.action,
+                    BusAction::BusRdMem(_, _)
+                        | BusAction::BusRdXMem(_, _)
+                        | BusAction::BusUpdMem(_, _)
+                        | BusAction::Flush(_, _)
+                );
+                if memory && !served_above {
+                    let (channel, bank, row) = self.locate(BusAction::extract_addr(task.action));
+                    self.queue.push(Request {
+                        task_id: task.id,
+                        // latency added in front of memory, e.g. a directory or L2 lookup
+                        arrival: clk
+                            + task
+                                .remaining_cycles
+                                .saturating_sub(timing.price(&task.action)),
+                        channel,
+                        bank,
+                        row,
+                        done_at: None,
+                    });
+                }
+            }
+        }
+        self.schedule(clk);
+
+        for request in self.queue.iter() {
+            bus.serve(request.task_id, request.done_at.map(|done| done - clk));
+        }
+        let stats = &mut self.stats;
+        self.queue.retain(|request| match request.done_at {
+            Some(done) if done <= clk => {
+                stats.total_latency += done - request.arrival;
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// Start the next request of every idle bank (FR-FCFS).
+    fn schedule(&mut self, clk: usize) {
+        for bank_idx in 0..self.banks.len() {
+            let bank = self.banks[bank_idx];
+            if bank.busy_until > clk {
+                continue;
+            }
+            // the queue is ordered by arrival
+            let next = self
+                .queue
+                .iter_mut()
+                .filter(|r| r.bank == bank_idx && r.done_at.is_none() && r.arrival <= clk)
+                .min_by_key(|r| (bank.open_row != Some(r.row), r.arrival));
+            let request = match next {
+                Some(request) => request,
+                None => continue,
+            };
+            let access = match bank.open_row {
+                Some(row) if row == request.row => {
+                    self.stats.num_row_hits += 1;
+                    self.config.t_cas
+                }
+                None => {
+                    self.stats.num_row_misses += 1;
+                    self.config.t_rcd + self.config.t_cas
+                }
+                Some(_) => {
+                    self.stats.num_row_conflicts += 1;
+                    self.config.t_rp + self.config.t_rcd + self.config.t_cas
+                }
+            };
+            let burst = (clk + access).max(self.channel_free[request.channel]);
+            let done = burst + self.config.t_burst;
+            self.channel_free[request.channel] = done;
+            self.banks[bank_idx] = Bank {
+                open_row: Some(request.row),
+                busy_until: clk + access,
+            };
+            request.done_at = Some(done);
+        }
+    }
+}
+
+impl Analyzable for MemoryController {
+    fn report(&self, stats: &mut crate::analyzer::Stats) {
+        stats.dram = Some(self.stats.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::TimingConfig;
+
+    const BLOCK_SIZE: usize = 32;
+
+    fn config() -> DramConfig {
+        DramConfig {
+            channels: 2,
+            banks: 2,
+            row_size: 128,
+            t_cas: 10,
+            t_rcd: 20,
+            t_rp: 30,
+            t_burst: 4,
+        }
+    }
+
+    /// word address of the block in the row of the bank of the channel
+    fn addr(channel: usize, bank: usize, row: usize, block: usize) -> u32 {
+        let row_idx = (row * 2 + bank) * 2 + channel;
+        ((row_idx * 128 + block * BLOCK_SIZE) / WORD_SIZE) as u32
+    }
+
+    /// Issue a read to addr, run the controller until it is served. Returns the cycle the
+    /// request was done.
+    fn read(controller: &mut MemoryController, bus: &mut Bus, clk: &mut usize, addr: u32) -> usize {
+        bus.put_on(0, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        loop {
+            controller.update(bus, *clk, false);
+            if bus.active_task().unwrap().remaining_cycles == 0 {
+                return *clk;
+            }
+            *clk += 1;
+            bus.update();
+        }
+    }
+
+    #[test]
+    fn address_mapping() {
+        let controller = MemoryController::new(config());
+        assert_eq!(controller.locate(addr(0, 0, 0, 3)), (0, 0, 0));
+        assert_eq!(controller.locate(addr(1, 0, 0, 0)), (1, 2, 0));
+        assert_eq!(controller.locate(addr(0, 1, 5, 1)), (0, 1, 5));
+        assert_eq!(controller.locate(addr(1, 1, 2, 0)), (1, 3, 2));
+    }
+
+    #[test]
+    fn row_buffer_latencies() {
+        let mut controller = MemoryController::new(config());
+        let mut bus = Bus::new();
+        let mut clk = 0;
+        // closed bank, open row, other row
+        for (addr, latency) in [
+            (addr(0, 0, 0, 0), 20 + 10 + 4),
+            (addr(0, 0, 0, 1), 10 + 4),
+            (addr(0, 0, 1, 0), 30 + 20 + 10 + 4),
+        ] {
+            let start = clk;
+            assert_eq!(
+                read(&mut controller, &mut bus, &mut clk, addr) - start,
+                latency
+            );
+            clk += 1;
+            bus.update();
+        }
+        let stats = &controller.stats;
+        assert_eq!(
+            (
+                stats.num_row_hits,
+                stats.num_row_misses,
+                stats.num_row_conflicts
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(stats.total_latency, 34 + 14 + 64);
+    }
+
+    #[test]
+    fn first_ready_first() {
+        let mut controller = MemoryController::new(config());
+        controller.banks[0].open_row = Some(1);
+        // an older request to another row waits for a younger one to the open row
+        controller.queue.push(Request {
+            task_id: 1,
+            arrival: 0,
+            channel: 0,
+            bank: 0,
+            row: 0,
+            done_at: None,
+        });
+        controller.queue.push(Request {
+            task_id: 2,
+            arrival: 1,
+            channel: 0,
+            bank: 0,
+            row: 1,
+            done_at: None,
+        });
+        controller.schedule(1);
+        assert_eq!(controller.queue[0].done_at, None);
+        assert_eq!(controller.queue[1].done_at, Some(1 + 10 + 4));
+        // the bank is busy until the column access is done
+        controller.schedule(10);
+        assert_eq!(controller.queue[0].done_at, None);
+        controller.schedule(11);
+        assert_eq!(controller.queue[0].done_at, Some(11 + 30 + 20 + 10 + 4));
+    }
+
+    #[test]
+    fn channel_serializes_bursts() {
+        let mut controller = MemoryController::new(config());
+        for (task_id, bank) in [(1, 0), (2, 1)] {
+            controller.queue.push(Request {
+                task_id,
+                arrival: 0,
+                channel: 0,
+                bank,
+                row: 0,
+                done_at: None,
+            });
+        }
+        controller.schedule(0);
+        assert_eq!(controller.queue[0].done_at, Some(30 + 4));
+        assert_eq!(controller.queue[1].done_at, Some(30 + 4 + 4));
+    }
+
+    #[test]
+    fn writebacks_arrive_without_delay() {
+        let mut controller = MemoryController::new(config());
+        let timing = TimingConfig {
+            writeback_latency: 150,
+            ..Default::default()
+        };
+        let mut bus = Bus::with_timing(timing, false);
+        bus.put_on(0, BusAction::Flush(addr(0, 0, 0, 0), BLOCK_SIZE));
+        controller.update(&mut bus, 0, false);
+        assert_eq!(controller.queue[0].arrival, 0);
+    }
+}
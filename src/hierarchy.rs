@@ -0,0 +1,426 @@
+use crate::memory::Memory;
+use crate::utils::LruSets;
+use crate::victim::VictimConfig;
+use clap::ArgEnum;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Geometry and hit latency (in cycles) of a private cache level in front of the coherent one.
+/// The block size is the one of the coherent level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelConfig {
+    /// Size in bytes
+    pub size: usize,
+    pub associativity: usize,
+    pub hit_latency: usize,
+}
+
+impl FromStr for LevelConfig {
+    type Err = String;
+
+    /// Parse "SIZE:ASSOCIATIVITY:HIT_LATENCY", e.g. "1024:2:1".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<usize> = s
+            .split(':')
+            .map(|f| f.trim().parse::<usize>().map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        match fields[..] {
+            [size, associativity, hit_latency] => Ok(LevelConfig {
+                size,
+                associativity,
+                hit_latency,
+            }),
+            _ => Err(format!(
+                "Expected SIZE:ASSOCIATIVITY:HIT_LATENCY, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Relation between the blocks of the private cache levels
+#[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq, Default)]
+pub enum InclusionPolicy {
+    /// Every level holds the blocks of the levels above it, evictions back-invalidate them
+    #[default]
+    Inclusive,
+    /// Blocks move up on a hit and down on eviction, so that the levels hold disjoint blocks
+    Exclusive,
+    /// Non-inclusive non-exclusive: fills go to all levels, evictions stay local
+    Nine,
+}
+
+#[derive(Debug, Clone)]
+pub struct HierarchyConfig {
+    /// Private levels in front of the coherent cache, closest to the core first
+    pub upper_levels: Vec<LevelConfig>,
+    pub policy: InclusionPolicy,
+    /// Victim cache next to the coherent level
+    pub victim: Option<VictimConfig>,
+}
+
+impl Default for HierarchyConfig {
+    fn default() -> Self {
+        HierarchyConfig {
+            upper_levels: Vec::new(),
+            policy: InclusionPolicy::Inclusive,
+            victim: None,
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct HierarchyStats {
+    /// Load hits per upper level
+    pub num_hits: Vec<usize>,
+    /// Loads that missed all upper levels
+    pub num_misses: usize,
+    /// Upper copies invalidated because the coherent level lost the block to another core
+    pub num_back_invalidations: usize,
+    /// Upper copies invalidated because the block was evicted from a level below (inclusive)
+    pub num_inclusion_victims: usize,
+    /// Upper copies not held by the coherent level that were invalidated by a snooped transaction
+    pub num_snoop_invalidations: usize,
+}
+
+/// Tag and data store of one upper level
+struct Level {
+    config: LevelConfig,
+    blocks: LruSets,
+    // word versions of each held block
+    data: HashMap<u32, Vec<u64>>,
+}
+
+impl Level {
+    /// Insert the block. Returns the evicted block and its data.
+    fn insert(&mut self, block: u32, data: Vec<u64>) -> Option<(u32, Vec<u64>)> {
+        self.data.insert(block, data);
+        let victim = self.blocks.touch(block)?;
+        let victim_data = self.data.remove(&victim).unwrap();
+        Some((victim, victim_data))
+    }
+
+    fn remove(&mut self, block: u32) -> Option<Vec<u64>> {
+        self.blocks.remove(block);
+        self.data.remove(&block)
+    }
+}
+
+/// Private cache levels in front of the coherent cache of a core. The upper levels are
+/// write-through and only allocate on loads, so their copies are never dirty; stores are performed
+/// by the coherent level and update the copies above it.
+/// Coherence is enforced by the coherent level alone: whenever it loses a block to another core,
+/// the copies above are back-invalidated. Copies that outlived an eviction of the coherent level
+/// (exclusive and NINE) cannot answer snoops and are dropped by any foreign transaction.
+pub struct Hierarchy {
+    levels: Vec<Level>,
+    policy: InclusionPolicy,
+    words_per_block: usize,
+    hit_latency: usize,
+    stats: HierarchyStats,
+}
+
+impl Hierarchy {
+    /// hit_latency: hit latency of the coherent level
+    pub fn new(
+        config: &HierarchyConfig,
+        hit_latency: usize,
+        block_size: usize,
+        words_per_block: usize,
+    ) -> Self {
+        Hierarchy {
+            levels: config
+                .upper_levels
+                .iter()
+                .map(|level| Level {
+                    config: *level,
+                    blocks: LruSets::new(level.size / block_size, level.associativity),
+                    data: HashMap::new(),
+                })
+                .collect(),
+            policy: config.policy,
+            words_per_block,
+            hit_latency,
+            stats: HierarchyStats {
+                num_hits: vec![0; config.upper_levels.len()],
+                ..HierarchyStats::default()
+            },
+        }
+    }
+
+    pub fn policy(&self) -> InclusionPolicy {
+        self.policy
+    }
+
+    pub fn stats(&self) -> &HierarchyStats {
+        &self.stats
+    }
+
+    fn block(&self, addr: u32) -> u32 {
+        addr / self.words_per_block as u32
+    }
+
+    /// Latency of an access served by the coherent level (all upper levels missed).
+    pub fn miss_latency(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|l| l.config.hit_latency)
+            .sum::<usize>()
+            + self.hit_latency
+    }
+
+    /// Serve a load from the upper levels, the loaded version is checked against memory.
+    /// Returns the latency of the hit, None if all upper levels missed.
+    pub fn load(&mut self, addr: u32, memory: &mut Memory) -> Option<usize> {
+        let block = self.block(addr);
+        let level_idx = self
+            .levels
+            .iter()
+            .position(|level| level.blocks.contains(block))?;
+        self.stats.num_hits[level_idx] += 1;
+        let latency = self.levels[..=level_idx]
+            .iter()
+            .map(|l| l.config.hit_latency)
+            .sum();
+
+        let data = self.levels[level_idx].data[&block].clone();
+        memory.check_load(addr, data[addr as usize % self.words_per_block]);
+
+        match self.policy {
+            InclusionPolicy::Exclusive => {
+                // move the block up to the first level
+                self.levels[level_idx].remove(block);
+                self.insert_exclusive(block, data);
+            }
+            InclusionPolicy::Inclusive | InclusionPolicy::Nine => {
+                self.levels[level_idx].blocks.touch(block);
+                for idx in (0..level_idx).rev() {
+                    self.insert(idx, block, data.clone());
+                }
+            }
+        }
+        Some(latency)
+    }
+
+    /// Allocate the block in the upper levels after a load was served by the coherent level.
+    pub fn fill(&mut self, addr: u32, data: &[u64]) {
+        if self.levels.is_empty() {
+            return;
+        }
+        self.stats.num_misses += 1;
+        let block = self.block(addr);
+        match self.policy {
+            InclusionPolicy::Exclusive => self.insert_exclusive(block, data.to_vec()),
+            InclusionPolicy::Inclusive | InclusionPolicy::Nine => {
+                // bottom up, so that inclusive victims do not hit the new block
+                for idx in (0..self.levels.len()).rev() {
+                    self.insert(idx, block, data.to_vec());
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, level_idx: usize, block: u32, data: Vec<u64>) {
+        if let Some((victim, _)) = self.levels[level_idx].insert(block, data) {
+            if self.policy == InclusionPolicy::Inclusive {
+                for level in self.levels[..level_idx].iter_mut() {
+                    if level.remove(victim).is_some() {
+                        self.stats.num_inclusion_victims += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Insert into the first level, victims move one level down (and are dropped by the last one).
+    fn insert_exclusive(&mut self, block: u32, data: Vec<u64>) {
+        let mut moving = Some((block, data));
+        for level in self.levels.iter_mut() {
+            moving = match moving {
+                Some((block, data)) => level.insert(block, data),
+                None => return,
+            };
+        }
+    }
+
+    /// Pick up a word written by the own core or by another core (update protocols).
+    pub fn update_word(&mut self, addr: u32, version: u64) {
+        let block = self.block(addr);
+        let word = addr as usize % self.words_per_block;
+        for level in self.levels.iter_mut() {
+            if let Some(data) = level.data.get_mut(&block) {
+                data[word] = version;
+            }
+        }
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        let block = self.block(addr);
+        self.levels.iter().any(|level| level.blocks.contains(block))
+    }
+
+    /// Drop all upper copies of the block. Returns the number of dropped copies.
+    fn invalidate(&mut self, addr: u32) -> usize {
+        let block = self.block(addr);
+        self.levels
+            .iter_mut()
+            .filter_map(|level| level.remove(block))
+            .count()
+    }
+
+    /// The coherent level lost the block to another core.
+    pub fn back_invalidate(&mut self, addr: u32) {
+        self.stats.num_back_invalidations += self.invalidate(addr);
+    }
+
+    /// The coherent level evicted the block.
+    pub fn evicted(&mut self, addr: u32) {
+        if self.policy == InclusionPolicy::Inclusive {
+            self.stats.num_inclusion_victims += self.invalidate(addr);
+        }
+    }
+
+    /// Another core accesses a block that is not held by the coherent level.
+    pub fn snooped(&mut self, addr: u32) {
+        self.stats.num_snoop_invalidations += self.invalidate(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 16;
+
+    // L1: 2 blocks (fully associative), L2: 4 blocks (2 sets of 2)
+    fn hierarchy(policy: InclusionPolicy) -> Hierarchy {
+        Hierarchy::new(
+            &HierarchyConfig {
+                upper_levels: vec![
+                    LevelConfig {
+                        size: 32,
+                        associativity: 2,
+                        hit_latency: 1,
+                    },
+                    LevelConfig {
+                        size: 64,
+                        associativity: 2,
+                        hit_latency: 4,
+                    },
+                ],
+                policy,
+                victim: None,
+            },
+            10,
+            BLOCK_SIZE,
+            BLOCK_SIZE / 4,
+        )
+    }
+
+    fn addr(block: u32) -> u32 {
+        block * 4
+    }
+
+    #[test]
+    fn parse_level() {
+        assert_eq!(
+            "1024:2:3".parse::<LevelConfig>(),
+            Ok(LevelConfig {
+                size: 1024,
+                associativity: 2,
+                hit_latency: 3
+            })
+        );
+        assert!("1024:2".parse::<LevelConfig>().is_err());
+        assert!("1024:x:3".parse::<LevelConfig>().is_err());
+    }
+
+    #[test]
+    fn latencies() {
+        let mut h = hierarchy(InclusionPolicy::Inclusive);
+        let mut memory = Memory::default();
+        assert_eq!(h.miss_latency(), 15);
+        assert_eq!(h.load(addr(1), &mut memory), None);
+        h.fill(addr(1), &[0; 4]);
+        assert_eq!(h.load(addr(1), &mut memory), Some(1));
+        // blocks 3 and 4 replace block 1 in L1
+        h.fill(addr(3), &[0; 4]);
+        h.fill(addr(4), &[0; 4]);
+        assert_eq!(h.load(addr(1), &mut memory), Some(5));
+        assert_eq!(h.stats.num_hits, vec![1, 1]);
+        assert_eq!(h.stats.num_misses, 3);
+    }
+
+    #[test]
+    fn inclusive_victims_are_back_invalidated() {
+        let mut h = hierarchy(InclusionPolicy::Inclusive);
+        let mut memory = Memory::default();
+        h.fill(addr(0), &[0; 4]);
+        h.fill(addr(1), &[0; 4]);
+        h.load(addr(0), &mut memory);
+        // L1 keeps block 0, which is the LRU block of its L2 set when block 4 arrives
+        h.fill(addr(2), &[0; 4]);
+        h.fill(addr(4), &[0; 4]);
+        assert!(!h.contains(addr(0)));
+        assert_eq!(h.stats.num_inclusion_victims, 1);
+
+        // the coherent level evicts a block
+        h.evicted(addr(4));
+        assert!(!h.contains(addr(4)));
+        assert_eq!(h.stats.num_inclusion_victims, 3);
+    }
+
+    #[test]
+    fn exclusive_levels_are_disjoint() {
+        let mut h = hierarchy(InclusionPolicy::Exclusive);
+        let mut memory = Memory::default();
+        h.fill(addr(0), &[0; 4]);
+        h.fill(addr(2), &[0; 4]);
+        // block 4 replaces block 0 in L1, which moves down
+        h.fill(addr(4), &[0; 4]);
+        assert!(h.levels[1].blocks.contains(0));
+        assert!(!h.levels[0].blocks.contains(0));
+
+        // a hit in L2 moves the block back up, swapping it with block 2
+        assert_eq!(h.load(addr(0), &mut memory), Some(5));
+        assert!(h.levels[0].blocks.contains(0));
+        assert!(h.levels[1].blocks.contains(2));
+        assert!(!h.levels[1].blocks.contains(0));
+
+        // evictions of the coherent level do not affect the upper levels
+        h.evicted(addr(0));
+        assert!(h.contains(addr(0)));
+    }
+
+    #[test]
+    fn nine_keeps_copies_until_snooped() {
+        let mut h = hierarchy(InclusionPolicy::Nine);
+        h.fill(addr(0), &[0; 4]);
+        h.evicted(addr(0));
+        assert!(h.contains(addr(0)));
+        h.snooped(addr(0));
+        assert!(!h.contains(addr(0)));
+        assert_eq!(h.stats.num_snoop_invalidations, 2);
+    }
+
+    #[test]
+    fn copies_follow_updates() {
+        let mut h = hierarchy(InclusionPolicy::Inclusive);
+        let mut memory = Memory::default();
+        h.fill(addr(1), &[0; 4]);
+        let version = memory.new_version(addr(1) + 2);
+        memory.commit();
+        h.update_word(addr(1) + 2, version);
+        h.load(addr(1) + 2, &mut memory);
+        assert_eq!(memory.num_stale_reads(), 0);
+
+        memory.new_version(addr(1) + 2);
+        memory.commit();
+        h.load(addr(1) + 2, &mut memory);
+        assert_eq!(memory.num_stale_reads(), 1);
+
+        h.back_invalidate(addr(1));
+        assert!(!h.contains(addr(1)));
+        assert_eq!(h.stats.num_back_invalidations, 2);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::analyzer::Analyzable;
+use crate::bus::{Bus, BusAction};
+use crate::system::WORD_SIZE;
+use crate::utils::LruSets;
+
+/// Geometry and hit latency (in cycles) of the shared L2 cache
+#[derive(Debug, Clone, Copy)]
+pub struct L2Config {
+    /// Size in bytes
+    pub size: usize,
+    pub associativity: usize,
+    /// Block size in bytes, at least the block size of the private caches
+    pub block_size: usize,
+    pub hit_latency: usize,
+}
+
+impl Default for L2Config {
+    fn default() -> Self {
+        L2Config {
+            size: 262144,
+            associativity: 8,
+            block_size: 64,
+            hit_latency: 10,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct L2Stats {
+    pub num_hits: usize,
+    pub num_misses: usize,
+}
+
+/// Last-level cache shared by all cores, between the bus and main memory. Transactions served by
+/// main memory are looked up in the L2: hits replace the memory latency by the hit latency, misses
+/// add the lookup to it. Write-allocate; writebacks of the L2 itself are not priced.
+pub struct L2Cache {
+    config: L2Config,
+    blocks: LruSets,
+    // id of the last transaction that was served
+    last_task_id: usize,
+    stats: L2Stats,
+}
+
+impl L2Cache {
+    pub fn new(config: L2Config) -> Self {
+        L2Cache {
+            blocks: LruSets::new(config.size / config.block_size, config.associativity),
+            config,
+            last_task_id: 0,
+            stats: L2Stats::default(),
+        }
+    }
+
+    /// Serve a newly issued transaction (once the snooping caches responded to it). Returns true
+    /// if the L2 served it, false if main memory has to.
+    pub fn update(&mut self, bus: &mut Bus) -> bool {
+        let timing = bus.timing();
+        let task = match bus.active_task() {
+            // buses are snooped in issue order, older transactions were served already
+            Some(task) if task.id > self.last_task_id => task,
+            _ => return false,
+        };
+        self.last_task_id = task.id;
+
+        let write = match task.action {
+            BusAction::BusRdMem(_, _) | BusAction::BusRdXMem(_, _) => false,
+            BusAction::BusUpdMem(_, _) | BusAction::Flush(_, _) => true,
+            // supplied by another cache
+            _ => return false,
+        };
+        let block =
+            BusAction::extract_addr(task.action) / (self.config.block_size / WORD_SIZE) as u32;
+        let hit = self.blocks.contains(block);
+        self.blocks.touch(block);
+
+        if hit {
+            self.stats.num_hits += 1;
+        } else {
+            self.stats.num_misses += 1;
+        }
+        let memory_latency = timing.price(&task.action);
+        task.remaining_cycles = if hit || write {
+            task.remaining_cycles.saturating_sub(memory_latency) + self.config.hit_latency
+        } else {
+            task.remaining_cycles + self.config.hit_latency
+        };
+
+        #[cfg(verbose)]
+        println!(
+            "L2: {:?} {}, remaining: {:?}",
+            task.action,
+            if hit { "hit" } else { "miss" },
+            task.remaining_cycles
+        );
+        hit || write
+    }
+}
+
+impl Analyzable for L2Cache {
+    fn report(&self, stats: &mut crate::analyzer::Stats) {
+        stats.l2 = Some(self.stats.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn l2() -> L2Cache {
+        L2Cache::new(L2Config {
+            size: 128,
+            associativity: 2,
+            block_size: 32,
+            hit_latency: 10,
+        })
+    }
+
+    #[test]
+    fn miss_then_hit() {
+        let mut l2 = l2();
+        let mut bus = Bus::new();
+        bus.put_on(0, BusAction::BusRdMem(0x40, 32));
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 110);
+        // the same transaction is only served once
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 110);
+
+        bus.clear();
+        bus.put_on(1, BusAction::BusRdXMem(0x41, 32));
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 10);
+        assert_eq!(l2.stats.num_hits, 1);
+        assert_eq!(l2.stats.num_misses, 1);
+    }
+
+    #[test]
+    fn cache_to_cache_transfers_bypass() {
+        let mut l2 = l2();
+        let mut bus = Bus::new();
+        bus.put_on(0, BusAction::BusRdShared(0x40, 32));
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 16);
+        assert_eq!(l2.stats.num_hits + l2.stats.num_misses, 0);
+    }
+
+    #[test]
+    fn writebacks_allocate() {
+        let mut l2 = l2();
+        let mut bus = Bus::new();
+        bus.put_on(0, BusAction::Flush(0x40, 32));
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 10);
+
+        bus.clear();
+        bus.put_on(0, BusAction::BusRdMem(0x40, 32));
+        l2.update(&mut bus);
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 10);
+        assert_eq!(l2.stats.num_hits, 1);
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let mut l2 = l2();
+        let mut bus = Bus::new();
+        // 2 sets of 2 blocks, 8 words per block: blocks 0, 2, 4 map to set 0
+        for addr in [0x0, 0x10, 0x20, 0x0] {
+            bus.clear();
+            bus.put_on(0, BusAction::BusRdMem(addr, 32));
+            l2.update(&mut bus);
+        }
+        assert_eq!(l2.stats.num_misses, 4);
+    }
+}
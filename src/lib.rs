@@ -1,21 +1,83 @@
 mod analyzer;
+mod arbiter;
 mod bus;
 mod cache;
+mod checker;
 mod core;
+mod directory;
+mod dram;
+mod hierarchy;
+mod l2;
 mod loader;
+mod memory;
+mod mshr;
+mod prefetch;
 mod protocol;
 mod record;
+mod replacement;
+mod store_buffer;
 mod system;
+mod timing;
 mod utils;
+pub mod verify;
+mod victim;
 
 pub use crate::analyzer::Analyzer;
-pub use crate::bus::Bus;
+pub use crate::arbiter::{ArbitrationConfig, ArbitrationKind, ArbitrationStats};
+pub use crate::bus::{Bus, BusStats};
 pub use crate::core::Core;
+pub use crate::directory::{DirectoryConfig, DirectoryKind, DirectoryStats};
+pub use crate::dram::{DramConfig, DramStats};
+pub use crate::hierarchy::{HierarchyConfig, HierarchyStats, InclusionPolicy, LevelConfig};
+pub use crate::l2::{L2Config, L2Stats};
 pub use crate::loader::FileLoader;
+pub use crate::mshr::MshrStats;
+pub use crate::prefetch::{PrefetchConfig, PrefetchKind, PrefetchStats};
+pub use crate::protocol::table::ProtocolTable;
 pub use crate::protocol::ProtocolKind;
+pub use crate::replacement::{ReplacementConfig, ReplacementKind};
+pub use crate::store_buffer::StoreBufferStats;
 pub use crate::system::System;
+pub use crate::timing::TimingConfig;
+pub use crate::victim::{VictimConfig, VictimStats};
+use std::rc::Rc;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Optimizations {
     pub read_broadcast: bool,
 }
+
+#[derive(Debug, Default, Clone)]
+pub struct SystemConfig {
+    pub optimizations: Optimizations,
+    /// Use a home-node directory instead of broadcasting bus transactions
+    pub directory: Option<DirectoryConfig>,
+    /// Shared L2 cache between the private caches and main memory
+    pub l2: Option<L2Config>,
+    /// DRAM memory controller serving the main memory transactions (flat memory latency if None)
+    pub dram: Option<DramConfig>,
+    /// Private cache levels in front of each core's coherent cache
+    pub hierarchy: HierarchyConfig,
+    /// Replacement policy of the coherent caches
+    pub replacement: ReplacementConfig,
+    /// Hardware prefetcher of the coherent caches
+    pub prefetch: Option<PrefetchConfig>,
+    /// Number of MSHRs of the non-blocking caches (blocking caches if None)
+    pub mshrs: Option<usize>,
+    /// Depth of the per-core store buffers (stores wait for the cache if None)
+    pub store_buffer: Option<usize>,
+    /// Split memory transactions into a request and a later response phase, the bus serves other
+    /// transactions in between
+    pub split_bus: bool,
+    /// Number of address-interleaved buses, each serving and snooping a slice of the blocks (a
+    /// single bus if None). Not used with the directory, which has one home node per core.
+    pub num_buses: Option<usize>,
+    /// Latencies and bandwidth of caches, bus and main memory
+    pub timing: TimingConfig,
+    /// Arbitration policy of the bus
+    pub arbitration: ArbitrationConfig,
+    /// Transition table of the `ProtocolKind::Table` protocol
+    pub protocol_table: Option<Rc<ProtocolTable>>,
+    /// Check the single-writer/multiple-reader invariant across all caches after every cycle
+    pub check_coherence: bool,
+}
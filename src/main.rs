@@ -1,18 +1,34 @@
-use cacher::{Analyzer, FileLoader, Optimizations, ProtocolKind, System};
-use clap::Parser;
+use cacher::verify;
+use cacher::{
+    Analyzer, ArbitrationConfig, ArbitrationKind, DirectoryConfig, DirectoryKind, DramConfig,
+    FileLoader, HierarchyConfig, InclusionPolicy, L2Config, LevelConfig, Optimizations,
+    PrefetchConfig, PrefetchKind, ProtocolKind, ProtocolTable, ReplacementConfig, ReplacementKind,
+    System, SystemConfig, TimingConfig, VictimConfig,
+};
+use clap::{Parser, Subcommand};
+use std::rc::Rc;
 
 #[derive(Parser, Debug)]
 #[clap(version,
     about = "\x1b[1mCACHER\x1b[0m - \x1b[1mCA\x1b[0mche \x1b[1mC\x1b[0mo\x1b[1mH\x1b[0merence \x1b[1mE\x1b[0mmulato\x1b[1mR\x1b[0m",
-    long_about = None)]
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true)]
 struct ProgramArgs {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Cache coherence protocol
-    #[clap(arg_enum, value_parser)]
-    protocol: ProtocolKind,
+    #[clap(arg_enum, value_parser, required = true)]
+    protocol: Option<ProtocolKind>,
+
+    /// Protocol specification for the table protocol, e.g. "./protocols/mesi.toml"
+    #[clap(long, value_parser)]
+    protocol_file: Option<String>,
 
     /// Path to the benchmark archive, e.g. "./blackscholes_four.zip"
-    #[clap(value_parser)]
-    input_file: String,
+    #[clap(value_parser, required = true)]
+    input_file: Option<String>,
 
     /// Cache size in bytes
     #[clap(value_parser, default_value_t = 4096)]
@@ -26,6 +42,14 @@ struct ProgramArgs {
     #[clap(value_parser, default_value_t = 32)]
     block_size: usize,
 
+    /// Replacement policy of the caches
+    #[clap(long, arg_enum, value_parser, default_value = "lru")]
+    replacement: ReplacementKind,
+
+    /// Seed of the random replacement policy
+    #[clap(long, value_parser, default_value_t = ReplacementConfig::default().seed)]
+    replacement_seed: u64,
+
     /// Disable progress display
     #[clap(short, long)]
     no_progress: bool,
@@ -33,6 +57,195 @@ struct ProgramArgs {
     /// Enable the read-broadcasting optimization (MESI only)
     #[clap(short, long)]
     read_broadcast: bool,
+
+    /// Check the single-writer/multiple-reader invariant across all caches after every cycle
+    #[clap(long)]
+    check_coherence: bool,
+
+    /// Private cache level in front of the coherent cache as SIZE:ASSOCIATIVITY:HIT_LATENCY,
+    /// repeat for multiple levels (closest to the core first)
+    #[clap(long = "upper-level", value_parser)]
+    upper_levels: Vec<LevelConfig>,
+
+    /// Inclusion policy of the private cache levels
+    #[clap(long, arg_enum, value_parser, default_value = "inclusive")]
+    inclusion: InclusionPolicy,
+
+    /// Latency and bandwidth model, e.g. "./timing/default.toml" (defaults if omitted)
+    #[clap(long, value_parser)]
+    timing_file: Option<String>,
+
+    /// Main memory latency in cycles (overrides the timing file)
+    #[clap(long, value_parser)]
+    memory_latency: Option<usize>,
+
+    /// Bytes transferred per bus beat (overrides the timing file)
+    #[clap(long, value_parser)]
+    bus_width: Option<usize>,
+
+    /// Cycles per bus beat (overrides the timing file)
+    #[clap(long, value_parser)]
+    cycles_per_beat: Option<usize>,
+
+    /// Hit latency of the coherent (last private) cache level in cycles (overrides the timing
+    /// file)
+    #[clap(long, value_parser)]
+    hit_latency: Option<usize>,
+
+    /// Cycles until snooping caches supply or update a block (overrides the timing file)
+    #[clap(long, value_parser)]
+    snoop_latency: Option<usize>,
+
+    /// Cycles of main memory to absorb a flushed block (overrides the timing file)
+    #[clap(long, value_parser)]
+    writeback_latency: Option<usize>,
+
+    /// Attach a fully-associative victim cache with this many entries to each private cache
+    #[clap(long, value_parser)]
+    victim_entries: Option<usize>,
+
+    /// Additional latency of a victim cache hit in cycles
+    #[clap(long, value_parser, default_value_t = VictimConfig::default().hit_latency)]
+    victim_latency: usize,
+
+    /// Hardware prefetcher of the private caches
+    #[clap(long, arg_enum, value_parser)]
+    prefetch: Option<PrefetchKind>,
+
+    /// Number of blocks prefetched ahead
+    #[clap(long, value_parser, default_value_t = PrefetchConfig::default().degree)]
+    prefetch_degree: usize,
+
+    /// Make the private caches non-blocking with this many MSHRs
+    #[clap(long, value_parser)]
+    mshrs: Option<usize>,
+
+    /// Retire stores into a per-core store buffer of this depth
+    #[clap(long, value_parser)]
+    store_buffer: Option<usize>,
+
+    /// Split memory transactions into request and response phases, so that the bus serves other
+    /// transactions while memory is working
+    #[clap(long)]
+    split_bus: bool,
+
+    /// Number of address-interleaved buses, each serving and snooping a slice of the blocks
+    #[clap(long, value_parser)]
+    buses: Option<usize>,
+
+    /// Arbitration policy of the bus
+    #[clap(long, arg_enum, value_parser, default_value = "shuffle")]
+    arbitration: ArbitrationKind,
+
+    /// Seed of the random arbitration policy
+    #[clap(long, value_parser, default_value_t = ArbitrationConfig::default().seed)]
+    arbitration_seed: u64,
+
+    /// Add a shared L2 cache between the private caches and main memory
+    #[clap(long)]
+    l2: bool,
+
+    /// L2 cache size in bytes (262144 if omitted)
+    #[clap(long, value_parser)]
+    l2_size: Option<usize>,
+
+    /// L2 cache associativity (8 if omitted)
+    #[clap(long, value_parser)]
+    l2_associativity: Option<usize>,
+
+    /// L2 cache block size in bytes (64 if omitted)
+    #[clap(long, value_parser)]
+    l2_block_size: Option<usize>,
+
+    /// L2 hit latency in cycles (10 if omitted)
+    #[clap(long, value_parser)]
+    l2_hit_latency: Option<usize>,
+
+    /// Serve main memory transactions by a DRAM memory controller with banks and row buffers
+    #[clap(long)]
+    dram: bool,
+
+    /// Number of DRAM channels (1 if omitted)
+    #[clap(long, value_parser)]
+    dram_channels: Option<usize>,
+
+    /// Number of banks per DRAM channel (8 if omitted)
+    #[clap(long, value_parser)]
+    dram_banks: Option<usize>,
+
+    /// DRAM row size in bytes (2048 if omitted)
+    #[clap(long, value_parser)]
+    dram_row_size: Option<usize>,
+
+    /// DRAM column access latency in cycles (44 if omitted)
+    #[clap(long, value_parser)]
+    dram_t_cas: Option<usize>,
+
+    /// DRAM row activation latency in cycles (44 if omitted)
+    #[clap(long, value_parser)]
+    dram_t_rcd: Option<usize>,
+
+    /// DRAM precharge latency in cycles (44 if omitted)
+    #[clap(long, value_parser)]
+    dram_t_rp: Option<usize>,
+
+    /// DRAM data burst in cycles (8 if omitted)
+    #[clap(long, value_parser)]
+    dram_t_burst: Option<usize>,
+
+    /// Use a home-node directory instead of broadcasting on the bus
+    #[clap(short, long)]
+    directory: bool,
+
+    /// Organization of the directory entries (full-map if omitted)
+    #[clap(long, arg_enum, value_parser)]
+    dir_kind: Option<DirectoryKind>,
+
+    /// Number of sharer pointers per entry of a limited-pointer directory (4 if omitted)
+    #[clap(long, value_parser)]
+    dir_pointers: Option<usize>,
+
+    /// Number of entries of a sparse directory (unlimited if omitted)
+    #[clap(long, value_parser)]
+    dir_entries: Option<usize>,
+
+    /// Associativity of a sparse directory (8 if omitted)
+    #[clap(long, value_parser)]
+    dir_associativity: Option<usize>,
+
+    /// Directory lookup latency in cycles (5 if omitted)
+    #[clap(long, value_parser)]
+    dir_lookup_latency: Option<usize>,
+
+    /// Latency of one point-to-point directory message in cycles (10 if omitted)
+    #[clap(long, value_parser)]
+    dir_hop_latency: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Explore the states a protocol reaches by sequences of accesses and check the
+    /// single-writer/multiple-reader invariant
+    ///
+    /// Each access runs to completion before the next one starts, so concurrent transactions and
+    /// their interleavings are not explored. This is no model checker of the transient states.
+    Verify {
+        /// Cache coherence protocol
+        #[clap(arg_enum, value_parser)]
+        protocol: ProtocolKind,
+
+        /// Protocol specification for the table protocol
+        #[clap(long, value_parser)]
+        protocol_file: Option<String>,
+
+        /// Number of caches
+        #[clap(long, value_parser, default_value_t = 3)]
+        cores: usize,
+
+        /// Number of (conflicting) blocks, 1 or 2
+        #[clap(long, value_parser, default_value_t = 1)]
+        blocks: usize,
+    },
 }
 
 // taken from https://stackoverflow.com/a/600306
@@ -56,35 +269,292 @@ fn check_args(args: &ProgramArgs) {
     if (args.cache_size / args.associativity) % args.block_size != 0 {
         panic!("Cache set size has to be multiple of the block size. (CacheSize / Associativity) mod BlockSize != 0");
     }
-    if args.read_broadcast && args.protocol != ProtocolKind::Mesi {
+    if args.read_broadcast && args.protocol != Some(ProtocolKind::Mesi) {
         panic!("Read broadcast optimization is only possible with the MESI protocol.");
     }
+    if (args.protocol == Some(ProtocolKind::Table)) != args.protocol_file.is_some() {
+        panic!("A protocol file has to be supplied if and only if the table protocol is used.");
+    }
+    if args.read_broadcast && args.directory {
+        panic!("Read broadcast optimization cannot be combined with the directory.");
+    }
+    let l2_options = [
+        args.l2_size.is_some(),
+        args.l2_associativity.is_some(),
+        args.l2_block_size.is_some(),
+        args.l2_hit_latency.is_some(),
+    ];
+    if !args.l2 && l2_options.contains(&true) {
+        panic!("The L2 options require the L2 cache (--l2).");
+    }
+    let l2_config = l2_config(args);
+    if args.l2
+        && (!power_of_two(l2_config.size)
+            || !power_of_two(l2_config.associativity)
+            || !power_of_two(l2_config.block_size))
+    {
+        panic!("L2 size, associativity and block size must be powers of 2.");
+    }
+    if args.l2 && l2_config.block_size < args.block_size {
+        panic!("The L2 block size has to be at least the block size of the private caches.");
+    }
+    if args.l2 && l2_config.size / l2_config.associativity < l2_config.block_size {
+        panic!("Each L2 set should be big enough to at least hold one block.");
+    }
+    let dram_options = [
+        args.dram_channels.is_some(),
+        args.dram_banks.is_some(),
+        args.dram_row_size.is_some(),
+        args.dram_t_cas.is_some(),
+        args.dram_t_rcd.is_some(),
+        args.dram_t_rp.is_some(),
+        args.dram_t_burst.is_some(),
+    ];
+    if !args.dram && dram_options.contains(&true) {
+        panic!("The DRAM options require the DRAM memory controller (--dram).");
+    }
+    let dram_config = dram_config(args);
+    if args.dram && (dram_config.channels == 0 || dram_config.banks == 0) {
+        panic!("The DRAM needs at least one channel and one bank per channel.");
+    }
+    if args.dram && (!power_of_two(dram_config.row_size) || dram_config.row_size < args.block_size)
+    {
+        panic!("The DRAM row size must be a power of 2 and hold at least one block.");
+    }
+    for level in args.upper_levels.iter() {
+        if !power_of_two(level.size) || !power_of_two(level.associativity) {
+            panic!("Size and associativity of the upper cache levels must be powers of 2.");
+        }
+        if level.size / level.associativity < args.block_size {
+            panic!("Each set of an upper cache level has to hold at least one block.");
+        }
+        if level.hit_latency == 0 {
+            panic!("Hit latencies have to be at least one cycle.");
+        }
+    }
+    if args.directory && args.inclusion != InclusionPolicy::Inclusive {
+        panic!("The directory only tracks the coherent cache level and requires an inclusive hierarchy.");
+    }
+    if args.victim_entries == Some(0) {
+        panic!("The victim cache needs at least one entry.");
+    }
+    if args.directory && args.victim_entries.is_some() {
+        panic!("The directory does not track victim cache entries.");
+    }
+    if args.prefetch_degree == 0 {
+        panic!("The prefetch degree has to be at least one.");
+    }
+    if args.mshrs == Some(0) {
+        panic!("A non-blocking cache needs at least one MSHR.");
+    }
+    if args.buses == Some(0) {
+        panic!("The system needs at least one bus.");
+    }
+    if args.directory && args.buses.is_some() {
+        panic!("The directory has one home node per core instead of buses.");
+    }
+    if args.store_buffer == Some(0) {
+        panic!("A store buffer needs at least one entry.");
+    }
+    let dir_options = [
+        args.dir_kind.is_some(),
+        args.dir_pointers.is_some(),
+        args.dir_entries.is_some(),
+        args.dir_associativity.is_some(),
+        args.dir_lookup_latency.is_some(),
+        args.dir_hop_latency.is_some(),
+    ];
+    if !args.directory && dir_options.contains(&true) {
+        panic!("The directory options require the directory (--directory).");
+    }
+    let dir_config = directory_config(args);
+    if dir_config.num_pointers == 0 {
+        panic!("A limited-pointer directory needs at least one pointer per entry.");
+    }
+    if dir_config.associativity == 0 {
+        panic!("A sparse directory needs an associativity of at least one.");
+    }
+    if let Some(entries) = dir_config.num_entries {
+        if entries == 0 || entries % dir_config.associativity.min(entries) != 0 {
+            panic!("Number of sparse directory entries has to be a multiple of its associativity.");
+        }
+    }
 }
 
-fn main() {
-    let args = ProgramArgs::parse();
-    check_args(&args);
+/// Directory organization of the command line, defaults for the omitted options.
+fn directory_config(args: &ProgramArgs) -> DirectoryConfig {
+    let default = DirectoryConfig::default();
+    DirectoryConfig {
+        kind: args.dir_kind.unwrap_or(default.kind),
+        num_pointers: args.dir_pointers.unwrap_or(default.num_pointers),
+        num_entries: args.dir_entries,
+        associativity: args.dir_associativity.unwrap_or(default.associativity),
+        lookup_latency: args.dir_lookup_latency.unwrap_or(default.lookup_latency),
+        hop_latency: args.dir_hop_latency.unwrap_or(default.hop_latency),
+    }
+}
 
-    let record_streams = match FileLoader::open(&args.input_file, !args.no_progress) {
-        Ok(streams) => streams,
-        Err(e) => {
+/// L2 cache of the command line, defaults for the omitted options.
+fn l2_config(args: &ProgramArgs) -> L2Config {
+    let default = L2Config::default();
+    L2Config {
+        size: args.l2_size.unwrap_or(default.size),
+        associativity: args.l2_associativity.unwrap_or(default.associativity),
+        block_size: args.l2_block_size.unwrap_or(default.block_size),
+        hit_latency: args.l2_hit_latency.unwrap_or(default.hit_latency),
+    }
+}
+
+/// DRAM organization and timing of the command line, defaults for the omitted options.
+fn dram_config(args: &ProgramArgs) -> DramConfig {
+    let default = DramConfig::default();
+    DramConfig {
+        channels: args.dram_channels.unwrap_or(default.channels),
+        banks: args.dram_banks.unwrap_or(default.banks),
+        row_size: args.dram_row_size.unwrap_or(default.row_size),
+        t_cas: args.dram_t_cas.unwrap_or(default.t_cas),
+        t_rcd: args.dram_t_rcd.unwrap_or(default.t_rcd),
+        t_rp: args.dram_t_rp.unwrap_or(default.t_rp),
+        t_burst: args.dram_t_burst.unwrap_or(default.t_burst),
+    }
+}
+
+fn check_timing(timing: &TimingConfig) {
+    if timing.hit_latency == 0 {
+        panic!("Hit latencies have to be at least one cycle.");
+    }
+    if timing.bus_width == 0 || timing.cycles_per_beat == 0 {
+        panic!("Bus width and cycles per beat have to be at least one.");
+    }
+}
+
+/// Timing model of the timing file (defaults if omitted), overridden by the command line.
+fn load_timing(args: &ProgramArgs) -> TimingConfig {
+    let mut timing = match args.timing_file.as_deref().map(TimingConfig::open) {
+        Some(Ok(timing)) => timing,
+        Some(Err(e)) => {
             println!(
-                "Error during loading of the supplied input file: {:?}",
+                "Error during loading of the supplied timing file: {:?}",
                 e.to_string()
             );
             std::process::exit(e.raw_os_error().unwrap_or(1));
         }
+        None => TimingConfig::default(),
     };
+    for (value, field) in [
+        (args.memory_latency, &mut timing.memory_latency),
+        (args.bus_width, &mut timing.bus_width),
+        (args.cycles_per_beat, &mut timing.cycles_per_beat),
+        (args.hit_latency, &mut timing.hit_latency),
+        (args.snoop_latency, &mut timing.snoop_latency),
+        (args.writeback_latency, &mut timing.writeback_latency),
+    ] {
+        if let Some(value) = value {
+            *field = value;
+        }
+    }
+    timing
+}
+
+fn load_protocol_table(path: Option<&str>) -> Option<Rc<ProtocolTable>> {
+    match path.map(ProtocolTable::open) {
+        Some(Ok(table)) => Some(Rc::new(table)),
+        Some(Err(e)) => {
+            println!(
+                "Error during loading of the supplied protocol file: {:?}",
+                e.to_string()
+            );
+            std::process::exit(e.raw_os_error().unwrap_or(1));
+        }
+        None => None,
+    }
+}
+
+fn main() {
+    let args = ProgramArgs::parse();
+
+    if let Some(Command::Verify {
+        protocol,
+        protocol_file,
+        cores,
+        blocks,
+    }) = &args.command
+    {
+        if (*protocol == ProtocolKind::Table) != protocol_file.is_some() {
+            panic!("A protocol file has to be supplied if and only if the table protocol is used.");
+        }
+        if *cores == 0 || !(1..=2).contains(blocks) {
+            panic!("Verification needs at least one core and one or two blocks.");
+        }
+        let protocol_table = load_protocol_table(protocol_file.as_deref());
+        let report = verify::verify(protocol, protocol_table.as_ref(), *cores, *blocks);
+        println!("{}", report);
+        if report.violation.is_some() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    check_args(&args);
+    let protocol = args.protocol.unwrap();
+
+    let record_streams =
+        match FileLoader::open(args.input_file.as_ref().unwrap(), !args.no_progress) {
+            Ok(streams) => streams,
+            Err(e) => {
+                println!(
+                    "Error during loading of the supplied input file: {:?}",
+                    e.to_string()
+                );
+                std::process::exit(e.raw_os_error().unwrap_or(1));
+            }
+        };
+
+    let protocol_table = load_protocol_table(args.protocol_file.as_deref());
+    let timing = load_timing(&args);
+    check_timing(&timing);
 
     let mut system = System::new(
-        &args.protocol,
+        &protocol,
         args.cache_size,
         args.associativity,
         args.block_size,
         record_streams,
         !args.no_progress,
-        Optimizations {
-            read_broadcast: args.read_broadcast,
+        SystemConfig {
+            optimizations: Optimizations {
+                read_broadcast: args.read_broadcast,
+            },
+            directory: args.directory.then(|| directory_config(&args)),
+            l2: args.l2.then(|| l2_config(&args)),
+            dram: args.dram.then(|| dram_config(&args)),
+            hierarchy: HierarchyConfig {
+                upper_levels: args.upper_levels.clone(),
+                policy: args.inclusion,
+                victim: args.victim_entries.map(|num_entries| VictimConfig {
+                    num_entries,
+                    hit_latency: args.victim_latency,
+                }),
+            },
+            replacement: ReplacementConfig {
+                kind: args.replacement,
+                seed: args.replacement_seed,
+            },
+            prefetch: args.prefetch.map(|kind| PrefetchConfig {
+                kind,
+                degree: args.prefetch_degree,
+            }),
+            mshrs: args.mshrs,
+            store_buffer: args.store_buffer,
+            split_bus: args.split_bus,
+            num_buses: args.buses,
+            timing,
+            arbitration: ArbitrationConfig {
+                kind: args.arbitration,
+                seed: args.arbitration_seed,
+            },
+            protocol_table,
+            check_coherence: args.check_coherence,
         },
     );
 
@@ -0,0 +1,190 @@
+use crate::bus::{Bus, BusAction};
+use crate::cache::Cache;
+use std::collections::HashMap;
+
+/// Main memory modelled as a version store. Every store creates a new (globally unique,
+/// increasing) version of the written word; loads are checked against the latest committed one.
+#[derive(Default, Debug)]
+pub struct Memory {
+    // version held by main memory for each word (0 = initial value)
+    values: HashMap<u32, u64>,
+    // latest committed version of each word
+    latest: HashMap<u32, u64>,
+    // stores of the current cycle, committed at its end
+    uncommitted: Vec<(u32, u64)>,
+    num_versions: u64,
+    num_stale_reads: usize,
+}
+
+impl Memory {
+    pub fn read_block(&self, addr: u32, num_words: usize) -> Vec<u64> {
+        let block_addr = addr - addr % num_words as u32;
+        (0..num_words as u32)
+            .map(|i| self.values.get(&(block_addr + i)).copied().unwrap_or(0))
+            .collect()
+    }
+
+    pub fn write_block(&mut self, addr: u32, data: &[u64]) {
+        let block_addr = addr - addr % data.len() as u32;
+        for (i, version) in data.iter().enumerate() {
+            self.values.insert(block_addr + i as u32, *version);
+        }
+    }
+
+    pub fn write_word(&mut self, addr: u32, version: u64) {
+        self.values.insert(addr, version);
+    }
+
+    /// Create a new version of the word, visible to the other cores from the next cycle on.
+    pub fn new_version(&mut self, addr: u32) -> u64 {
+        self.num_versions += 1;
+        self.uncommitted.push((addr, self.num_versions));
+        self.num_versions
+    }
+
+    /// Commit the stores of the current cycle.
+    pub fn commit(&mut self) {
+        for (addr, version) in self.uncommitted.drain(..) {
+            self.latest.insert(addr, version);
+        }
+    }
+
+    /// Returns false (and counts a stale read) if a newer version of the word was committed.
+    pub fn check_load(&mut self, addr: u32, version: u64) -> bool {
+        if version < self.latest.get(&addr).copied().unwrap_or(0) {
+            self.num_stale_reads += 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn num_stale_reads(&self) -> usize {
+        self.num_stale_reads
+    }
+}
+
+/// Data movement of a bus transaction. The caches' blocks are captured before they snoop a newly
+/// issued transaction, the data is moved when the transaction ends.
+pub struct Transfer {
+    task_id: usize,
+    issuer_id: usize,
+    addr: u32,
+    action: BusAction,
+    // (dirty, data) of each cache's valid copy before the snoop
+    before: Vec<Option<(bool, Vec<u64>)>>,
+    // a snooping cache took over supplying the data (BusXShared)
+    cache_supplied: Option<bool>,
+}
+
+impl Transfer {
+    /// Returns None if there is no transaction on the bus.
+    pub fn begin(bus: &mut Bus, caches: &[&mut Cache]) -> Option<Transfer> {
+        let task = *bus.active_task()?;
+        let addr = BusAction::extract_addr(task.action);
+        Some(Transfer {
+            task_id: task.id,
+            issuer_id: task.issuer_id,
+            addr,
+            action: task.action,
+            before: caches.iter().map(|c| c.block_data(addr)).collect(),
+            cache_supplied: None,
+        })
+    }
+
+    /// Remember the response of the snooping caches (first snoop only, the issuer may still
+    /// change the action afterwards).
+    pub fn snooped(&mut self, bus: &mut Bus) {
+        if self.cache_supplied.is_none() && bus.active_task().is_some_and(|t| t.id == self.task_id)
+        {
+            self.cache_supplied = Some(matches!(
+                bus.active_task().map(|t| t.action),
+                Some(
+                    BusAction::BusRdShared(_, _)
+                        | BusAction::BusRdXShared(_, _)
+                        | BusAction::BusUpdShared(_, _)
+                )
+            ));
+        }
+    }
+
+    /// The transaction completes in this cycle (or was cancelled by its issuer). Split
+    /// transactions complete with their response.
+    pub fn done(&self, bus: &mut Bus) -> bool {
+        bus.task(self.task_id)
+            .is_none_or(|t| t.remaining_cycles == 0)
+    }
+
+    pub fn task_id(&self) -> usize {
+        self.task_id
+    }
+
+    pub fn finish(self, bus: &mut Bus, caches: &mut [&mut Cache]) {
+        let memory = bus.memory();
+
+        // a dirty copy supplies the data, if it is no longer dirty it was flushed to memory. Without
+        // the response of a cache, the data is lost.
+        let mut supplied = None;
+        for (core_id, line) in self.before.into_iter().enumerate() {
+            if let (false, Some((true, data)), Some(true)) =
+                (core_id == self.issuer_id, line, self.cache_supplied)
+            {
+                if !caches[core_id]
+                    .block_data(self.addr)
+                    .is_some_and(|(dirty, _)| dirty)
+                {
+                    memory.write_block(self.addr, &data);
+                }
+                supplied = Some(data);
+            }
+        }
+        let data = supplied.unwrap_or_else(|| {
+            memory.read_block(self.addr, caches[self.issuer_id].words_per_block())
+        });
+        // the issuer's line leaves its transient state before it takes the data
+        caches[self.issuer_id].response(self.addr);
+        // the issuer and caches picking up the block (read broadcast)
+        for cache in caches.iter_mut() {
+            cache.fill(self.addr, &data);
+        }
+
+        let stored = caches[self.issuer_id].complete_access(memory);
+        if let (Some(addr), BusAction::BusUpdMem(_, _) | BusAction::BusUpdShared(_, _)) =
+            (stored, self.action)
+        {
+            // the sharers pick up the new value from the bus
+            let version = caches[self.issuer_id].version(addr).unwrap();
+            for (core_id, cache) in caches.iter_mut().enumerate() {
+                if core_id != self.issuer_id {
+                    cache.update_word(addr, version);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_are_visible_after_commit() {
+        let mut memory = Memory::default();
+        let version = memory.new_version(0x11);
+        // loads of the same cycle may still see the old value
+        assert!(memory.check_load(0x11, 0));
+        memory.commit();
+        assert!(!memory.check_load(0x11, 0));
+        assert!(memory.check_load(0x11, version));
+        assert_eq!(memory.num_stale_reads(), 1);
+    }
+
+    #[test]
+    fn blocks_are_aligned() {
+        let mut memory = Memory::default();
+        memory.write_block(0x13, &[1, 2, 3, 4]);
+        assert_eq!(memory.read_block(0x10, 4), vec![1, 2, 3, 4]);
+        memory.write_word(0x12, 7);
+        assert_eq!(memory.read_block(0x11, 2), vec![1, 2]);
+        assert_eq!(memory.read_block(0x12, 2), vec![7, 4]);
+    }
+}
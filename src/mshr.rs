@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+#[derive(Default, Clone, Debug)]
+pub struct MshrStats {
+    /// Load misses that allocated an MSHR
+    pub num_primary_misses: usize,
+    /// Load misses merged into the MSHR of their block
+    pub num_secondary_misses: usize,
+    /// Load hits while misses were outstanding
+    pub num_hits_under_miss: usize,
+}
+
+struct Mshr {
+    block: u32,
+    // word addresses of the waiting loads, the first one issues the bus transaction
+    loads: Vec<u32>,
+    issued: bool,
+}
+
+/// Miss status holding registers of a non-blocking cache. Outstanding load misses are issued one
+/// after another (in allocation order), as the bus serves one transaction of a cache at a time.
+pub struct MshrFile {
+    entries: VecDeque<Mshr>,
+    capacity: usize,
+    stats: MshrStats,
+}
+
+impl MshrFile {
+    pub fn new(capacity: usize) -> Self {
+        MshrFile {
+            entries: VecDeque::new(),
+            capacity,
+            stats: MshrStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &MshrStats {
+        &self.stats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        self.entries.iter().any(|mshr| mshr.block == block)
+    }
+
+    /// Merge a load into the MSHR of its block. Returns false if the block has none.
+    pub fn merge(&mut self, addr: u32, block: u32) -> bool {
+        match self.entries.iter_mut().find(|mshr| mshr.block == block) {
+            Some(mshr) => {
+                mshr.loads.push(addr);
+                self.stats.num_secondary_misses += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Allocate an MSHR for a load miss. Returns false if all MSHRs are occupied.
+    pub fn allocate(&mut self, addr: u32, block: u32) -> bool {
+        if self.entries.len() == self.capacity {
+            return false;
+        }
+        self.entries.push_back(Mshr {
+            block,
+            loads: vec![addr],
+            issued: false,
+        });
+        self.stats.num_primary_misses += 1;
+        true
+    }
+
+    pub fn hit_under_miss(&mut self) {
+        if !self.is_empty() {
+            self.stats.num_hits_under_miss += 1;
+        }
+    }
+
+    /// Load that issues the bus transaction of the next MSHR (if it is not yet issued).
+    pub fn next_to_issue(&self) -> Option<u32> {
+        self.entries
+            .front()
+            .filter(|mshr| !mshr.issued)
+            .map(|mshr| mshr.loads[0])
+    }
+
+    pub fn issued(&mut self) {
+        self.entries.front_mut().unwrap().issued = true;
+    }
+
+    /// Free the MSHR whose transaction completed. Returns its merged (secondary) loads.
+    pub fn complete(&mut self) -> Option<Vec<u32>> {
+        if !self.entries.front().is_some_and(|mshr| mshr.issued) {
+            return None;
+        }
+        let mshr = self.entries.pop_front().unwrap();
+        Some(mshr.loads[1..].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_secondary_misses() {
+        let mut mshrs = MshrFile::new(2);
+        assert!(!mshrs.merge(0x11, 4));
+        assert!(mshrs.allocate(0x10, 4));
+        assert!(mshrs.merge(0x13, 4));
+        assert!(mshrs.allocate(0x20, 8));
+        assert!(!mshrs.allocate(0x30, 12));
+        assert!(mshrs.contains(8) && !mshrs.contains(12));
+
+        assert_eq!(mshrs.complete(), None);
+        assert_eq!(mshrs.next_to_issue(), Some(0x10));
+        mshrs.issued();
+        assert_eq!(mshrs.next_to_issue(), None);
+        assert!(mshrs.merge(0x12, 4));
+        assert_eq!(mshrs.complete(), Some(vec![0x13, 0x12]));
+        assert_eq!(mshrs.next_to_issue(), Some(0x20));
+
+        let stats = mshrs.stats();
+        assert_eq!(stats.num_primary_misses, 2);
+        assert_eq!(stats.num_secondary_misses, 2);
+    }
+
+    #[test]
+    fn hits_under_miss() {
+        let mut mshrs = MshrFile::new(1);
+        mshrs.hit_under_miss();
+        mshrs.allocate(0x10, 4);
+        mshrs.hit_under_miss();
+        assert_eq!(mshrs.stats().num_hits_under_miss, 1);
+    }
+}
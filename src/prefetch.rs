@@ -0,0 +1,329 @@
+use clap::ArgEnum;
+use std::collections::{HashSet, VecDeque};
+
+// maximum number of candidates waiting to be issued
+const QUEUE_SIZE: usize = 8;
+// streams tracked by the stream prefetcher and their training window in blocks
+const NUM_STREAMS: usize = 8;
+const STREAM_WINDOW: i64 = 4;
+
+/// Predicts the blocks accessed next. Prefetchers are trained with block numbers (address /
+/// words per block) of the demand misses and of the first hits on prefetched blocks.
+pub trait Prefetcher {
+    /// Returns the blocks to prefetch.
+    fn train(&mut self, block: u32) -> Vec<u32>;
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq, Default)]
+pub enum PrefetchKind {
+    /// The blocks following the accessed one
+    #[default]
+    NextLine,
+    /// Repeats a confirmed distance between consecutive accesses (one global stride, no PCs)
+    Stride,
+    /// Follows ascending and descending sequences of nearby accesses
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchConfig {
+    pub kind: PrefetchKind,
+    /// Number of blocks prefetched ahead
+    pub degree: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        PrefetchConfig {
+            kind: PrefetchKind::default(),
+            degree: 1,
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct PrefetchStats {
+    pub num_issued: usize,
+    /// Prefetched blocks that were accessed by the processor
+    pub num_useful: usize,
+    /// Prefetched blocks evicted before their first access
+    pub num_evicted_unused: usize,
+    /// Prefetched blocks invalidated by another core before their first access
+    pub num_invalidated: usize,
+}
+
+pub struct PrefetcherBuilder;
+
+impl PrefetcherBuilder {
+    pub fn create(config: PrefetchConfig) -> Box<dyn Prefetcher> {
+        match config.kind {
+            PrefetchKind::NextLine => Box::new(NextLine::new(config.degree)),
+            PrefetchKind::Stride => Box::new(Stride::new(config.degree)),
+            PrefetchKind::Stream => Box::new(Stream::new(config.degree)),
+        }
+    }
+}
+
+/// Blocks `block + step * i` for i in 1..=degree (within the address space).
+fn ahead(block: u32, step: i64, degree: usize) -> Vec<u32> {
+    (1..=degree as i64)
+        .filter_map(|i| u32::try_from(block as i64 + step * i).ok())
+        .collect()
+}
+
+struct NextLine {
+    degree: usize,
+}
+
+impl NextLine {
+    fn new(degree: usize) -> Self {
+        NextLine { degree }
+    }
+}
+
+impl Prefetcher for NextLine {
+    fn train(&mut self, block: u32) -> Vec<u32> {
+        ahead(block, 1, self.degree)
+    }
+}
+
+struct Stride {
+    degree: usize,
+    last: Option<u32>,
+    stride: i64,
+}
+
+impl Stride {
+    fn new(degree: usize) -> Self {
+        Stride {
+            degree,
+            last: None,
+            stride: 0,
+        }
+    }
+}
+
+impl Prefetcher for Stride {
+    fn train(&mut self, block: u32) -> Vec<u32> {
+        let last = self.last.replace(block);
+        let stride = match last {
+            Some(last) if last != block => block as i64 - last as i64,
+            _ => return Vec::new(),
+        };
+        let confirmed = stride == self.stride;
+        self.stride = stride;
+        if confirmed {
+            ahead(block, stride, self.degree)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+struct StreamEntry {
+    last: u32,
+    // -1, +1 or 0 if not yet known
+    direction: i64,
+}
+
+struct Stream {
+    degree: usize,
+    // most recently used stream first
+    streams: VecDeque<StreamEntry>,
+}
+
+impl Stream {
+    fn new(degree: usize) -> Self {
+        Stream {
+            degree,
+            streams: VecDeque::new(),
+        }
+    }
+}
+
+impl Prefetcher for Stream {
+    fn train(&mut self, block: u32) -> Vec<u32> {
+        let distance = |entry: &StreamEntry| block as i64 - entry.last as i64;
+        let idx = match self
+            .streams
+            .iter()
+            .position(|s| distance(s).abs() <= STREAM_WINDOW)
+        {
+            Some(idx) => idx,
+            None => {
+                self.streams.push_front(StreamEntry {
+                    last: block,
+                    direction: 0,
+                });
+                self.streams.truncate(NUM_STREAMS);
+                return Vec::new();
+            }
+        };
+        let mut entry = self.streams.remove(idx).unwrap();
+        let direction = distance(&entry).signum();
+        let confirmed = direction != 0 && direction == entry.direction;
+        if direction != 0 {
+            entry.direction = direction;
+            entry.last = block;
+        }
+        self.streams.push_front(entry);
+        if confirmed {
+            ahead(block, direction, self.degree)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Prefetcher of a cache together with its queue of candidates and the bookkeeping of the
+/// prefetched blocks that were not yet accessed.
+pub struct PrefetchUnit {
+    prefetcher: Box<dyn Prefetcher>,
+    queue: VecDeque<u32>,
+    unused: HashSet<u32>,
+    // (bus task id, block) of the prefetch on the bus
+    in_flight: Option<(usize, u32)>,
+    stats: PrefetchStats,
+}
+
+impl PrefetchUnit {
+    pub fn new(config: PrefetchConfig) -> Self {
+        PrefetchUnit {
+            prefetcher: PrefetcherBuilder::create(config),
+            queue: VecDeque::new(),
+            unused: HashSet::new(),
+            in_flight: None,
+            stats: PrefetchStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &PrefetchStats {
+        &self.stats
+    }
+
+    /// Demand access of the processor to the block.
+    pub fn demand(&mut self, block: u32, miss: bool) {
+        let useful = self.unused.remove(&block);
+        if useful {
+            self.stats.num_useful += 1;
+        }
+        if !(miss || useful) {
+            return;
+        }
+        for candidate in self.prefetcher.train(block) {
+            if !self.queue.contains(&candidate) {
+                self.queue.push_back(candidate);
+            }
+        }
+        while self.queue.len() > QUEUE_SIZE {
+            self.queue.pop_front();
+        }
+    }
+
+    /// Next candidate block to prefetch.
+    pub fn next(&mut self) -> Option<u32> {
+        self.queue.pop_front()
+    }
+
+    /// The prefetch of the block was put on the bus as transaction task_id.
+    pub fn issued(&mut self, task_id: usize, block: u32) {
+        self.stats.num_issued += 1;
+        self.unused.insert(block);
+        self.in_flight = Some((task_id, block));
+    }
+
+    /// (bus task id, block) of the prefetch on the bus (if any).
+    pub fn in_flight(&self) -> Option<(usize, u32)> {
+        self.in_flight
+    }
+
+    /// The prefetch transaction left the bus.
+    pub fn completed(&mut self) {
+        self.in_flight = None;
+    }
+
+    /// The block was evicted from the cache.
+    pub fn evicted(&mut self, block: u32) {
+        if self.unused.remove(&block) {
+            self.stats.num_evicted_unused += 1;
+        }
+    }
+
+    /// The block was invalidated by another core.
+    pub fn invalidated(&mut self, block: u32) {
+        if self.unused.remove(&block) {
+            self.stats.num_invalidated += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefetcher(kind: PrefetchKind, degree: usize) -> Box<dyn Prefetcher> {
+        PrefetcherBuilder::create(PrefetchConfig { kind, degree })
+    }
+
+    #[test]
+    fn next_line() {
+        let mut next_line = prefetcher(PrefetchKind::NextLine, 2);
+        assert_eq!(next_line.train(10), vec![11, 12]);
+        assert_eq!(next_line.train(u32::MAX), vec![]);
+    }
+
+    #[test]
+    fn stride_needs_confirmation() {
+        let mut stride = prefetcher(PrefetchKind::Stride, 2);
+        assert!(stride.train(10).is_empty());
+        assert!(stride.train(13).is_empty());
+        assert_eq!(stride.train(16), vec![19, 22]);
+        // repeated accesses to the same block do not break the stride
+        assert!(stride.train(16).is_empty());
+        assert_eq!(stride.train(19), vec![22, 25]);
+        assert!(stride.train(17).is_empty());
+        assert!(stride.train(5).is_empty());
+        assert!(stride.train(2).is_empty());
+        assert_eq!(stride.train(1), vec![]);
+    }
+
+    #[test]
+    fn interleaved_streams() {
+        let mut stream = prefetcher(PrefetchKind::Stream, 1);
+        assert!(stream.train(100).is_empty());
+        assert!(stream.train(500).is_empty());
+        assert!(stream.train(101).is_empty());
+        assert!(stream.train(499).is_empty());
+        assert_eq!(stream.train(103), vec![104]);
+        assert_eq!(stream.train(497), vec![496]);
+        // direction change restarts the training
+        assert!(stream.train(102).is_empty());
+        assert_eq!(stream.train(101), vec![100]);
+    }
+
+    #[test]
+    fn unit_accounting() {
+        let mut unit = PrefetchUnit::new(PrefetchConfig::default());
+        unit.demand(10, true);
+        unit.demand(10, false);
+        assert_eq!(unit.next(), Some(11));
+        assert_eq!(unit.next(), None);
+        unit.issued(1, 11);
+        assert_eq!(unit.in_flight(), Some((1, 11)));
+        unit.completed();
+
+        // first access of the prefetched block continues the training
+        unit.demand(11, false);
+        assert_eq!(unit.next(), Some(12));
+        unit.issued(2, 12);
+        unit.issued(3, 13);
+        unit.evicted(12);
+        unit.invalidated(13);
+        unit.invalidated(11);
+        let stats = unit.stats();
+        assert_eq!(stats.num_issued, 3);
+        assert_eq!(stats.num_useful, 1);
+        assert_eq!(stats.num_evicted_unused, 1);
+        assert_eq!(stats.num_invalidated, 1);
+    }
+}
@@ -3,9 +3,20 @@ use crate::{
     utils::AddressLayout,
 };
 use clap::ArgEnum;
+use std::rc::Rc;
+use table::ProtocolTable;
 
+pub mod berkeley;
 pub mod dragon;
+pub mod firefly;
 pub mod mesi;
+pub mod mesif;
+pub mod moesi;
+pub mod msi;
+pub mod table;
+pub mod token;
+pub mod transient;
+pub mod write_once;
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum ProcessorAction {
@@ -13,6 +24,14 @@ pub enum ProcessorAction {
     Write,
 }
 
+/// Access a cache line grants its processor without a bus transaction
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    Invalid,
+    Read,
+    Write,
+}
+
 pub trait Protocol {
     /// cache_idx contains the flat index of the already stored tag (if stored)
     /// store_idx contains the address at which the tag would be stored (useful if not stored yet)
@@ -55,14 +74,33 @@ pub trait Protocol {
     #[cfg(sanity_check)]
     fn sanity_check(&self, cache_idx: usize) -> Option<u32>;
 
+    /// (debugging only) tokens held for the given address and whether the owner token is among
+    /// them (token coherence only)
+    #[cfg(sanity_check)]
+    fn tokens(&self, _addr: u32) -> Option<(usize, bool)> {
+        None
+    }
+
     /// Read broadcast optimization
     fn read_broadcast(&mut self, bus: &mut Bus);
+
+    /// (verification) permission and name of the state of the line holding addr
+    fn line_state(&self, addr: u32) -> (Permission, String);
 }
 
 #[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq)]
 pub enum ProtocolKind {
     Mesi,
     Dragon,
+    Moesi,
+    Mesif,
+    Msi,
+    Firefly,
+    Berkeley,
+    WriteOnce,
+    Token,
+    /// Protocol read from a specification file
+    Table,
 }
 
 pub struct ProtocolBuilder;
@@ -75,6 +113,7 @@ impl ProtocolBuilder {
         block_size: usize,
         associativity: usize,
         addr_layout: &AddressLayout,
+        table: Option<&Rc<ProtocolTable>>,
     ) -> Box<dyn Protocol> {
         match kind {
             ProtocolKind::Dragon => Box::new(dragon::Dragon::new(
@@ -91,6 +130,63 @@ impl ProtocolBuilder {
                 associativity,
                 addr_layout,
             )),
+            ProtocolKind::Moesi => Box::new(moesi::Moesi::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Mesif => Box::new(mesif::Mesif::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Msi => Box::new(msi::Msi::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Firefly => Box::new(firefly::Firefly::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Berkeley => Box::new(berkeley::Berkeley::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::WriteOnce => Box::new(write_once::WriteOnce::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Token => Box::new(token::Token::new(
+                core_id,
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
+            ProtocolKind::Table => Box::new(table::TableProtocol::new(
+                core_id,
+                Rc::clone(table.expect("Table protocol requires a protocol specification.")),
+                cache_size,
+                block_size,
+                associativity,
+                addr_layout,
+            )),
         }
     }
 }
@@ -0,0 +1,502 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+use std::vec::Vec;
+
+const PLACEHOLDER_TAG: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BerkeleyState {
+    /// Dirty: only copy, modified
+    D,
+    /// Shared-Dirty: owner of a modified line that might be shared
+    Sd,
+    /// Valid: clean copy (with respect to the owner), possibly shared
+    V,
+    I,
+}
+
+/// Berkeley (ownership) protocol. Only the owner of a line (D or Sd) supplies it to other caches,
+/// dirty lines are handed over between caches without updating main memory. Valid copies never
+/// supply data.
+pub struct Berkeley {
+    core_id: usize,
+    cache_state: Vec<(BerkeleyState, u32)>,
+    // bookkeeping only (not part of the protocol): true if another cache accessed the line while
+    // we held it. Required for the private / shared access statistics.
+    shared: Vec<bool>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+}
+
+impl Berkeley {
+    pub fn new(
+        core_id: usize,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        Berkeley {
+            core_id,
+            cache_state: vec![(BerkeleyState::I, PLACEHOLDER_TAG); cache_size / block_size],
+            shared: vec![false; cache_size / block_size],
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        let (current_state, current_tag) = &self.cache_state[flat_cache_idx.unwrap_or_default()];
+        assert!(!hit || *current_tag == self.addr_layout.tag(addr));
+        let (next_state, bus_transaction) = match (current_state, &action, hit) {
+            // HIT
+            (BerkeleyState::D, ProcessorAction::Read | ProcessorAction::Write, true) => {
+                (BerkeleyState::D, None)
+            }
+            (BerkeleyState::Sd, ProcessorAction::Read, true) => (BerkeleyState::Sd, None),
+            (BerkeleyState::V, ProcessorAction::Read, true) => (BerkeleyState::V, None),
+            // all other copies have to be invalidated before writing
+            (BerkeleyState::Sd | BerkeleyState::V, ProcessorAction::Write, true) => (
+                BerkeleyState::D,
+                Some(BusAction::BusRdXMem(addr, WORD_SIZE)),
+            ),
+
+            (BerkeleyState::I, ProcessorAction::Read, true) => (
+                BerkeleyState::V,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (BerkeleyState::I, ProcessorAction::Write, true) => (
+                BerkeleyState::D,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+
+            // MISS
+            (_, ProcessorAction::Read, false) => (
+                BerkeleyState::V,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (_, ProcessorAction::Write, false) => (
+                BerkeleyState::D,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+        };
+        #[cfg(verbose)]
+        println!(
+            "({:?}) Berkeley: Require state transition: {:?} -> {:?}, bus: {:?}",
+            self.core_id, current_state, next_state, bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action || no bus action required => already modify state
+            let idx = flat_cache_idx.unwrap_or(flat_store_idx);
+            if bus_transaction.is_some() {
+                self.shared[idx] = false;
+            }
+            self.cache_state[idx] = (next_state, self.addr_layout.tag(addr));
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Berkeley: protocol could not update: bus is busy and required.",
+                self.core_id
+            );
+        }
+        bus_transaction
+    }
+
+    fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
+        // no active tasks means no snooping
+        let task = bus.active_task()?;
+        if task.issuer_id == self.core_id {
+            return None;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached => we don't care
+        let idx = self.idx_of_addr(addr)?;
+        let (state, stored_tag) = &mut self.cache_state[idx];
+        assert!(*stored_tag == tag);
+
+        // save for logging purposes:
+        let old_state = *state;
+        let old_task_action = task.action;
+        let old_task_time = task.remaining_cycles;
+
+        match (task.action, &state) {
+            // Event: Someone else wants to read (not exclusive) our dirty or owned line
+            // => D / Sd -> Sd and supply the line. Main memory is not updated.
+            (BusAction::BusRdMem(b_addr, c), BerkeleyState::D | BerkeleyState::Sd) => {
+                debug_assert!(b_addr == addr);
+                *state = BerkeleyState::Sd;
+                self.shared[idx] = true;
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to read (not exclusive) our valid line
+            // => V -> V, data is supplied by the owner or main memory
+            (
+                BusAction::BusRdMem(b_addr, _) | BusAction::BusRdShared(b_addr, _),
+                BerkeleyState::V,
+            ) => {
+                debug_assert!(b_addr == addr);
+                self.shared[idx] = true;
+                task.shared = true;
+            }
+
+            // Event: Someone else wants to readX our dirty or owned line
+            // => D / Sd -> I and hand the line (and ownership) over to the new holder
+            (
+                BusAction::BusRdXMem(b_addr, c) | BusAction::BusRdXShared(b_addr, c),
+                BerkeleyState::Sd,
+            )
+            | (BusAction::BusRdXMem(b_addr, c), BerkeleyState::D) => {
+                debug_assert!(b_addr == addr);
+                *state = BerkeleyState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to readX our valid line
+            // => V -> I
+            (
+                BusAction::BusRdXMem(b_addr, _) | BusAction::BusRdXShared(b_addr, _),
+                BerkeleyState::V,
+            ) => {
+                debug_assert!(b_addr == addr);
+                *state = BerkeleyState::I;
+            }
+
+            (BusAction::BusRdXShared(_, _) | BusAction::BusRdShared(_, _), BerkeleyState::D) => {
+                panic!("Reached invalid state.");
+            }
+            // Ignore bus events that don't change anything
+            _ => return None,
+        }
+        if *state != old_state {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Berkeley: Snooping update: State of tag {:x}: {:?} -> {:?}",
+                self.core_id, tag, old_state, state
+            );
+        }
+        if task.action != old_task_action || task.remaining_cycles != old_task_time {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Berkeley: Snooping update: Task changed: Action {:?} ({:?}) -> Action {:?} ({:?}).",
+                self.core_id,
+                old_task_action,
+                old_task_time,
+                task.action,
+                task.remaining_cycles
+            );
+        }
+        Some(*task)
+    }
+
+    fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        // no active tasks means no after-snoop
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return,
+        };
+        // after-snoop only regards actions of other cores on our task
+        if task.issuer_id != self.core_id {
+            return;
+        }
+        // Event: We read and the line was supplied by the owner or is held by other caches
+        // => Value is shared. There is no state change, only statistics are affected.
+        if let BusAction::BusRdMem(addr, _) | BusAction::BusRdShared(addr, _) = task.action {
+            if task.shared || matches!(task.action, BusAction::BusRdShared(_, _)) {
+                if let Some(idx) = self.idx_of_addr(addr) {
+                    self.shared[idx] = true;
+                }
+            }
+        }
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity)).find(|&i| self.cache_state[i].1 == tag)
+    }
+}
+
+impl Protocol for Berkeley {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        self.bus_snoop_transition(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.bus_after_snoop_transition(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == tag);
+        matches!(state, BerkeleyState::D | BerkeleyState::Sd)
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        assert!(state != BerkeleyState::I);
+        match state {
+            BerkeleyState::Sd => true,
+            BerkeleyState::V => self.shared[cache_idx],
+            _ => false,
+        }
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        Some(self.cache_state[cache_idx].1)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].1 == tag);
+        self.cache_state[cache_idx] = (BerkeleyState::I, PLACEHOLDER_TAG)
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with Berkeley protocol.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            BerkeleyState::D => Permission::Write,
+            BerkeleyState::Sd | BerkeleyState::V => Permission::Read,
+            BerkeleyState::I => Permission::Invalid,
+        };
+        (permission, format!("{:?}", state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    #[test]
+    fn invalid_to_valid_to_valid_to_dirty() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Berkeley::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdMem(addr, BLOCK_SIZE)));
+        bus.put_on(0, action.unwrap());
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (BerkeleyState::V, layout.tag(addr))
+        );
+        assert!(!protocol.is_shared(store_idx, addr));
+        bus.clear();
+
+        let action = protocol.read(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (BerkeleyState::V, layout.tag(addr))
+        );
+
+        // no exclusive state => the write always requires an invalidation
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, WORD_SIZE)));
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (BerkeleyState::D, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn dirty_to_shared_dirty() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Berkeley::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Berkeley::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        bus.put_on(0, action.unwrap());
+        protocol.after_snoop(&mut bus);
+        bus.clear();
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (BerkeleyState::D, layout.tag(addr))
+        );
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+
+        // the owner supplies the line without a flush to main memory
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdShared(addr, BLOCK_SIZE));
+        assert_eq!(task.remaining_cycles, 2);
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (BerkeleyState::Sd, layout.tag(addr))
+        );
+        assert!(protocol.writeback_required(store_idx, layout.tag(addr)));
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (BerkeleyState::V, layout.tag(addr))
+        );
+        assert!(other_protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    fn valid_does_not_supply() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Berkeley::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Berkeley::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        protocol.cache_state[store_idx] = (BerkeleyState::V, layout.tag(addr));
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        assert!(task.shared);
+        assert!(protocol.is_shared(store_idx, addr));
+
+        other_protocol.after_snoop(&mut bus);
+        assert!(other_protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    fn shared_dirty_to_invalid() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Berkeley::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Berkeley::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        protocol.cache_state[store_idx] = (BerkeleyState::Sd, layout.tag(addr));
+        other_protocol.cache_state[store_idx] = (BerkeleyState::V, layout.tag(addr));
+
+        let action = other_protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, WORD_SIZE)));
+        bus.put_on(1, action.unwrap());
+
+        // ownership moves to the writer
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdXShared(addr, WORD_SIZE));
+        assert_eq!(protocol.cache_state[store_idx].0, BerkeleyState::I);
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (BerkeleyState::D, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn valid_to_invalid() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Berkeley::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Berkeley::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        protocol.cache_state[store_idx] = (BerkeleyState::V, layout.tag(addr));
+
+        let action = other_protocol.write(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdXMem(addr, BLOCK_SIZE));
+        assert_eq!(task.remaining_cycles, 100);
+        assert_eq!(protocol.cache_state[store_idx].0, BerkeleyState::I);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{ProcessorAction, Protocol};
+use super::{Permission, ProcessorAction, Protocol};
 use crate::bus::{Bus, BusAction, Task};
 use crate::system::WORD_SIZE;
 use crate::utils::AddressLayout;
@@ -97,7 +97,7 @@ impl Dragon {
             bus_transaction
         );
 
-        if bus_transaction.is_none() || !bus.occupied() {
+        if bus_transaction.is_none() || bus.available(self.core_id) {
             // Cache will issue bus action => already modifiy state
             self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
                 Some((next_state, self.addr_layout.tag(addr)));
@@ -119,6 +119,7 @@ impl Dragon {
     }
 
     fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
         let mut task = match bus.active_task() {
             Some(t) => t,
             None => return None,
@@ -143,7 +144,7 @@ impl Dragon {
             (BusAction::BusRdMem(b_addr, c), _) => {
                 debug_assert!(*b_addr == addr);
                 task.action = BusAction::BusRdShared(*b_addr, *c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
 
             // Event: Someone else updates a cache block that we have cached
@@ -152,7 +153,7 @@ impl Dragon {
             (BusAction::BusUpdMem(b_addr, c), _) => {
                 debug_assert!(*b_addr == addr);
                 task.action = BusAction::BusUpdShared(*b_addr, *c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
             _ => (),
         }
@@ -199,6 +200,7 @@ impl Dragon {
     }
 
     fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        let timing = bus.timing();
         let task = match bus.active_task() {
             Some(t) => t,
             None => return,
@@ -241,14 +243,14 @@ impl Dragon {
             (BusAction::BusUpdMem(_, _), DragonState::E) => {
                 // BusUpd not required, BusRd time should be counted though.
                 task.action = BusAction::BusRdMem(addr, self.block_size);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
                 *state = Some((DragonState::M, tag));
             }
             (BusAction::BusUpdShared(_, _), DragonState::E) => {
                 // BusUpd and BusRd required => adjust time.
                 task.action = BusAction::BusUpdShared(addr, self.block_size);
-                task.remaining_cycles = Bus::price(&task.action)
-                    + Bus::price(&BusAction::BusRdShared(addr, self.block_size));
+                task.remaining_cycles = timing.price(&task.action)
+                    + timing.price(&BusAction::BusRdShared(addr, self.block_size));
                 *state = Some((DragonState::Sm, tag));
             }
             _ => (),
@@ -324,6 +326,18 @@ impl Protocol for Dragon {
     fn read_broadcast(&mut self, _: &mut Bus) {
         panic!("Read broadcast optimization cannot be used with dragon protocol.")
     }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].unwrap().0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            DragonState::E | DragonState::M => Permission::Write,
+            DragonState::Sc | DragonState::Sm => Permission::Read,
+        };
+        (permission, format!("{:?}", state))
+    }
 }
 
 #[cfg(test)]
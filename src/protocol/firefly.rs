@@ -0,0 +1,547 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FireflyState {
+    /// Valid-Exclusive: only copy, clean
+    E,
+    /// Shared: other caches might hold a copy, always clean
+    S,
+    /// Dirty: only copy, modified
+    D,
+}
+
+/// DEC Firefly update protocol. In contrast to Dragon, writes to shared lines are written through
+/// to main memory (BusUpdMem) while the other sharers pick up the new value from the bus. Shared
+/// lines are therefore always clean and only D requires a writeback.
+pub struct Firefly {
+    core_id: usize,
+    cache_state: Vec<Option<(FireflyState, u32)>>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+}
+
+impl Firefly {
+    pub fn new(
+        core_id: usize,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        Firefly {
+            core_id,
+            cache_state: vec![None; cache_size / block_size],
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        debug_assert!((hit && flat_cache_idx.is_some()) || (!hit && flat_cache_idx.is_none()));
+
+        // fills current_state with placeholder if hit == false.
+        let current_state = &self.cache_state[flat_cache_idx.unwrap_or_default()];
+
+        let (next_state, bus_transaction) = match (current_state, &action, hit) {
+            // HIT
+            (Some((FireflyState::E, _)), ProcessorAction::Read, true) => (FireflyState::E, None),
+            (Some((FireflyState::E, _)), ProcessorAction::Write, true) => (FireflyState::D, None),
+            (Some((FireflyState::S, _)), ProcessorAction::Read, true) => (FireflyState::S, None),
+            // write through to memory and update all sharers. If no other cache raises the shared
+            // line, the line becomes exclusive after the transaction.
+            (Some((FireflyState::S, _)), ProcessorAction::Write, true) => {
+                (FireflyState::S, Some(BusAction::BusUpdMem(addr, WORD_SIZE)))
+            }
+            (Some((FireflyState::D, _)), _, true) => (FireflyState::D, None),
+
+            // MISS
+            // check shared line, if set => FireflyState::S
+            (_, ProcessorAction::Read, false) => (
+                FireflyState::E,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (_, ProcessorAction::Write, false) => (
+                // signal after_snoop that this is a cold write (E never issues an update)
+                FireflyState::E,
+                Some(BusAction::BusUpdMem(addr, self.block_size)),
+            ),
+            _ => panic!(
+                "({:?}) Unresolved processor event: {:?}",
+                self.core_id,
+                (current_state, action, hit)
+            ),
+        };
+        #[cfg(verbose)]
+        println!(
+            "({:?}) Firefly: Require state transition: {:?} -> {:?}, bus: {:?}",
+            self.core_id,
+            current_state.map(|state| state.0),
+            next_state,
+            bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action => already modifiy state
+            self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
+                Some((next_state, self.addr_layout.tag(addr)));
+
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Firefly: protocol state successfully updated",
+                self.core_id
+            );
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Firefly: protocol could not update: bus is busy and required.",
+                self.core_id
+            );
+        }
+        bus_transaction
+    }
+
+    fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
+        let task = bus.active_task()?;
+        if task.issuer_id == self.core_id {
+            return None;
+        }
+
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        let idx = self.idx_of_addr(addr)?;
+        let state = self.cache_state[idx].as_mut().unwrap();
+
+        match (task.action, state.0) {
+            // Event: Someone else reads our dirty line
+            // => D -> S, flush line to main memory (flush cost + transfer to other core cost)
+            (BusAction::BusRdMem(b_addr, c), FireflyState::D) => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
+            }
+
+            // Event: Someone else reads our clean line
+            // => E -> S / S -> S and supply line
+            (BusAction::BusRdMem(b_addr, c), FireflyState::E | FireflyState::S) => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else does a cold write to our dirty line
+            // => D -> S, flush line to main memory. The write through is priced by the issuer.
+            (BusAction::BusUpdMem(b_addr, c), FireflyState::D) => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusUpdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
+            }
+
+            // Event: Someone else writes through to a line that we have cached
+            // => take the update, E -> S / S -> S
+            (BusAction::BusUpdMem(b_addr, _), FireflyState::E | FireflyState::S) => {
+                debug_assert!(b_addr == addr);
+            }
+
+            // Ignore bus events that don't change anything
+            _ => return None,
+        };
+        // every cache that holds the line raises the shared line
+        task.shared = true;
+        #[cfg(verbose)]
+        if state.0 != FireflyState::S {
+            println!(
+                "({:?}) Firefly: Snooping update: State of tag {:x}: {:?} -> {:?}",
+                self.core_id,
+                tag,
+                state.0,
+                FireflyState::S
+            );
+        }
+        *state = (FireflyState::S, tag);
+        Some(*task)
+    }
+
+    fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        let timing = bus.timing();
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return,
+        };
+        if task.issuer_id != self.core_id {
+            return;
+        }
+
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        let idx = match self.idx_of_addr(addr) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let state = &mut self.cache_state[idx];
+
+        match (task.action, state.map(|value| value.0), task.shared) {
+            // Event: We read using BusRdMem and some other core changed action to BusRdShared
+            // => Value is shared.
+            (BusAction::BusRdShared(_, _), Some(FireflyState::E), _) => {
+                *state = Some((FireflyState::S, tag));
+            }
+
+            // Event: Our write through was not picked up by any other cache
+            // => S -> E once it is done, memory is up to date. Until then the line stays in S, so
+            // the after-snoops of the remaining cycles do not mistake it for a cold write.
+            (BusAction::BusUpdMem(_, _), Some(FireflyState::S), false)
+                if task.remaining_cycles == 0 =>
+            {
+                *state = Some((FireflyState::E, tag));
+            }
+
+            // cold writes are signaled by not setting the state to the appropriate D / S
+            (BusAction::BusUpdMem(_, _), Some(FireflyState::E), false) => {
+                // no sharers: write into the private line, only the BusRd time is counted.
+                task.action = BusAction::BusRdMem(addr, self.block_size);
+                task.remaining_cycles = timing.price(&task.action);
+                *state = Some((FireflyState::D, tag));
+            }
+            (
+                BusAction::BusUpdMem(_, _) | BusAction::BusUpdShared(_, _),
+                Some(FireflyState::E),
+                true,
+            ) => {
+                // line is supplied by a sharer (or flushed by the dirty owner), afterwards the
+                // written word is written through to memory.
+                let supply_time = match task.action {
+                    BusAction::BusUpdShared(_, _) => task.remaining_cycles,
+                    _ => timing.price(&BusAction::BusRdShared(addr, self.block_size)),
+                };
+                task.action = BusAction::BusUpdMem(addr, self.block_size);
+                task.remaining_cycles =
+                    supply_time + timing.price(&BusAction::BusUpdMem(addr, WORD_SIZE));
+                *state = Some((FireflyState::S, tag));
+            }
+            _ => (),
+        }
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity))
+            .find(|&i| self.cache_state[i].is_some_and(|(_, t)| t == tag))
+    }
+}
+
+impl Protocol for Firefly {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        self.bus_snoop_transition(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.bus_after_snoop_transition(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        assert!(self.cache_state[cache_idx].is_some());
+        let (state, stored_tag) = self.cache_state[cache_idx].unwrap();
+        assert!(stored_tag == tag);
+        state == FireflyState::D
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        assert!(self.cache_state[cache_idx].is_some());
+        let (state, stored_tag) = self.cache_state[cache_idx].unwrap();
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        state == FireflyState::S
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        self.cache_state[cache_idx].map(|(_, tag)| tag)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].unwrap().1 == tag);
+        self.cache_state[cache_idx] = None
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with firefly protocol.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].unwrap().0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            FireflyState::E | FireflyState::D => Permission::Write,
+            FireflyState::S => Permission::Read,
+        };
+        (permission, format!("{:?}", state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    #[test]
+    fn invalid_to_exclusive_to_exclusive_to_dirty() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Firefly::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        assert_eq!(protocol.cache_state[store_idx], None);
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+        if let Some(action) = action {
+            bus.put_on(0, action);
+        }
+
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::E, layout.tag(addr))
+        );
+        bus.clear();
+
+        let action = protocol.read(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::E, layout.tag(addr))
+        );
+
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::D, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn cold_write_without_sharers_to_dirty() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Firefly::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        assert_eq!(action, Some(BusAction::BusUpdMem(addr, BLOCK_SIZE)));
+        bus.put_on(0, action.unwrap());
+
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::D, layout.tag(addr))
+        );
+        let task = bus.active_task().unwrap();
+        assert_eq!(task.action, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        assert_eq!(task.remaining_cycles, 100);
+    }
+
+    #[test]
+    fn dirty_to_shared_on_read() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Firefly::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Firefly::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        protocol.cache_state[store_idx] = Some((FireflyState::D, layout.tag(addr)));
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            task.unwrap().action,
+            BusAction::BusRdShared(addr, BLOCK_SIZE)
+        );
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+        assert!(!protocol.writeback_required(store_idx, layout.tag(addr)));
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn shared_write_through() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Firefly::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Firefly::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        protocol.cache_state[store_idx] = Some((FireflyState::S, layout.tag(addr)));
+        other_protocol.cache_state[store_idx] = Some((FireflyState::S, layout.tag(addr)));
+
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusUpdMem(addr, WORD_SIZE)));
+        bus.put_on(0, action.unwrap());
+
+        let task = other_protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert!(task.unwrap().shared);
+        assert_eq!(
+            other_protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+        // write through is never cleared, memory is always updated
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 100);
+        while bus.occupied() {
+            bus.update();
+        }
+
+        // the other sharer is gone => next write through makes the line exclusive
+        other_protocol.cache_state[store_idx] = None;
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        bus.put_on(0, action.unwrap());
+        assert!(other_protocol.snoop(&mut bus).is_none());
+        protocol.after_snoop(&mut bus);
+        // the write through stays an update until it is done
+        assert_eq!(
+            bus.active_task().unwrap().action,
+            BusAction::BusUpdMem(addr, WORD_SIZE)
+        );
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+        while bus.active_task().unwrap().remaining_cycles > 0 {
+            bus.update();
+            protocol.after_snoop(&mut bus);
+        }
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::E, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn cold_write_to_shared() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Firefly::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Firefly::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        other_protocol.cache_state[store_idx] = Some((FireflyState::D, layout.tag(addr)));
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        bus.put_on(0, action.unwrap());
+
+        let task = other_protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            other_protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx].unwrap(),
+            (FireflyState::S, layout.tag(addr))
+        );
+        // flush of the dirty line + write through of the written word
+        let task = bus.active_task().unwrap();
+        assert_eq!(task.action, BusAction::BusUpdMem(addr, BLOCK_SIZE));
+        assert_eq!(task.remaining_cycles, 200);
+    }
+}
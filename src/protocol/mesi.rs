@@ -1,4 +1,4 @@
-use super::{ProcessorAction, Protocol};
+use super::{Permission, ProcessorAction, Protocol};
 use crate::bus::{Bus, BusAction, Task};
 use crate::system::WORD_SIZE;
 use crate::utils::AddressLayout;
@@ -87,7 +87,7 @@ impl Mesi {
             self.core_id, current_state, next_state, bus_transaction
         );
 
-        if bus_transaction.is_none() || !bus.occupied() {
+        if bus_transaction.is_none() || bus.available(self.core_id) {
             // Cache will issue bus action || no bus action required => already modify state
             self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
                 (next_state, self.addr_layout.tag(addr));
@@ -103,6 +103,7 @@ impl Mesi {
     }
 
     fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
         // no active tasks means no snooping
         let mut task = match bus.active_task() {
             Some(t) => t,
@@ -133,7 +134,7 @@ impl Mesi {
                 debug_assert!(b_addr == addr);
                 *state = MesiState::S;
                 task.action = BusAction::BusRdShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&BusAction::Flush(0, self.block_size));
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
             }
 
             // Event: Someone else wants to readX our modified line
@@ -142,7 +143,7 @@ impl Mesi {
                 debug_assert!(b_addr == addr);
                 *state = MesiState::I;
                 task.action = BusAction::BusRdXShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&BusAction::Flush(0, self.block_size));
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
             }
 
             // Event: Someone else wants to read (not X) our exclusive line
@@ -151,7 +152,7 @@ impl Mesi {
                 debug_assert!(b_addr == addr);
                 *state = MesiState::S;
                 task.action = BusAction::BusRdShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
 
             // Event: Someone else wants to readX our exclusive line
@@ -160,7 +161,7 @@ impl Mesi {
                 debug_assert!(b_addr == addr);
                 *state = MesiState::I;
                 task.action = BusAction::BusRdXShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
 
             // Event: Someone else wants to read (not X) our shared line
@@ -168,7 +169,7 @@ impl Mesi {
             (BusAction::BusRdMem(b_addr, c), MesiState::S) => {
                 debug_assert!(b_addr == addr);
                 task.action = BusAction::BusRdShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
 
             // Event: Someone else wants to readX our shared line
@@ -180,7 +181,7 @@ impl Mesi {
                 debug_assert!(b_addr == addr);
                 *state = MesiState::I;
                 task.action = BusAction::BusRdXShared(b_addr, c);
-                task.remaining_cycles = Bus::price(&task.action);
+                task.remaining_cycles = timing.price(&task.action);
             }
 
             (
@@ -341,6 +342,19 @@ impl Protocol for Mesi {
             }
         }
     }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            MesiState::M | MesiState::E => Permission::Write,
+            MesiState::S => Permission::Read,
+            MesiState::I => Permission::Invalid,
+        };
+        (permission, format!("{:?}", state))
+    }
 }
 
 
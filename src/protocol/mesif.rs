@@ -0,0 +1,562 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+use std::vec::Vec;
+
+const PLACEHOLDER_TAG: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MesifState {
+    M,
+    E,
+    S,
+    I,
+    F,
+}
+
+/// MESIF protocol. In contrast to Illinois MESI, only the cache holding the line in the Forward
+/// state answers shared reads. Plain sharers only raise the shared line, so a read that finds no
+/// forwarder is served by main memory. The most recent reader always becomes the new forwarder.
+pub struct Mesif {
+    core_id: usize,
+    cache_state: Vec<(MesifState, u32)>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+}
+
+impl Mesif {
+    pub fn new(
+        core_id: usize,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        Mesif {
+            core_id,
+            cache_state: vec![(MesifState::I, PLACEHOLDER_TAG); cache_size / block_size],
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        let (current_state, current_tag) = &self.cache_state[flat_cache_idx.unwrap_or_default()];
+        assert!(!hit || *current_tag == self.addr_layout.tag(addr));
+        let (next_state, bus_transaction) = match (current_state, &action, hit) {
+            // HIT
+            (MesifState::M, ProcessorAction::Read | ProcessorAction::Write, true) => {
+                (MesifState::M, None)
+            }
+            (MesifState::E, ProcessorAction::Write, true) => (MesifState::M, None),
+            (MesifState::E, ProcessorAction::Read, true) => (MesifState::E, None),
+            (MesifState::S, ProcessorAction::Read, true) => (MesifState::S, None),
+            (MesifState::F, ProcessorAction::Read, true) => (MesifState::F, None),
+            (MesifState::S | MesifState::F, ProcessorAction::Write, true) => {
+                (MesifState::M, Some(BusAction::BusRdXMem(addr, WORD_SIZE)))
+            }
+
+            (MesifState::I, ProcessorAction::Read, true) => (
+                MesifState::E,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (MesifState::I, ProcessorAction::Write, true) => (
+                MesifState::M,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+
+            // MISS
+            (_, ProcessorAction::Read, false) => (
+                MesifState::E,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (_, ProcessorAction::Write, false) => (
+                MesifState::M,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+        };
+        #[cfg(verbose)]
+        println!(
+            "({:?}) MESIF: Require state transition: {:?} -> {:?}, bus: {:?}",
+            self.core_id, current_state, next_state, bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action || no bus action required => already modify state
+            self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
+                (next_state, self.addr_layout.tag(addr));
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MESIF: protocol could not update: bus is busy and required.",
+                self.core_id
+            );
+        }
+        bus_transaction
+    }
+
+    fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
+        // no active tasks means no snooping
+        let task = bus.active_task()?;
+        if task.issuer_id == self.core_id {
+            return None;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached => we don't care
+        let (state, stored_tag) = match self.idx_of_addr(addr) {
+            Some(idx) => &mut self.cache_state[idx],
+            None => return None,
+        };
+        assert!(*stored_tag == tag);
+
+        // save for logging purposes:
+        let old_state = *state;
+        let old_task_action = task.action;
+        let old_task_time = task.remaining_cycles;
+
+        match (task.action, &state) {
+            // Event: Someone else wants to read (not exclusive) our modified line
+            // => transition from M -> S, flush line to main memory (flush cost + transfer to other
+            // core cost). The reader becomes the new forwarder.
+            (BusAction::BusRdMem(b_addr, c), MesifState::M) => {
+                debug_assert!(b_addr == addr);
+                *state = MesifState::S;
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
+            }
+
+            // Event: Someone else wants to readX our modified line
+            // => M -> I and Flush
+            (BusAction::BusRdXMem(b_addr, c), MesifState::M) => {
+                debug_assert!(b_addr == addr);
+                *state = MesifState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&BusAction::Flush(0, self.block_size));
+            }
+
+            // Event: Someone else wants to read (not X) our exclusive or forwarded line
+            // => E / F -> S and supply line, the reader takes over the forwarding
+            (BusAction::BusRdMem(b_addr, c), MesifState::E | MesifState::F) => {
+                debug_assert!(b_addr == addr);
+                *state = MesifState::S;
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to readX our exclusive line
+            // => E -> I
+            (BusAction::BusRdXMem(b_addr, c), MesifState::E) => {
+                debug_assert!(b_addr == addr);
+                *state = MesifState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to read (not X) our shared line
+            // => S -> S, only signal that the line is shared. Data is supplied by the forwarder
+            // (if any) or by main memory.
+            (BusAction::BusRdMem(b_addr, _), MesifState::S) => {
+                debug_assert!(b_addr == addr);
+                task.shared = true;
+            }
+
+            // Event: Someone else wants to readX our shared or forwarded line
+            // => S / F -> I but supply line
+            (
+                BusAction::BusRdXMem(b_addr, c) | BusAction::BusRdXShared(b_addr, c),
+                MesifState::S | MesifState::F,
+            ) => {
+                debug_assert!(b_addr == addr);
+                *state = MesifState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            (
+                BusAction::BusRdXShared(_, _) | BusAction::BusRdShared(_, _),
+                MesifState::E | MesifState::M,
+            ) => {
+                panic!("Reached invalid state.");
+            }
+            // Ignore bus events that don't change anything
+            _ => return None,
+        }
+        if *state != old_state {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MESIF: Snooping update: State of tag {:x}: {:?} -> {:?}",
+                self.core_id, tag, old_state, state
+            );
+        }
+        if task.action != old_task_action || task.remaining_cycles != old_task_time {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MESIF: Snooping update: Task changed: Action {:?} ({:?}) -> Action {:?} ({:?}).",
+                self.core_id,
+                old_task_action,
+                old_task_time,
+                task.action,
+                task.remaining_cycles
+            );
+        }
+        Some(*task)
+    }
+
+    fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        // no active tasks means no after-snoop
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return,
+        };
+        // after-snoop only regards actions of other cores on our task
+        if task.issuer_id != self.core_id {
+            return;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached => we don't care
+        let (state, stored_tag) = match self.idx_of_addr(addr) {
+            Some(idx) => &mut self.cache_state[idx],
+            None => return,
+        };
+        assert!(*stored_tag == tag);
+
+        // Event: We read using BusRdMem and the line was either forwarded by another cache or
+        // other sharers raised the shared line
+        // => Value is shared and we are the new forwarder.
+        let shared = match task.action {
+            BusAction::BusRdShared(_, _) => true,
+            BusAction::BusRdMem(_, _) => task.shared,
+            _ => false,
+        };
+        if shared && *state == MesifState::E {
+            *state = MesifState::F;
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MESIF: After-Snoop update: State of tag {:x}: E -> F",
+                self.core_id, tag
+            );
+        }
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity)).find(|&i| self.cache_state[i].1 == tag)
+    }
+}
+
+impl Protocol for Mesif {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        self.bus_snoop_transition(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.bus_after_snoop_transition(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == tag);
+        state == MesifState::M
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        assert!(state != MesifState::I);
+        matches!(state, MesifState::S | MesifState::F)
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        Some(self.cache_state[cache_idx].1)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].1 == tag);
+        self.cache_state[cache_idx] = (MesifState::I, PLACEHOLDER_TAG)
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with MESIF protocol.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            MesifState::M | MesifState::E => Permission::Write,
+            MesifState::S | MesifState::F => Permission::Read,
+            MesifState::I => Permission::Invalid,
+        };
+        (permission, format!("{:?}", state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::TimingConfig;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    #[test]
+    fn exclusive_to_shared_and_forward() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Mesif::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        assert_eq!(protocol.cache_state[store_idx], (MesifState::I, 0));
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MesifState::E, layout.tag(addr))
+        );
+
+        let mut other_protocol = Mesif::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(1, action);
+        }
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MesifState::S, layout.tag(addr))
+        );
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MesifState::F, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn only_forwarder_answers_shared_read() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut sharer = Mesif::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut forwarder = Mesif::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut reader = Mesif::new(2, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        sharer.cache_state[store_idx] = (MesifState::S, layout.tag(addr));
+        forwarder.cache_state[store_idx] = (MesifState::F, layout.tag(addr));
+
+        let action = reader.read(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(2, action);
+        }
+
+        // the plain sharer does not supply the line
+        let task = sharer.snoop(&mut bus);
+        assert!(task.is_some());
+        let task = task.unwrap();
+        assert_eq!(task.action, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        assert!(task.shared);
+        assert_eq!(
+            sharer.cache_state[store_idx],
+            (MesifState::S, layout.tag(addr))
+        );
+
+        let task = forwarder.snoop(&mut bus);
+        assert!(task.is_some());
+        let task = task.unwrap();
+        assert_eq!(task.action, BusAction::BusRdShared(addr, BLOCK_SIZE));
+        assert_eq!(
+            task.remaining_cycles,
+            TimingConfig::default().price(&task.action)
+        );
+        assert_eq!(
+            forwarder.cache_state[store_idx],
+            (MesifState::S, layout.tag(addr))
+        );
+
+        reader.after_snoop(&mut bus);
+        assert_eq!(
+            reader.cache_state[store_idx],
+            (MesifState::F, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn shared_read_without_forwarder_goes_to_memory() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut sharer = Mesif::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut reader = Mesif::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        sharer.cache_state[store_idx] = (MesifState::S, layout.tag(addr));
+
+        let action = reader.read(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(1, action);
+        }
+
+        sharer.snoop(&mut bus);
+        let task = *bus.active_task().unwrap();
+        assert_eq!(task.action, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        assert_eq!(
+            task.remaining_cycles,
+            TimingConfig::default().price(&task.action)
+        );
+
+        // shared line is raised => the reader must not end up exclusive
+        reader.after_snoop(&mut bus);
+        assert_eq!(
+            reader.cache_state[store_idx],
+            (MesifState::F, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn forward_to_modified() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut forwarder = Mesif::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut sharer = Mesif::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        forwarder.cache_state[store_idx] = (MesifState::F, layout.tag(addr));
+        sharer.cache_state[store_idx] = (MesifState::S, layout.tag(addr));
+
+        let action = forwarder.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, WORD_SIZE)));
+        assert_eq!(
+            forwarder.cache_state[store_idx],
+            (MesifState::M, layout.tag(addr))
+        );
+        if let Some(action) = action {
+            bus.put_on(0, action);
+        }
+
+        let task = sharer.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            sharer.cache_state[store_idx],
+            (MesifState::I, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn modified_to_shared() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Mesif::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MesifState::M, layout.tag(addr))
+        );
+
+        let mut other_protocol = Mesif::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(1, action);
+        }
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MesifState::S, layout.tag(addr))
+        );
+        assert_eq!(
+            task.unwrap().remaining_cycles,
+            TimingConfig::default().price(&BusAction::Flush(0, BLOCK_SIZE))
+        );
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MesifState::F, layout.tag(addr))
+        );
+    }
+}
@@ -0,0 +1,563 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+use std::vec::Vec;
+
+const PLACEHOLDER_TAG: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoesiState {
+    M,
+    O,
+    E,
+    S,
+    I,
+}
+
+pub struct Moesi {
+    core_id: usize,
+    cache_state: Vec<(MoesiState, u32)>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+}
+
+impl Moesi {
+    pub fn new(
+        core_id: usize,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        Moesi {
+            core_id,
+            cache_state: vec![(MoesiState::I, PLACEHOLDER_TAG); cache_size / block_size],
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        let (current_state, current_tag) = &self.cache_state[flat_cache_idx.unwrap_or_default()];
+        assert!(!hit || *current_tag == self.addr_layout.tag(addr));
+        let (next_state, bus_transaction) = match (current_state, &action, hit) {
+            // HIT
+            (MoesiState::M, ProcessorAction::Read | ProcessorAction::Write, true) => {
+                (MoesiState::M, None)
+            }
+            (MoesiState::O, ProcessorAction::Read, true) => (MoesiState::O, None),
+            // the owner still has to invalidate all sharers before writing
+            (MoesiState::O, ProcessorAction::Write, true) => {
+                (MoesiState::M, Some(BusAction::BusRdXMem(addr, WORD_SIZE)))
+            }
+            (MoesiState::E, ProcessorAction::Write, true) => (MoesiState::M, None),
+            (MoesiState::E, ProcessorAction::Read, true) => (MoesiState::E, None),
+            (MoesiState::S, ProcessorAction::Read, true) => (MoesiState::S, None),
+            (MoesiState::S, ProcessorAction::Write, true) => {
+                (MoesiState::M, Some(BusAction::BusRdXMem(addr, WORD_SIZE)))
+            }
+
+            (MoesiState::I, ProcessorAction::Read, true) => (
+                MoesiState::E,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (MoesiState::I, ProcessorAction::Write, true) => (
+                MoesiState::M,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+
+            // MISS
+            (_, ProcessorAction::Read, false) => (
+                MoesiState::E,
+                Some(BusAction::BusRdMem(addr, self.block_size)),
+            ),
+            (_, ProcessorAction::Write, false) => (
+                MoesiState::M,
+                Some(BusAction::BusRdXMem(addr, self.block_size)),
+            ),
+        };
+        #[cfg(verbose)]
+        println!(
+            "({:?}) MOESI: Require state transition: {:?} -> {:?}, bus: {:?}",
+            self.core_id, current_state, next_state, bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action || no bus action required => already modify state
+            self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
+                (next_state, self.addr_layout.tag(addr));
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MOESI: protocol could not update: bus is busy and required.",
+                self.core_id
+            );
+        }
+        bus_transaction
+    }
+
+    fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
+        // no active tasks means no snooping
+        let task = bus.active_task()?;
+        if task.issuer_id == self.core_id {
+            return None;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached => we don't care
+        let (state, stored_tag) = match self.idx_of_addr(addr) {
+            Some(idx) => &mut self.cache_state[idx],
+            None => return None,
+        };
+        assert!(*stored_tag == tag);
+
+        // save for logging purposes:
+        let old_state = *state;
+        let old_task_action = task.action;
+        let old_task_time = task.remaining_cycles;
+
+        match (task.action, &state) {
+            // Event: Someone else wants to read (not exclusive) our modified line
+            // => transition from M -> O and supply the dirty line directly. In contrast to MESI,
+            // main memory is not updated, so no flush is required.
+            (BusAction::BusRdMem(b_addr, c), MoesiState::M) => {
+                debug_assert!(b_addr == addr);
+                *state = MoesiState::O;
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to read (not exclusive) our owned line
+            // => O -> O and supply line
+            (BusAction::BusRdMem(b_addr, c), MoesiState::O) => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to readX our modified or owned line
+            // => M / O -> I and supply line. The new holder becomes the owner of the dirty data,
+            // therefore no flush is required either.
+            (
+                BusAction::BusRdXMem(b_addr, c) | BusAction::BusRdXShared(b_addr, c),
+                MoesiState::O,
+            )
+            | (BusAction::BusRdXMem(b_addr, c), MoesiState::M) => {
+                debug_assert!(b_addr == addr);
+                *state = MoesiState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to read (not X) our exclusive line
+            // => E -> S
+            (BusAction::BusRdMem(b_addr, c), MoesiState::E) => {
+                debug_assert!(b_addr == addr);
+                *state = MoesiState::S;
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to readX our exclusive line
+            // => E -> I
+            (BusAction::BusRdXMem(b_addr, c), MoesiState::E) => {
+                debug_assert!(b_addr == addr);
+                *state = MoesiState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to read (not X) our shared line
+            // => S -> S and supply line
+            (BusAction::BusRdMem(b_addr, c), MoesiState::S) => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            // Event: Someone else wants to readX our shared line
+            // => S -> I but supply line
+            (
+                BusAction::BusRdXMem(b_addr, c) | BusAction::BusRdXShared(b_addr, c),
+                MoesiState::S,
+            ) => {
+                debug_assert!(b_addr == addr);
+                *state = MoesiState::I;
+                task.action = BusAction::BusRdXShared(b_addr, c);
+                task.remaining_cycles = timing.price(&task.action);
+            }
+
+            (
+                BusAction::BusRdXShared(_, _) | BusAction::BusRdShared(_, _),
+                MoesiState::E | MoesiState::M,
+            ) => {
+                panic!("Reached invalid state.");
+            }
+            // Ignore bus events that don't change anything
+            _ => return None,
+        }
+        if *state != old_state {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MOESI: Snooping update: State of tag {:x}: {:?} -> {:?}",
+                self.core_id, tag, old_state, state
+            );
+        }
+        if task.action != old_task_action || task.remaining_cycles != old_task_time {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MOESI: Snooping update: Task changed: Action {:?} ({:?}) -> Action {:?} ({:?}).",
+                self.core_id,
+                old_task_action,
+                old_task_time,
+                task.action,
+                task.remaining_cycles
+            );
+        }
+        Some(*task)
+    }
+
+    fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        // no active tasks means no after-snoop
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return,
+        };
+        // after-snoop only regards actions of other cores on our task
+        if task.issuer_id != self.core_id {
+            return;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached => we don't care
+        let (state, stored_tag) = match self.idx_of_addr(addr) {
+            Some(idx) => &mut self.cache_state[idx],
+            None => return,
+        };
+        assert!(*stored_tag == tag);
+
+        // Event: We read using BusRdMem and some other core changed action to BusRdShared
+        // => Value is shared.
+        if let (BusAction::BusRdShared(_, _), MoesiState::E) = (&task.action, &state) {
+            *state = MoesiState::S;
+            #[cfg(verbose)]
+            println!(
+                "({:?}) MOESI: After-Snoop update: State of tag {:x}: E -> S",
+                self.core_id, tag
+            );
+        }
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity)).find(|&i| self.cache_state[i].1 == tag)
+    }
+}
+
+impl Protocol for Moesi {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        self.bus_snoop_transition(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.bus_after_snoop_transition(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == tag);
+        matches!(state, MoesiState::M | MoesiState::O)
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        assert!(state != MoesiState::I);
+        matches!(state, MoesiState::S | MoesiState::O)
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        Some(self.cache_state[cache_idx].1)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].1 == tag);
+        self.cache_state[cache_idx] = (MoesiState::I, PLACEHOLDER_TAG)
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with MOESI protocol.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state {
+            MoesiState::M | MoesiState::E => Permission::Write,
+            MoesiState::O | MoesiState::S => Permission::Read,
+            MoesiState::I => Permission::Invalid,
+        };
+        (permission, format!("{:?}", state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::TimingConfig;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    #[test]
+    fn invalid_to_exclusive_to_exclusive_to_modified() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Moesi::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        assert_eq!(protocol.cache_state[store_idx], (MoesiState::I, 0));
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::E, layout.tag(addr))
+        );
+
+        let action = protocol.read(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::E, layout.tag(addr))
+        );
+
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::M, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn modified_to_owned_without_flush() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Moesi::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::M, layout.tag(addr))
+        );
+
+        let mut other_protocol = Moesi::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        assert!(action.is_some());
+
+        if let Some(action) = action {
+            bus.put_on(1, action);
+        }
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::O, layout.tag(addr))
+        );
+        // the dirty line is transferred cache-to-cache, memory is not involved
+        let task = task.unwrap();
+        assert_eq!(task.action, BusAction::BusRdShared(addr, BLOCK_SIZE));
+        assert_eq!(
+            task.remaining_cycles,
+            TimingConfig::default().price(&task.action)
+        );
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MoesiState::S, layout.tag(addr))
+        );
+
+        // the owner is still responsible for the write back, the sharer is not
+        assert!(protocol.writeback_required(store_idx, layout.tag(addr)));
+        assert!(!other_protocol.writeback_required(store_idx, layout.tag(addr)));
+        assert!(protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    fn owned_supplies_further_reads() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Moesi::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        protocol.cache_state[store_idx] = (MoesiState::O, layout.tag(addr));
+
+        let mut other_protocol = Moesi::new(2, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(2, action);
+        }
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::O, layout.tag(addr))
+        );
+        assert_eq!(
+            task.unwrap().action,
+            BusAction::BusRdShared(addr, BLOCK_SIZE)
+        );
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MoesiState::S, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn owned_to_modified_invalidates_sharers() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Moesi::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Moesi::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        protocol.cache_state[store_idx] = (MoesiState::O, layout.tag(addr));
+        other_protocol.cache_state[store_idx] = (MoesiState::S, layout.tag(addr));
+
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, WORD_SIZE)));
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::M, layout.tag(addr))
+        );
+
+        if let Some(action) = action {
+            bus.put_on(0, action);
+        }
+
+        let task = other_protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MoesiState::I, layout.tag(addr))
+        );
+    }
+
+    #[test]
+    fn owned_to_invalid() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Moesi::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let set_idx = layout.index(addr);
+        let block_idx = 0;
+        let store_idx = layout.nested_to_flat(set_idx, block_idx);
+        protocol.cache_state[store_idx] = (MoesiState::O, layout.tag(addr));
+
+        let mut other_protocol = Moesi::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let action = other_protocol.write(addr, None, store_idx, false, &mut bus);
+        if let Some(action) = action {
+            bus.put_on(1, action);
+        }
+
+        let task = protocol.snoop(&mut bus);
+        assert!(task.is_some());
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (MoesiState::I, layout.tag(addr))
+        );
+        assert_eq!(
+            task.unwrap().action,
+            BusAction::BusRdXShared(addr, BLOCK_SIZE)
+        );
+
+        other_protocol.after_snoop(&mut bus);
+        assert_eq!(
+            other_protocol.cache_state[store_idx],
+            (MoesiState::M, layout.tag(addr))
+        );
+    }
+}
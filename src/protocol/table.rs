@@ -0,0 +1,768 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::mem::discriminant;
+use std::rc::Rc;
+
+const PLACEHOLDER_TAG: u32 = 0;
+
+/// Size of a bus transaction emitted by a transition
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Size {
+    Block,
+    Word,
+}
+
+#[derive(Clone, Debug)]
+struct ProcessorRule {
+    // None matches any state
+    states: Option<Vec<usize>>,
+    read: bool,
+    write: bool,
+    hit: bool,
+    next: usize,
+    action: Option<(BusAction, Size)>,
+}
+
+#[derive(Clone, Debug)]
+struct BusRule {
+    actions: Vec<BusAction>,
+    // None matches any state
+    states: Option<Vec<usize>>,
+    // None: the combination is invalid and panics
+    next: Option<usize>,
+    action: Option<BusAction>,
+    // keeps the size of the current transaction if None
+    size: Option<Size>,
+    // sum of the prices of these actions, price of the new action if empty
+    cost: Vec<BusAction>,
+    clear: bool,
+}
+
+/// Coherence protocol specification, read from a file.
+///
+/// The file consists of `key = value` pairs followed by the transition tables in the sections
+/// `[processor]`, `[snoop]` and `[after_snoop]`. Each table row is a whitespace-separated list of
+/// columns, alternatives are separated by `|`, `*` matches every state and `#` starts a comment.
+///
+/// ```text
+/// name = "MESI"
+/// states = ["M", "E", "S", "I"]
+/// invalid = "I"           # state of lines that are not cached
+/// writeback = ["M"]       # states that require a flush on eviction
+/// shared = ["S"]          # states that count as shared data accesses
+///
+/// [processor]
+/// # state   event        hit|miss  ->  next  [bus action  block|word]
+/// S         write        hit       ->  M     BusRdXMem    word
+///
+/// [snoop]
+/// # bus action  state  ->  next|panic  [bus action  [block|word]]  [cost=Action+Action]
+/// BusRdMem      M      ->  S           BusRdShared                  cost=Flush
+///
+/// [after_snoop]
+/// # bus action  state  ->  next  [bus action  [block|word]]  [cost=Action+Action]  [clear]
+/// BusRdShared   E      ->  S
+/// ```
+///
+/// Processor rows are applied when the cache accesses a line, snoop rows when another cache puts
+/// a transaction on the bus and after-snoop rows to our own transactions after all caches
+/// snooped. The first matching row wins. A changed bus action is priced like the new action unless
+/// a cost is given, `clear` removes the transaction from the bus.
+#[derive(Clone, Debug)]
+pub struct ProtocolTable {
+    pub name: String,
+    states: Vec<String>,
+    invalid: usize,
+    writeback: Vec<usize>,
+    shared: Vec<usize>,
+    processor: Vec<ProcessorRule>,
+    snoop: Vec<BusRule>,
+    after_snoop: Vec<BusRule>,
+}
+
+impl ProtocolTable {
+    pub fn open(path: &str) -> Result<ProtocolTable, Error> {
+        ProtocolTable::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(spec: &str) -> Result<ProtocolTable, Error> {
+        let mut table = ProtocolTable {
+            name: String::new(),
+            states: Vec::new(),
+            invalid: usize::MAX,
+            writeback: Vec::new(),
+            shared: Vec::new(),
+            processor: Vec::new(),
+            snoop: Vec::new(),
+            after_snoop: Vec::new(),
+        };
+        // (key, value, line number) of the header, resolved once all states are known
+        let mut header: Vec<(String, String, usize)> = Vec::new();
+        let mut section = String::new();
+
+        for (line_idx, line) in spec.lines().enumerate() {
+            let line_nr = line_idx + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                if !["processor", "snoop", "after_snoop"].contains(&section.as_str()) {
+                    return Err(parse_error(
+                        line_nr,
+                        &format!("unknown section {:?}", section),
+                    ));
+                }
+                if table.states.is_empty() {
+                    table.resolve_header(&header)?;
+                }
+                continue;
+            }
+            match section.as_str() {
+                "" => {
+                    let (key, value) = line
+                        .split_once('=')
+                        .ok_or_else(|| parse_error(line_nr, "expected key = value"))?;
+                    header.push((key.trim().to_string(), value.trim().to_string(), line_nr));
+                }
+                "processor" => {
+                    let rule = table.parse_processor_rule(line, line_nr)?;
+                    table.processor.push(rule);
+                }
+                "snoop" => {
+                    let rule = table.parse_bus_rule(line, line_nr)?;
+                    table.snoop.push(rule);
+                }
+                _ => {
+                    let rule = table.parse_bus_rule(line, line_nr)?;
+                    table.after_snoop.push(rule);
+                }
+            }
+        }
+        if table.states.is_empty() {
+            table.resolve_header(&header)?;
+        }
+        Ok(table)
+    }
+
+    fn resolve_header(&mut self, header: &[(String, String, usize)]) -> Result<(), Error> {
+        for (key, _, line_nr) in header {
+            if !["name", "states", "invalid", "writeback", "shared"].contains(&key.as_str()) {
+                return Err(parse_error(*line_nr, &format!("unknown key {:?}", key)));
+            }
+        }
+        let value_of = |key: &str| header.iter().find(|(k, _, _)| k == key);
+
+        let (_, states, line_nr) = value_of("states").ok_or_else(|| missing_key("states"))?;
+        self.states = parse_list(states);
+        if self.states.is_empty() {
+            return Err(parse_error(*line_nr, "no states defined"));
+        }
+        self.name = value_of("name").map_or(String::from("Table"), |(_, v, _)| unquote(v));
+        let (_, invalid, line_nr) = value_of("invalid").ok_or_else(|| missing_key("invalid"))?;
+        self.invalid = self.state_id(&unquote(invalid), *line_nr)?;
+        if let Some((_, value, line_nr)) = value_of("writeback") {
+            self.writeback = self.state_ids(value, *line_nr)?;
+        }
+        if let Some((_, value, line_nr)) = value_of("shared") {
+            self.shared = self.state_ids(value, *line_nr)?;
+        }
+        Ok(())
+    }
+
+    fn state_ids(&self, list: &str, line_nr: usize) -> Result<Vec<usize>, Error> {
+        parse_list(list)
+            .iter()
+            .map(|s| self.state_id(s, line_nr))
+            .collect()
+    }
+
+    fn state_id(&self, name: &str, line_nr: usize) -> Result<usize, Error> {
+        self.states
+            .iter()
+            .position(|s| s == name)
+            .ok_or_else(|| parse_error(line_nr, &format!("unknown state {:?}", name)))
+    }
+
+    fn parse_states(&self, column: &str, line_nr: usize) -> Result<Option<Vec<usize>>, Error> {
+        if column == "*" {
+            return Ok(None);
+        }
+        column
+            .split('|')
+            .map(|s| self.state_id(s, line_nr))
+            .collect::<Result<Vec<usize>, Error>>()
+            .map(Some)
+    }
+
+    fn parse_processor_rule(&self, line: &str, line_nr: usize) -> Result<ProcessorRule, Error> {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if !(columns.len() == 5 || columns.len() == 7) || columns[3] != "->" {
+            return Err(parse_error(
+                line_nr,
+                "expected: state event hit|miss -> next [bus action block|word]",
+            ));
+        }
+        let events: Vec<&str> = columns[1].split('|').collect();
+        if events.iter().any(|e| *e != "read" && *e != "write") {
+            return Err(parse_error(line_nr, "events are read and write"));
+        }
+        let hit = match columns[2] {
+            "hit" => true,
+            "miss" => false,
+            _ => return Err(parse_error(line_nr, "expected hit or miss")),
+        };
+        let action = match columns.len() {
+            7 => Some((
+                parse_action(columns[5], line_nr)?,
+                parse_size(columns[6], line_nr)?,
+            )),
+            _ => None,
+        };
+        Ok(ProcessorRule {
+            states: self.parse_states(columns[0], line_nr)?,
+            read: events.contains(&"read"),
+            write: events.contains(&"write"),
+            hit,
+            next: self.state_id(columns[4], line_nr)?,
+            action,
+        })
+    }
+
+    fn parse_bus_rule(&self, line: &str, line_nr: usize) -> Result<BusRule, Error> {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 4 || columns[2] != "->" {
+            return Err(parse_error(
+                line_nr,
+                "expected: bus action state -> next|panic [bus action [block|word]] [cost=..] [clear]",
+            ));
+        }
+        let mut rule = BusRule {
+            actions: columns[0]
+                .split('|')
+                .map(|a| parse_action(a, line_nr))
+                .collect::<Result<Vec<BusAction>, Error>>()?,
+            states: self.parse_states(columns[1], line_nr)?,
+            next: match columns[3] {
+                "panic" => None,
+                state => Some(self.state_id(state, line_nr)?),
+            },
+            action: None,
+            size: None,
+            cost: Vec::new(),
+            clear: false,
+        };
+        for column in &columns[4..] {
+            if let Some(cost) = column.strip_prefix("cost=") {
+                rule.cost = cost
+                    .split('+')
+                    .map(|a| parse_action(a, line_nr))
+                    .collect::<Result<Vec<BusAction>, Error>>()?;
+            } else if *column == "clear" {
+                rule.clear = true;
+            } else if *column == "block" || *column == "word" {
+                rule.size = Some(parse_size(column, line_nr)?);
+            } else {
+                rule.action = Some(parse_action(column, line_nr)?);
+            }
+        }
+        Ok(rule)
+    }
+
+    /// Lines in a state whose write hits need no bus transaction may be written silently
+    fn permission(&self, state: usize) -> Permission {
+        if state == self.invalid {
+            Permission::Invalid
+        } else if self.processor.iter().any(|rule| {
+            rule.write
+                && rule.hit
+                && rule.action.is_none()
+                && rule.states.as_ref().is_none_or(|s| s.contains(&state))
+        }) {
+            Permission::Write
+        } else {
+            Permission::Read
+        }
+    }
+
+    fn find_bus_rule<'a>(
+        rules: &'a [BusRule],
+        action: &BusAction,
+        state: usize,
+    ) -> Option<&'a BusRule> {
+        rules.iter().find(|rule| {
+            rule.actions
+                .iter()
+                .any(|a| discriminant(a) == discriminant(action))
+                && rule.states.as_ref().is_none_or(|s| s.contains(&state))
+        })
+    }
+}
+
+fn parse_error(line_nr: usize, msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("protocol specification, line {}: {}", line_nr, msg),
+    )
+}
+
+fn missing_key(key: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("protocol specification: missing key {:?}", key),
+    )
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+/// Parse `["A", "B"]` (or `A B`) into its elements
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(unquote)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn parse_action(name: &str, line_nr: usize) -> Result<BusAction, Error> {
+    Ok(match name {
+        "BusRdMem" => BusAction::BusRdMem(0, 0),
+        "BusRdShared" => BusAction::BusRdShared(0, 0),
+        "BusRdXMem" => BusAction::BusRdXMem(0, 0),
+        "BusRdXShared" => BusAction::BusRdXShared(0, 0),
+        "BusUpdMem" => BusAction::BusUpdMem(0, 0),
+        "BusUpdShared" => BusAction::BusUpdShared(0, 0),
+        "Flush" => BusAction::Flush(0, 0),
+        _ => {
+            return Err(parse_error(
+                line_nr,
+                &format!("unknown bus action {:?}", name),
+            ))
+        }
+    })
+}
+
+fn parse_size(name: &str, line_nr: usize) -> Result<Size, Error> {
+    match name {
+        "block" => Ok(Size::Block),
+        "word" => Ok(Size::Word),
+        _ => Err(parse_error(line_nr, "expected block or word")),
+    }
+}
+
+/// Instantiate the bus action template with address and size
+fn build_action(template: &BusAction, addr: u32, size: usize) -> BusAction {
+    match template {
+        BusAction::BusRdMem(_, _) => BusAction::BusRdMem(addr, size),
+        BusAction::BusRdShared(_, _) => BusAction::BusRdShared(addr, size),
+        BusAction::BusRdXMem(_, _) => BusAction::BusRdXMem(addr, size),
+        BusAction::BusRdXShared(_, _) => BusAction::BusRdXShared(addr, size),
+        BusAction::BusUpdMem(_, _) => BusAction::BusUpdMem(addr, size),
+        BusAction::BusUpdShared(_, _) => BusAction::BusUpdShared(addr, size),
+        BusAction::Flush(_, _) => BusAction::Flush(addr, size),
+    }
+}
+
+/// Protocol whose transitions are looked up in a `ProtocolTable`
+pub struct TableProtocol {
+    core_id: usize,
+    table: Rc<ProtocolTable>,
+    cache_state: Vec<(usize, u32)>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+}
+
+impl TableProtocol {
+    pub fn new(
+        core_id: usize,
+        table: Rc<ProtocolTable>,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        TableProtocol {
+            core_id,
+            cache_state: vec![(table.invalid, PLACEHOLDER_TAG); cache_size / block_size],
+            table,
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+        }
+    }
+
+    fn size(&self, size: Size) -> usize {
+        match size {
+            Size::Block => self.block_size,
+            Size::Word => WORD_SIZE,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        let (current_state, current_tag) = self.cache_state[flat_cache_idx.unwrap_or_default()];
+        assert!(!hit || current_tag == self.addr_layout.tag(addr));
+        let write = action == ProcessorAction::Write;
+        let rule = self
+            .table
+            .processor
+            .iter()
+            .find(|rule| {
+                rule.hit == hit
+                    && (if write { rule.write } else { rule.read })
+                    && rule
+                        .states
+                        .as_ref()
+                        .is_none_or(|s| s.contains(&current_state))
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "({:?}) Unresolved processor event: {:?}",
+                    self.core_id,
+                    (&self.table.states[current_state], action, hit)
+                )
+            });
+        let next_state = rule.next;
+        let bus_transaction = rule
+            .action
+            .map(|(template, size)| build_action(&template, addr, self.size(size)));
+        #[cfg(verbose)]
+        println!(
+            "({:?}) {}: Require state transition: {} -> {}, bus: {:?}",
+            self.core_id,
+            self.table.name,
+            self.table.states[current_state],
+            self.table.states[next_state],
+            bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action || no bus action required => already modify state
+            self.cache_state[flat_cache_idx.unwrap_or(flat_store_idx)] =
+                (next_state, self.addr_layout.tag(addr));
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) {}: protocol could not update: bus is busy and required.",
+                self.core_id, self.table.name
+            );
+        }
+        bus_transaction
+    }
+
+    /// Apply a snoop or after-snoop rule to the line and the active task.
+    /// Returns false if no rule matched.
+    fn apply_bus_rule(&mut self, after_snoop: bool, bus: &mut Bus) -> bool {
+        let timing = bus.timing();
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return false,
+        };
+        let addr = BusAction::extract_addr(task.action);
+        let idx = match self.idx_of_addr(addr) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let state = self.cache_state[idx].0;
+        let rules = if after_snoop {
+            &self.table.after_snoop
+        } else {
+            &self.table.snoop
+        };
+        let rule = match ProtocolTable::find_bus_rule(rules, &task.action, state) {
+            Some(rule) => rule,
+            None => return false,
+        };
+        let next_state = match rule.next {
+            Some(next) => next,
+            None => panic!("Reached invalid state."),
+        };
+
+        #[cfg(verbose)]
+        let (old_task_action, old_task_time) = (task.action, task.remaining_cycles);
+        if let Some(template) = &rule.action {
+            let size = rule
+                .size
+                .map_or(BusAction::extract_size(task.action), |s| self.size(s));
+            task.action = build_action(template, addr, size);
+            task.remaining_cycles = timing.price(&task.action);
+        }
+        if !rule.cost.is_empty() {
+            let size = BusAction::extract_size(task.action);
+            task.remaining_cycles = rule
+                .cost
+                .iter()
+                .map(|a| timing.price(&build_action(a, addr, size)))
+                .sum();
+        }
+        let clear = rule.clear;
+        self.cache_state[idx].0 = next_state;
+
+        #[cfg(verbose)]
+        {
+            if state != next_state {
+                println!(
+                    "({:?}) {}: Bus update: State of tag {:x}: {} -> {}",
+                    self.core_id,
+                    self.table.name,
+                    self.addr_layout.tag(addr),
+                    self.table.states[state],
+                    self.table.states[next_state]
+                );
+            }
+            let task = bus.active_task().unwrap();
+            if task.action != old_task_action || task.remaining_cycles != old_task_time {
+                println!(
+                    "({:?}) {}: Bus update: Task changed: Action {:?} ({:?}) -> Action {:?} ({:?}).",
+                    self.core_id,
+                    self.table.name,
+                    old_task_action,
+                    old_task_time,
+                    task.action,
+                    task.remaining_cycles
+                );
+            }
+        }
+        if clear {
+            bus.clear();
+        }
+        true
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity)).find(|&i| self.cache_state[i].1 == tag)
+    }
+}
+
+impl Protocol for TableProtocol {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        if bus
+            .active_task()
+            .is_none_or(|task| task.issuer_id == self.core_id)
+        {
+            return None;
+        }
+        if self.apply_bus_rule(false, bus) {
+            bus.active_task().copied()
+        } else {
+            None
+        }
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        if bus
+            .active_task()
+            .is_some_and(|task| task.issuer_id == self.core_id)
+        {
+            self.apply_bus_rule(true, bus);
+        }
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == tag);
+        self.table.writeback.contains(&state)
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        assert!(state != self.table.invalid);
+        self.table.shared.contains(&state)
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        Some(self.cache_state[cache_idx].1)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].1 == tag);
+        self.cache_state[cache_idx] = (self.table.invalid, PLACEHOLDER_TAG)
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with table-driven protocols.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        (
+            self.table.permission(state),
+            self.table.states[state].clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::TimingConfig;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    const MESI: &str = include_str!("../../protocols/mesi.toml");
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    #[test]
+    fn parse_reference_table() {
+        let table = ProtocolTable::parse(MESI).unwrap();
+        assert_eq!(table.name, "MESI");
+        assert_eq!(table.states, vec!["M", "E", "S", "I"]);
+        assert_eq!(table.invalid, 3);
+        assert_eq!(table.writeback, vec![0]);
+        assert_eq!(table.shared, vec![2]);
+        assert_eq!(table.processor.len(), 9);
+        assert_eq!(table.snoop.len(), 7);
+        assert_eq!(table.after_snoop.len(), 1);
+        assert_eq!(table.snoop[0].cost, vec![BusAction::Flush(0, 0)]);
+        assert!(table.snoop[6].next.is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let header = "states = [A, I]\ninvalid = I\n";
+        assert!(ProtocolTable::parse("invalid = I").is_err());
+        assert!(ProtocolTable::parse(&format!("{}unknown = 1", header)).is_err());
+        assert!(ProtocolTable::parse(&format!("{}[bus]", header)).is_err());
+        assert!(ProtocolTable::parse(&format!("{}[processor]\nB read hit -> A", header)).is_err());
+        assert!(ProtocolTable::parse(&format!("{}[processor]\nA read hit A", header)).is_err());
+        assert!(ProtocolTable::parse(&format!(
+            "{}[processor]\n* read miss -> A BusRd block",
+            header
+        ))
+        .is_err());
+        let error = ProtocolTable::parse(&format!("{}[snoop]\nBusRdMem A -> B", header))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("line 4"));
+        assert!(ProtocolTable::parse(&format!("{}[snoop]\nBusRdMem A -> A", header)).is_ok());
+    }
+
+    #[test]
+    fn modified_to_shared() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let table = Rc::new(ProtocolTable::parse(MESI).unwrap());
+        let mut protocol = TableProtocol::new(
+            0,
+            Rc::clone(&table),
+            CACHE_SIZE,
+            BLOCK_SIZE,
+            ASSOCIATIVITY,
+            &layout,
+        );
+        let mut other_protocol =
+            TableProtocol::new(1, table, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+
+        let action = protocol.write(addr, None, store_idx, false, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, BLOCK_SIZE)));
+        assert_eq!(protocol.cache_state[store_idx], (0, layout.tag(addr)));
+        assert!(protocol.writeback_required(store_idx, layout.tag(addr)));
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        assert_eq!(other_protocol.cache_state[store_idx], (1, layout.tag(addr)));
+        bus.put_on(1, action.unwrap());
+
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdShared(addr, BLOCK_SIZE));
+        assert_eq!(
+            task.remaining_cycles,
+            TimingConfig::default().price(&BusAction::Flush(0, BLOCK_SIZE))
+        );
+        other_protocol.after_snoop(&mut bus);
+
+        assert_eq!(protocol.cache_state[store_idx], (2, layout.tag(addr)));
+        assert_eq!(other_protocol.cache_state[store_idx], (2, layout.tag(addr)));
+        assert!(protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unresolved processor event")]
+    fn unresolved_processor_event() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let table =
+            ProtocolTable::parse("states = [V, I]\ninvalid = I\n[processor]\nV read hit -> V")
+                .unwrap();
+        let mut protocol = TableProtocol::new(
+            0,
+            Rc::new(table),
+            CACHE_SIZE,
+            BLOCK_SIZE,
+            ASSOCIATIVITY,
+            &layout,
+        );
+        protocol.read(0x100, None, 0, false, &mut Bus::new());
+    }
+}
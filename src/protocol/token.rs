@@ -0,0 +1,572 @@
+use super::{Permission, ProcessorAction, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use crate::system::WORD_SIZE;
+use crate::utils::AddressLayout;
+use std::vec::Vec;
+
+const PLACEHOLDER_TAG: u32 = 0;
+
+/// Number of tokens of each block. Reading requires at least one token, writing all of them.
+pub const TOKENS_PER_BLOCK: usize = 16;
+
+/// Number of bus arbitrations a request may lose before it escalates to a persistent request.
+const STARVATION_THRESHOLD: usize = 4;
+
+/// Tokens of a cache line. Main memory implicitly holds all tokens that are not held by a cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TokenState {
+    pub tokens: usize,
+    /// The owner token obliges the holder to supply the data (and to write it back if dirty)
+    pub owner: bool,
+    pub dirty: bool,
+}
+
+/// TokenB token coherence protocol. Correctness is guaranteed by counting tokens instead of
+/// relying on the order of transactions: every block has a fixed number of tokens, a cache may
+/// only read a line while it holds at least one token and only write it while it holds all of
+/// them.
+///
+/// Transient requests are broadcast on the bus. On a read, the holder of the owner token (a cache
+/// or memory) supplies the data and a single token. Memory hands out all tokens if no cache holds
+/// any, which gives an exclusive line similar to MESI's E state. On a write, all holders send all
+/// their tokens. Evicting a line silently returns its tokens to memory; only dirty owners flush.
+///
+/// Requests that keep losing the bus arbitration escalate to a persistent request, which makes
+/// the arbiter reserve the bus for the starving core.
+pub struct Token {
+    core_id: usize,
+    cache_state: Vec<(TokenState, u32)>,
+    block_size: usize,
+    associativity: usize,
+    addr_layout: AddressLayout,
+    // id of the last bus transaction we responded to (snooping happens every cycle, the buses are
+    // snooped in issue order)
+    last_task_id: usize,
+    // starvation detection: last transaction seen while waiting for the bus, lost arbitrations
+    last_seen_task_id: usize,
+    lost_arbitrations: usize,
+}
+
+impl Token {
+    pub fn new(
+        core_id: usize,
+        cache_size: usize,
+        block_size: usize,
+        associativity: usize,
+        addr_layout: &AddressLayout,
+    ) -> Self {
+        Token {
+            core_id,
+            cache_state: vec![(TokenState::default(), PLACEHOLDER_TAG); cache_size / block_size],
+            block_size,
+            associativity,
+            addr_layout: *addr_layout,
+            last_task_id: 0,
+            last_seen_task_id: 0,
+            lost_arbitrations: 0,
+        }
+    }
+
+    fn processor_transition(
+        &mut self,
+        addr: u32,
+        flat_cache_idx: Option<usize>,
+        flat_store_idx: usize,
+        hit: bool,
+        action: ProcessorAction,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        let (current_state, current_tag) = &self.cache_state[flat_cache_idx.unwrap_or_default()];
+        assert!(!hit || *current_tag == self.addr_layout.tag(addr));
+        let tokens = if hit { current_state.tokens } else { 0 };
+        let bus_transaction = match (&action, tokens) {
+            // no tokens: fetch line (and tokens)
+            (ProcessorAction::Read, 0) => Some(BusAction::BusRdMem(addr, self.block_size)),
+            (ProcessorAction::Write, 0) => Some(BusAction::BusRdXMem(addr, self.block_size)),
+            (ProcessorAction::Read, _) => None,
+            (ProcessorAction::Write, TOKENS_PER_BLOCK) => None,
+            // line is valid, only the missing tokens are collected
+            (ProcessorAction::Write, _) => Some(BusAction::BusRdXMem(addr, WORD_SIZE)),
+        };
+        #[cfg(verbose)]
+        println!(
+            "({:?}) Token: {:?} with {:?} tokens, bus: {:?}",
+            self.core_id, action, tokens, bus_transaction
+        );
+
+        if bus_transaction.is_none() || bus.available(self.core_id) {
+            // Cache will issue bus action || no bus action required => already modify state.
+            // Tokens arrive with the responses to the request (after-snoop).
+            self.lost_arbitrations = 0;
+            let idx = flat_cache_idx.unwrap_or(flat_store_idx);
+            if !hit {
+                self.cache_state[idx] = (TokenState::default(), self.addr_layout.tag(addr));
+            } else if bus_transaction.is_none() && action == ProcessorAction::Write {
+                self.cache_state[idx].0.dirty = true;
+            }
+        } else {
+            // else: bus is busy, cache will execute read / write again next cycle. "busy waiting"
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Token: protocol could not update: bus is busy and required.",
+                self.core_id
+            );
+            self.detect_starvation(bus);
+        }
+        bus_transaction
+    }
+
+    /// Count the arbitrations lost by the waiting request and activate a persistent request once
+    /// it starves.
+    fn detect_starvation(&mut self, bus: &mut Bus) {
+        if let Some(task) = bus.active_task() {
+            if task.id != self.last_seen_task_id {
+                self.last_seen_task_id = task.id;
+                self.lost_arbitrations += 1;
+            }
+        }
+        // the first transaction seen was already running when we started waiting
+        if self.lost_arbitrations > STARVATION_THRESHOLD && bus.persistent_request().is_none() {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Token: starving after {:?} lost arbitrations",
+                self.core_id, self.lost_arbitrations
+            );
+            bus.activate_persistent_request(self.core_id);
+            self.lost_arbitrations = 0;
+        }
+    }
+
+    fn bus_snoop_transition(&mut self, bus: &mut Bus) -> Option<Task> {
+        let timing = bus.timing();
+        // no active tasks means no snooping
+        let task = bus.active_task()?;
+        if task.issuer_id == self.core_id || task.id <= self.last_task_id {
+            return None;
+        }
+        let addr = BusAction::extract_addr(task.action);
+        let tag = self.addr_layout.tag(addr);
+        // abort if task tag is not cached or we hold no tokens => we don't care
+        let idx = self.idx_of_addr(addr)?;
+        let (state, stored_tag) = &mut self.cache_state[idx];
+        assert!(*stored_tag == tag);
+        if state.tokens == 0 {
+            return None;
+        }
+        self.last_task_id = task.id;
+
+        // save for logging purposes:
+        let old_state = *state;
+        let old_task_action = task.action;
+        let old_task_time = task.remaining_cycles;
+
+        match task.action {
+            // Event: Someone else wants to read and we own the line
+            // => supply data and one token, or our last token (the owner token).
+            BusAction::BusRdMem(b_addr, c) | BusAction::BusRdShared(b_addr, c) if state.owner => {
+                debug_assert!(b_addr == addr);
+                task.action = BusAction::BusRdShared(b_addr, c);
+                task.tokens += 1;
+                if state.tokens > 1 {
+                    state.tokens -= 1;
+                    task.shared = true;
+                    task.remaining_cycles = timing.price(&task.action);
+                } else {
+                    // hand over the ownership, dirty data is written back on the way
+                    task.owner_token = true;
+                    task.remaining_cycles = if state.dirty {
+                        timing.price(&BusAction::Flush(0, self.block_size))
+                    } else {
+                        timing.price(&task.action)
+                    };
+                    *state = TokenState::default();
+                }
+            }
+
+            // Event: Someone else wants to read, we only hold plain tokens
+            // => keep them, but signal that memory does not hold all tokens
+            BusAction::BusRdMem(_, _) | BusAction::BusRdShared(_, _) => task.shared = true,
+
+            // Event: Someone else wants to write
+            // => send all tokens, the owner also supplies the data
+            BusAction::BusRdXMem(b_addr, c) | BusAction::BusRdXShared(b_addr, c) => {
+                debug_assert!(b_addr == addr);
+                task.tokens += state.tokens;
+                if state.owner {
+                    task.owner_token = true;
+                    task.action = BusAction::BusRdXShared(b_addr, c);
+                    task.remaining_cycles = timing.price(&task.action);
+                }
+                *state = TokenState::default();
+            }
+
+            // Ignore bus events that don't change anything
+            _ => return None,
+        }
+        if *state != old_state {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Token: Snooping update: State of tag {:x}: {:?} -> {:?}",
+                self.core_id, tag, old_state, state
+            );
+        }
+        if task.action != old_task_action || task.remaining_cycles != old_task_time {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Token: Snooping update: Task changed: Action {:?} ({:?}) -> Action {:?} ({:?}).",
+                self.core_id,
+                old_task_action,
+                old_task_time,
+                task.action,
+                task.remaining_cycles
+            );
+        }
+        Some(*task)
+    }
+
+    fn bus_after_snoop_transition(&mut self, bus: &mut Bus) {
+        // no active tasks means no after-snoop
+        let task = match bus.active_task() {
+            Some(t) => t,
+            None => return,
+        };
+        // after-snoop only regards the responses to our own task (once)
+        if task.issuer_id != self.core_id || task.id <= self.last_task_id {
+            return;
+        }
+        self.last_task_id = task.id;
+        let addr = BusAction::extract_addr(task.action);
+        let idx = match self.idx_of_addr(addr) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let state = &mut self.cache_state[idx].0;
+        let old_state = *state;
+
+        match task.action {
+            // Event: A cache supplied the line
+            BusAction::BusRdShared(_, _) => {
+                debug_assert!(task.tokens > 0);
+                *state = TokenState {
+                    tokens: task.tokens,
+                    owner: task.owner_token,
+                    dirty: false,
+                };
+            }
+            // Event: Memory is the owner and supplied the line
+            // => all tokens if no cache holds any, otherwise the owner token
+            BusAction::BusRdMem(_, _) => {
+                debug_assert!(task.tokens == 0);
+                *state = TokenState {
+                    tokens: if task.shared { 1 } else { TOKENS_PER_BLOCK },
+                    owner: true,
+                    dirty: false,
+                };
+            }
+            // Event: We collected the tokens of all caches, memory sent the remaining ones
+            BusAction::BusRdXMem(_, _) | BusAction::BusRdXShared(_, _) => {
+                assert!(state.tokens + task.tokens <= TOKENS_PER_BLOCK);
+                *state = TokenState {
+                    tokens: TOKENS_PER_BLOCK,
+                    owner: true,
+                    dirty: true,
+                };
+            }
+            _ => (),
+        }
+        if *state != old_state {
+            #[cfg(verbose)]
+            println!(
+                "({:?}) Token: After-Snoop update: {:?} -> {:?}",
+                self.core_id, old_state, state
+            );
+        }
+    }
+
+    fn idx_of_addr(&self, addr: u32) -> Option<usize> {
+        let start_idx = self.addr_layout.index(addr) * self.associativity;
+        let tag = self.addr_layout.tag(addr);
+        (start_idx..(start_idx + self.associativity)).find(|&i| self.cache_state[i].1 == tag)
+    }
+}
+
+impl Protocol for Token {
+    fn read(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Read, bus)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.processor_transition(addr, cache_idx, store_idx, hit, ProcessorAction::Write, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        self.bus_snoop_transition(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.bus_after_snoop_transition(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == tag);
+        state.owner && state.dirty
+    }
+
+    fn is_shared(&self, mut cache_idx: usize, addr: u32) -> bool {
+        if cache_idx == usize::MAX {
+            cache_idx = self.idx_of_addr(addr).unwrap();
+        }
+        let (state, stored_tag) = self.cache_state[cache_idx];
+        assert!(stored_tag == self.addr_layout.tag(addr));
+        assert!(state.tokens > 0);
+        state.tokens < TOKENS_PER_BLOCK
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        let (state, tag) = self.cache_state[cache_idx];
+        assert!(state.tokens <= TOKENS_PER_BLOCK);
+        assert!(!state.owner || state.tokens > 0);
+        assert!(!state.dirty || state.owner);
+        Some(tag)
+    }
+
+    #[cfg(sanity_check)]
+    fn tokens(&self, addr: u32) -> Option<(usize, bool)> {
+        let (state, _) = self.cache_state[self.idx_of_addr(addr)?];
+        Some((state.tokens, state.owner))
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        debug_assert!(self.cache_state[cache_idx].1 == tag);
+        // tokens are returned to memory
+        self.cache_state[cache_idx] = (TokenState::default(), PLACEHOLDER_TAG)
+    }
+
+    fn read_broadcast(&mut self, _: &mut Bus) {
+        panic!("Read broadcast optimization cannot be used with the token protocol.")
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let state = match self.idx_of_addr(addr) {
+            Some(idx) => self.cache_state[idx].0,
+            None => return (Permission::Invalid, String::from("-")),
+        };
+        let permission = match state.tokens {
+            0 => Permission::Invalid,
+            TOKENS_PER_BLOCK => Permission::Write,
+            _ => Permission::Read,
+        };
+        let name = format!(
+            "{}{}{}",
+            state.tokens,
+            if state.owner { "O" } else { "" },
+            if state.dirty { "D" } else { "" }
+        );
+        (permission, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::TimingConfig;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+    const ASSOCIATIVITY: usize = 1;
+
+    fn addr_layout(cache_size: usize, block_size: usize, associativity: usize) -> AddressLayout {
+        let set_size = associativity * block_size;
+        let num_sets = cache_size / set_size;
+
+        // as integer logs are currently unstable, we have to be ugly
+        let offset_length = ((block_size / 4) as f64).log2() as usize;
+        let index_length = (num_sets as f64).log2() as usize;
+        let tag_length = 32 - (offset_length + index_length);
+
+        AddressLayout::new(
+            offset_length,
+            index_length,
+            tag_length,
+            set_size,
+            block_size,
+        )
+    }
+
+    fn state(tokens: usize, owner: bool, dirty: bool) -> TokenState {
+        TokenState {
+            tokens,
+            owner,
+            dirty,
+        }
+    }
+
+    #[test]
+    fn read_from_memory_gets_all_tokens() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Token::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdMem(addr, BLOCK_SIZE)));
+        bus.put_on(0, action.unwrap());
+        protocol.after_snoop(&mut bus);
+        assert_eq!(
+            protocol.cache_state[store_idx],
+            (state(TOKENS_PER_BLOCK, true, false), layout.tag(addr))
+        );
+        while bus.occupied() {
+            bus.update();
+        }
+
+        // all tokens => silent write
+        let action = protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert!(action.is_none());
+        assert_eq!(
+            protocol.cache_state[store_idx].0,
+            state(TOKENS_PER_BLOCK, true, true)
+        );
+        assert!(!protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    fn owner_supplies_one_token() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Token::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Token::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+        protocol.cache_state[store_idx] = (state(TOKENS_PER_BLOCK, true, true), layout.tag(addr));
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.action, BusAction::BusRdShared(addr, BLOCK_SIZE));
+        assert_eq!(
+            task.remaining_cycles,
+            TimingConfig::default().price(&task.action)
+        );
+        // snooping happens each cycle, tokens must only be sent once
+        assert!(protocol.snoop(&mut bus).is_none());
+        other_protocol.after_snoop(&mut bus);
+        other_protocol.after_snoop(&mut bus);
+
+        assert_eq!(
+            protocol.cache_state[store_idx].0,
+            state(TOKENS_PER_BLOCK - 1, true, true)
+        );
+        assert_eq!(
+            other_protocol.cache_state[store_idx].0,
+            state(1, false, false)
+        );
+        assert!(protocol.is_shared(store_idx, addr));
+        assert!(other_protocol.is_shared(store_idx, addr));
+    }
+
+    #[test]
+    fn memory_supplies_owner_token_to_sharers() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Token::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Token::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+        // plain tokens, memory is the owner
+        protocol.cache_state[store_idx] = (state(2, false, false), layout.tag(addr));
+
+        let action = other_protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(1, action.unwrap());
+        assert!(protocol.snoop(&mut bus).unwrap().shared);
+        other_protocol.after_snoop(&mut bus);
+
+        assert_eq!(bus.active_task().unwrap().remaining_cycles, 100);
+        assert_eq!(protocol.cache_state[store_idx].0, state(2, false, false));
+        assert_eq!(
+            other_protocol.cache_state[store_idx].0,
+            state(1, true, false)
+        );
+    }
+
+    #[test]
+    fn write_collects_all_tokens() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Token::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut other_protocol = Token::new(1, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+        protocol.cache_state[store_idx] = (state(3, true, true), layout.tag(addr));
+        other_protocol.cache_state[store_idx] = (state(1, false, false), layout.tag(addr));
+
+        // valid line: only the tokens are requested
+        let action = other_protocol.write(addr, Some(store_idx), store_idx, true, &mut bus);
+        assert_eq!(action, Some(BusAction::BusRdXMem(addr, WORD_SIZE)));
+        bus.put_on(1, action.unwrap());
+        let task = protocol.snoop(&mut bus).unwrap();
+        assert_eq!(task.tokens, 3);
+        assert!(task.owner_token);
+        assert_eq!(task.action, BusAction::BusRdXShared(addr, WORD_SIZE));
+        other_protocol.after_snoop(&mut bus);
+
+        assert_eq!(protocol.cache_state[store_idx].0, TokenState::default());
+        assert_eq!(
+            other_protocol.cache_state[store_idx].0,
+            state(TOKENS_PER_BLOCK, true, true)
+        );
+        assert!(other_protocol.writeback_required(store_idx, layout.tag(addr)));
+    }
+
+    #[test]
+    fn starvation_activates_persistent_request() {
+        let layout = addr_layout(CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY);
+        let mut protocol = Token::new(0, CACHE_SIZE, BLOCK_SIZE, ASSOCIATIVITY, &layout);
+        let mut bus = Bus::new();
+
+        let addr = 0x100;
+        let store_idx = layout.nested_to_flat(layout.index(addr), 0);
+
+        // other cores win the bus arbitration again and again
+        for _ in 0..STARVATION_THRESHOLD {
+            bus.put_on(1, BusAction::BusRdMem(0x200, BLOCK_SIZE));
+            protocol.read(addr, None, store_idx, false, &mut bus);
+            protocol.read(addr, None, store_idx, false, &mut bus);
+            bus.clear();
+            assert!(bus.available(1));
+        }
+        assert_eq!(bus.persistent_request(), None);
+        bus.put_on(1, BusAction::BusRdMem(0x200, BLOCK_SIZE));
+        protocol.read(addr, None, store_idx, false, &mut bus);
+        assert_eq!(bus.persistent_request(), Some(0));
+        bus.clear();
+
+        // the bus is reserved for the starving core
+        assert!(!bus.available(1));
+        assert!(bus.available(0));
+        let action = protocol.read(addr, None, store_idx, false, &mut bus);
+        bus.put_on(0, action.unwrap());
+        assert_eq!(bus.persistent_request(), None);
+    }
+}
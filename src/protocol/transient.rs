@@ -0,0 +1,220 @@
+use super::{Permission, Protocol};
+use crate::bus::{Bus, BusAction, Task};
+use std::collections::HashMap;
+
+/// Line whose transaction waits for its response
+struct Waiting {
+    /// name of the stable state the line left
+    from: String,
+    /// the response carries the data of the block (otherwise it only acknowledges the request)
+    data: bool,
+}
+
+/// Transient states on top of the stable states of a protocol. A line whose transaction waits for
+/// its response is named after the stable state it left and the one it enters, e.g. IS_D or IM_D
+/// for a read or write miss waiting for the data of the block and SM_A for an upgrade waiting for
+/// its acknowledgement. Transient lines grant no access until the response arrived. Other caches
+/// keep serving their own hits of the block, but their requests wait for the response, so snooped
+/// requests never hit a transient line.
+pub struct Transient {
+    core_id: usize,
+    protocol: Box<dyn Protocol>,
+    words_per_block: u32,
+    // stable state of the line before the last access
+    from: String,
+    waiting: HashMap<u32, Waiting>,
+}
+
+impl Transient {
+    pub fn new(core_id: usize, protocol: Box<dyn Protocol>, words_per_block: usize) -> Self {
+        Transient {
+            core_id,
+            protocol,
+            words_per_block: words_per_block as u32,
+            from: String::new(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// The cache put a transaction for the block containing addr on the bus, the line waits for
+    /// its response.
+    pub fn await_response(&mut self, addr: u32, data: bool) {
+        let from = std::mem::take(&mut self.from);
+        self.waiting
+            .insert(addr / self.words_per_block, Waiting { from, data });
+    }
+
+    /// The response for the block containing addr arrived, the line enters its stable state.
+    pub fn response(&mut self, addr: u32) {
+        self.waiting.remove(&(addr / self.words_per_block));
+    }
+
+    /// true if the line holding addr waits for the response of its transaction
+    pub fn is_transient(&self, addr: u32) -> bool {
+        self.waiting.contains_key(&(addr / self.words_per_block))
+    }
+
+    fn stable_name(&self, addr: u32) -> String {
+        match self.protocol.line_state(addr) {
+            (_, name) if name == "-" => String::from("I"),
+            (_, name) => name,
+        }
+    }
+}
+
+impl Protocol for Transient {
+    fn read(
+        &mut self,
+        tag: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.from = self.stable_name(tag);
+        self.protocol.read(tag, cache_idx, store_idx, hit, bus)
+    }
+
+    fn write(
+        &mut self,
+        tag: u32,
+        cache_idx: Option<usize>,
+        store_idx: usize,
+        hit: bool,
+        bus: &mut Bus,
+    ) -> Option<BusAction> {
+        self.from = self.stable_name(tag);
+        self.protocol.write(tag, cache_idx, store_idx, hit, bus)
+    }
+
+    fn snoop(&mut self, bus: &mut Bus) -> Option<Task> {
+        if let Some(task) = bus.active_task() {
+            assert!(
+                task.issuer_id == self.core_id
+                    || matches!(task.action, BusAction::Flush(_, _))
+                    || !self.is_transient(BusAction::extract_addr(task.action)),
+                "({:?}) Snooped {:?} of core {:?} hits a transient line.",
+                self.core_id,
+                task.action,
+                task.issuer_id
+            );
+        }
+        self.protocol.snoop(bus)
+    }
+
+    fn after_snoop(&mut self, bus: &mut Bus) {
+        self.protocol.after_snoop(bus)
+    }
+
+    fn writeback_required(&self, cache_idx: usize, tag: u32) -> bool {
+        self.protocol.writeback_required(cache_idx, tag)
+    }
+
+    fn invalidate(&mut self, cache_idx: usize, tag: u32) {
+        self.protocol.invalidate(cache_idx, tag)
+    }
+
+    fn is_shared(&self, cache_idx: usize, addr: u32) -> bool {
+        self.protocol.is_shared(cache_idx, addr)
+    }
+
+    #[cfg(sanity_check)]
+    fn sanity_check(&self, cache_idx: usize) -> Option<u32> {
+        self.protocol.sanity_check(cache_idx)
+    }
+
+    #[cfg(sanity_check)]
+    fn tokens(&self, addr: u32) -> Option<(usize, bool)> {
+        self.protocol.tokens(addr)
+    }
+
+    fn read_broadcast(&mut self, bus: &mut Bus) {
+        self.protocol.read_broadcast(bus)
+    }
+
+    fn line_state(&self, addr: u32) -> (Permission, String) {
+        let (permission, name) = self.protocol.line_state(addr);
+        match self.waiting.get(&(addr / self.words_per_block)) {
+            Some(Waiting { from, data }) => (
+                Permission::Invalid,
+                format!("{}{}_{}", from, name, if *data { "D" } else { "A" }),
+            ),
+            None => (permission, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::msi::Msi;
+    use crate::utils::AddressLayout;
+
+    const CACHE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = 4;
+
+    fn protocol(core_id: usize) -> Transient {
+        // direct mapped: 4 sets of 1 word blocks
+        let layout = AddressLayout::new(0, 2, 30, BLOCK_SIZE, BLOCK_SIZE);
+        let msi = Msi::new(core_id, CACHE_SIZE, BLOCK_SIZE, 1, &layout);
+        Transient::new(core_id, Box::new(msi), 1)
+    }
+
+    #[test]
+    fn misses_wait_for_their_data() {
+        let mut protocol = protocol(0);
+        let mut bus = Bus::new();
+        let addr = 0x100;
+
+        assert!(protocol.read(addr, None, 0, false, &mut bus).is_some());
+        protocol.await_response(addr, true);
+        assert!(protocol.is_transient(addr));
+        assert_eq!(
+            protocol.line_state(addr),
+            (Permission::Invalid, String::from("IS_D"))
+        );
+
+        protocol.response(addr);
+        assert!(!protocol.is_transient(addr));
+        assert_eq!(
+            protocol.line_state(addr),
+            (Permission::Read, String::from("S"))
+        );
+    }
+
+    #[test]
+    fn upgrades_wait_for_their_acknowledgement() {
+        let mut protocol = protocol(0);
+        let mut bus = Bus::new();
+        let addr = 0x100;
+
+        protocol.read(addr, None, 0, false, &mut bus);
+        while bus.occupied() {
+            bus.update();
+        }
+        assert!(protocol.write(addr, Some(0), 0, true, &mut bus).is_some());
+        protocol.await_response(addr, false);
+        assert_eq!(
+            protocol.line_state(addr),
+            (Permission::Invalid, String::from("SM_A"))
+        );
+
+        protocol.response(addr);
+        assert_eq!(
+            protocol.line_state(addr),
+            (Permission::Write, String::from("M"))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "hits a transient line")]
+    fn snooped_requests_never_hit_transient_lines() {
+        let mut protocol = protocol(0);
+        let mut bus = Bus::new();
+        let addr = 0x100;
+
+        protocol.await_response(addr, true);
+        bus.put_on(1, BusAction::BusRdMem(addr, BLOCK_SIZE));
+        protocol.snoop(&mut bus);
+    }
+}
@@ -39,14 +39,16 @@ impl Record {
 pub struct RecordStream {
     pub file_name: String,
     pub line_count: usize,
-    _zip_archive: Box<ZipArchive<File>>,
-    lines: Lines<BufReader<ZipFile<'static>>>,
+    // None while the entry is reopened. Declared before the archive it borrows from, so that it
+    // is dropped first.
+    lines: Option<Lines<BufReader<ZipFile<'static>>>>,
+    zip_archive: Box<ZipArchive<File>>,
 }
 
 impl Iterator for RecordStream {
     type Item = Record;
     fn next(&mut self) -> Option<Record> {
-        let next = self.lines.next()?;
+        let next = self.lines.as_mut()?.next()?;
         if let Ok(line) = next {
             let mut parts = line.split(' ');
             let label = parts.next().unwrap();
@@ -74,15 +76,29 @@ impl RecordStream {
             0
         };
 
-        let zip_file = unsafe {
-            std::mem::transmute::<_, ZipFile<'static>>(archive.by_name(&file_name).unwrap())
-        };
-
-        RecordStream {
+        let mut stream = RecordStream {
             file_name,
-            _zip_archive: archive,
+            zip_archive: archive,
             line_count,
-            lines: BufReader::new(zip_file).lines(),
-        }
+            lines: None,
+        };
+        stream.rewind();
+        stream
+    }
+
+    /// Restart at the first record, e.g. after a first pass over the trace.
+    pub fn rewind(&mut self) {
+        // the old reader borrows the archive
+        self.lines = None;
+        // SAFETY: the entry actually borrows the archive. The archive is boxed, so it stays in
+        // place when the stream moves, and it is never replaced. The previous entry was dropped
+        // above and `lines` is dropped before the archive (field order), so no entry outlives
+        // it. The 'static lifetime never escapes the stream.
+        let zip_file = unsafe {
+            std::mem::transmute::<_, ZipFile<'static>>(
+                self.zip_archive.by_name(&self.file_name).unwrap(),
+            )
+        };
+        self.lines = Some(BufReader::new(zip_file).lines());
     }
 }
@@ -0,0 +1,721 @@
+use crate::record::{Label, RecordStream};
+use clap::ArgEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+
+/// Chooses the block of a cache set that is replaced next. Ways that were never filled or that
+/// were demoted are preferred as victims.
+pub trait ReplacementPolicy {
+    /// A cached block was accessed.
+    fn touch(&mut self, set_idx: usize, block_idx: usize);
+
+    /// A new block (containing addr) was placed into the way, on demand or prefetched.
+    fn insert(&mut self, set_idx: usize, block_idx: usize, addr: u32);
+
+    /// Make the block the preferred victim of its set (e.g. it was invalidated).
+    fn demote(&mut self, set_idx: usize, block_idx: usize);
+
+    /// Way of the set that is replaced next. Repeated calls return the same way until the set is
+    /// accessed again.
+    fn victim(&self, set_idx: usize) -> usize;
+
+    /// The processor issued an access to addr (in program order, hit or miss).
+    fn access(&mut self, _addr: u32) {}
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum, PartialEq, Eq, Default)]
+pub enum ReplacementKind {
+    #[default]
+    Lru,
+    Fifo,
+    /// Uniformly random victim (seeded)
+    Random,
+    /// Tree-based pseudo-LRU
+    TreePlru,
+    /// Not recently used (one reference bit per block)
+    Nru,
+    /// Least frequently used
+    Lfu,
+    /// Static re-reference interval prediction
+    Srrip,
+    /// Bimodal re-reference interval prediction
+    Brrip,
+    /// Dynamic re-reference interval prediction (set dueling between SRRIP and BRRIP)
+    Drrip,
+    /// Offline optimal (Belady's MIN), replaces the block that is used again furthest in the
+    /// future. Requires a first pass over the traces.
+    Belady,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplacementConfig {
+    pub kind: ReplacementKind,
+    /// Seed of the random policy, combined with the core id
+    pub seed: u64,
+}
+
+pub struct ReplacementBuilder;
+
+impl ReplacementBuilder {
+    pub fn create(
+        config: ReplacementConfig,
+        core_id: usize,
+        num_sets: usize,
+        associativity: usize,
+        future: Option<FutureAccesses>,
+    ) -> Box<dyn ReplacementPolicy> {
+        match config.kind {
+            ReplacementKind::Lru => Box::new(Lru::new(num_sets, associativity)),
+            ReplacementKind::Fifo => Box::new(Fifo::new(num_sets, associativity)),
+            ReplacementKind::Random => Box::new(Random::new(
+                num_sets,
+                associativity,
+                config.seed.wrapping_add(core_id as u64),
+            )),
+            ReplacementKind::TreePlru => Box::new(TreePlru::new(num_sets, associativity)),
+            ReplacementKind::Nru => Box::new(Nru::new(num_sets, associativity)),
+            ReplacementKind::Lfu => Box::new(Lfu::new(num_sets, associativity)),
+            ReplacementKind::Srrip => {
+                Box::new(Rrip::new(num_sets, associativity, RripInsertion::Static))
+            }
+            ReplacementKind::Brrip => {
+                Box::new(Rrip::new(num_sets, associativity, RripInsertion::Bimodal))
+            }
+            ReplacementKind::Drrip => {
+                Box::new(Rrip::new(num_sets, associativity, RripInsertion::Dynamic))
+            }
+            ReplacementKind::Belady => Box::new(Belady::new(
+                num_sets,
+                associativity,
+                future.expect("Belady replacement requires the future accesses of the trace."),
+            )),
+        }
+    }
+}
+
+/// Index of the first minimum (first way on ties).
+fn min_idx(values: &[usize]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .min_by(|(_, v1), (_, v2)| v1.cmp(v2))
+        .unwrap()
+        .0
+}
+
+pub struct Lru {
+    storage: Vec<Vec<usize>>,
+    cnt: usize,
+}
+
+impl Lru {
+    pub fn new(num_sets: usize, associativity: usize) -> Lru {
+        Lru {
+            storage: vec![vec![0; associativity]; num_sets],
+            cnt: 1,
+        }
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn touch(&mut self, set_idx: usize, block_idx: usize) {
+        self.storage[set_idx][block_idx] = self.cnt;
+        self.cnt += 1;
+    }
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.touch(set_idx, block_idx);
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.storage[set_idx][block_idx] = 0;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        min_idx(&self.storage[set_idx])
+    }
+}
+
+/// Replaces the block that was inserted first, hits do not matter.
+pub struct Fifo {
+    inserted: Vec<Vec<usize>>,
+    cnt: usize,
+}
+
+impl Fifo {
+    pub fn new(num_sets: usize, associativity: usize) -> Fifo {
+        Fifo {
+            inserted: vec![vec![0; associativity]; num_sets],
+            cnt: 1,
+        }
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn touch(&mut self, _set_idx: usize, _block_idx: usize) {}
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.inserted[set_idx][block_idx] = self.cnt;
+        self.cnt += 1;
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.inserted[set_idx][block_idx] = 0;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        min_idx(&self.inserted[set_idx])
+    }
+}
+
+/// The victim of each set is drawn when a block is inserted into it.
+pub struct Random {
+    next: Vec<usize>,
+    empty: Vec<Vec<bool>>,
+    associativity: usize,
+    rng: StdRng,
+}
+
+impl Random {
+    pub fn new(num_sets: usize, associativity: usize, seed: u64) -> Random {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Random {
+            next: (0..num_sets)
+                .map(|_| rng.gen_range(0..associativity))
+                .collect(),
+            empty: vec![vec![true; associativity]; num_sets],
+            associativity,
+            rng,
+        }
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn touch(&mut self, _set_idx: usize, _block_idx: usize) {}
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.empty[set_idx][block_idx] = false;
+        self.next[set_idx] = self.rng.gen_range(0..self.associativity);
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.empty[set_idx][block_idx] = true;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        self.empty[set_idx]
+            .iter()
+            .position(|e| *e)
+            .unwrap_or(self.next[set_idx])
+    }
+}
+
+/// Binary tree over the ways of a set (associativity has to be a power of 2). Each node points to
+/// the half that was used less recently, accesses flip the nodes on their path away from them.
+pub struct TreePlru {
+    // nodes of each set in heap order (children of node i: 2i + 1, 2i + 2), true = right
+    trees: Vec<Vec<bool>>,
+    associativity: usize,
+}
+
+impl TreePlru {
+    pub fn new(num_sets: usize, associativity: usize) -> TreePlru {
+        assert!(
+            associativity.is_power_of_two(),
+            "Tree-PLRU requires a power of 2 associativity."
+        );
+        TreePlru {
+            trees: vec![vec![false; associativity - 1]; num_sets],
+            associativity,
+        }
+    }
+
+    /// Let the nodes on the path to the block point towards it (or away from it).
+    fn point(&mut self, set_idx: usize, block_idx: usize, towards: bool) {
+        let tree = &mut self.trees[set_idx];
+        let (mut node, mut lo, mut width) = (0, 0, self.associativity);
+        while width > 1 {
+            width /= 2;
+            let right = block_idx >= lo + width;
+            tree[node] = right == towards;
+            if right {
+                lo += width;
+                node = 2 * node + 2;
+            } else {
+                node = 2 * node + 1;
+            }
+        }
+    }
+}
+
+impl ReplacementPolicy for TreePlru {
+    fn touch(&mut self, set_idx: usize, block_idx: usize) {
+        self.point(set_idx, block_idx, false);
+    }
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.point(set_idx, block_idx, false);
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.point(set_idx, block_idx, true);
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        let tree = &self.trees[set_idx];
+        let (mut node, mut lo, mut width) = (0, 0, self.associativity);
+        while width > 1 {
+            width /= 2;
+            if tree[node] {
+                lo += width;
+                node = 2 * node + 2;
+            } else {
+                node = 2 * node + 1;
+            }
+        }
+        lo
+    }
+}
+
+/// Replaces the first block that was not referenced since the reference bits were last cleared.
+pub struct Nru {
+    referenced: Vec<Vec<bool>>,
+}
+
+impl Nru {
+    pub fn new(num_sets: usize, associativity: usize) -> Nru {
+        Nru {
+            referenced: vec![vec![false; associativity]; num_sets],
+        }
+    }
+}
+
+impl ReplacementPolicy for Nru {
+    fn touch(&mut self, set_idx: usize, block_idx: usize) {
+        let set = &mut self.referenced[set_idx];
+        set[block_idx] = true;
+        // keep one candidate
+        if set.iter().all(|r| *r) {
+            set.iter_mut().for_each(|r| *r = false);
+            set[block_idx] = true;
+        }
+    }
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.touch(set_idx, block_idx);
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.referenced[set_idx][block_idx] = false;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        self.referenced[set_idx]
+            .iter()
+            .position(|r| !*r)
+            .unwrap_or(0)
+    }
+}
+
+/// Replaces the block with the fewest accesses since it was inserted.
+pub struct Lfu {
+    counts: Vec<Vec<usize>>,
+}
+
+impl Lfu {
+    pub fn new(num_sets: usize, associativity: usize) -> Lfu {
+        Lfu {
+            counts: vec![vec![0; associativity]; num_sets],
+        }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn touch(&mut self, set_idx: usize, block_idx: usize) {
+        self.counts[set_idx][block_idx] += 1;
+    }
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        self.counts[set_idx][block_idx] = 1;
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.counts[set_idx][block_idx] = 0;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        min_idx(&self.counts[set_idx])
+    }
+}
+
+// 2 bit re-reference prediction values
+const RRPV_MAX: u8 = 3;
+const RRPV_LONG: u8 = RRPV_MAX - 1;
+// BRRIP inserts with a long instead of a distant re-reference interval every BIP_PERIOD insertions
+const BIP_PERIOD: usize = 32;
+// DRRIP: one leader set of each policy per LEADER_PERIOD sets, saturating 10 bit selector
+const LEADER_PERIOD: usize = 32;
+const PSEL_MAX: usize = 1023;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RripInsertion {
+    Static,
+    Bimodal,
+    Dynamic,
+}
+
+/// Re-reference interval prediction (Jaleel et al., ISCA 2010). Hits predict a near re-reference,
+/// the victim is the first block with a distant one; the set is aged until such a block exists.
+pub struct Rrip {
+    rrpv: Vec<Vec<u8>>,
+    insertion: RripInsertion,
+    num_insertions: usize,
+    // DRRIP policy selector, counts up on misses in SRRIP leader sets
+    psel: usize,
+}
+
+impl Rrip {
+    fn new(num_sets: usize, associativity: usize, insertion: RripInsertion) -> Rrip {
+        Rrip {
+            rrpv: vec![vec![RRPV_MAX; associativity]; num_sets],
+            insertion,
+            num_insertions: 0,
+            psel: PSEL_MAX / 2,
+        }
+    }
+
+    fn bimodal_rrpv(&self) -> u8 {
+        if self.num_insertions.is_multiple_of(BIP_PERIOD) {
+            RRPV_LONG
+        } else {
+            RRPV_MAX
+        }
+    }
+}
+
+impl ReplacementPolicy for Rrip {
+    fn touch(&mut self, set_idx: usize, block_idx: usize) {
+        self.rrpv[set_idx][block_idx] = 0;
+    }
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, _addr: u32) {
+        // age the set until the replaced block had a distant re-reference interval
+        let set = &mut self.rrpv[set_idx];
+        let age = RRPV_MAX - set[block_idx];
+        set.iter_mut().for_each(|v| *v = (*v + age).min(RRPV_MAX));
+
+        let bimodal = match self.insertion {
+            RripInsertion::Static => false,
+            RripInsertion::Bimodal => true,
+            RripInsertion::Dynamic => match set_idx % LEADER_PERIOD {
+                0 => {
+                    self.psel = (self.psel + 1).min(PSEL_MAX);
+                    false
+                }
+                1 => {
+                    self.psel = self.psel.saturating_sub(1);
+                    true
+                }
+                // SRRIP missed more often in its leader sets
+                _ => self.psel > PSEL_MAX / 2,
+            },
+        };
+        self.rrpv[set_idx][block_idx] = if bimodal {
+            self.bimodal_rrpv()
+        } else {
+            RRPV_LONG
+        };
+        self.num_insertions += 1;
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.rrpv[set_idx][block_idx] = RRPV_MAX;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        let set = &self.rrpv[set_idx];
+        let max = *set.iter().max().unwrap();
+        set.iter().position(|v| *v == max).unwrap()
+    }
+}
+
+/// Positions (in the sequence of memory accesses) at which each block will be accessed.
+pub struct FutureAccesses {
+    accesses: HashMap<u32, VecDeque<usize>>,
+    words_per_block: usize,
+}
+
+impl FutureAccesses {
+    pub fn new<I: Iterator<Item = (Label, u32)>>(records: I, words_per_block: usize) -> Self {
+        let mut accesses: HashMap<u32, VecDeque<usize>> = HashMap::new();
+        let memory_accesses = records.filter(|(label, _)| *label != Label::Other);
+        for (position, (_, addr)) in memory_accesses.enumerate() {
+            accesses
+                .entry(addr / words_per_block as u32)
+                .or_default()
+                .push_back(position);
+        }
+        FutureAccesses {
+            accesses,
+            words_per_block,
+        }
+    }
+
+    /// Scan the whole trace and rewind it for the simulation.
+    pub fn scan(records: &mut RecordStream, words_per_block: usize) -> Self {
+        let future = FutureAccesses::new(
+            records.by_ref().map(|record| (record.label, record.value)),
+            words_per_block,
+        );
+        records.rewind();
+        future
+    }
+
+    fn block(&self, addr: u32) -> u32 {
+        addr / self.words_per_block as u32
+    }
+
+    /// Consume the current access of the block.
+    fn pop(&mut self, block: u32) {
+        if let Some(positions) = self.accesses.get_mut(&block) {
+            positions.pop_front();
+        }
+    }
+
+    /// Position of the next access to the block, None if it is not accessed again.
+    fn next_use(&self, block: u32) -> Option<usize> {
+        self.accesses.get(&block)?.front().copied()
+    }
+}
+
+/// Belady's MIN: evicts the block whose next use lies furthest in the future (or never comes).
+/// Only the core's own accesses are known, invalidations by other cores are not anticipated.
+pub struct Belady {
+    blocks: Vec<Vec<Option<u32>>>,
+    future: FutureAccesses,
+}
+
+impl Belady {
+    pub fn new(num_sets: usize, associativity: usize, future: FutureAccesses) -> Belady {
+        Belady {
+            blocks: vec![vec![None; associativity]; num_sets],
+            future,
+        }
+    }
+}
+
+impl ReplacementPolicy for Belady {
+    fn touch(&mut self, _set_idx: usize, _block_idx: usize) {}
+
+    fn insert(&mut self, set_idx: usize, block_idx: usize, addr: u32) {
+        self.blocks[set_idx][block_idx] = Some(self.future.block(addr));
+    }
+
+    fn demote(&mut self, set_idx: usize, block_idx: usize) {
+        self.blocks[set_idx][block_idx] = None;
+    }
+
+    fn victim(&self, set_idx: usize) -> usize {
+        let set = &self.blocks[set_idx];
+        if let Some(empty) = set.iter().position(|b| b.is_none()) {
+            return empty;
+        }
+        let next_uses: Vec<usize> = set
+            .iter()
+            .map(|b| self.future.next_use(b.unwrap()).unwrap_or(usize::MAX))
+            .collect();
+        let furthest = *next_uses.iter().max().unwrap();
+        next_uses.iter().position(|n| *n == furthest).unwrap()
+    }
+
+    fn access(&mut self, addr: u32) {
+        let block = self.future.block(addr);
+        self.future.pop(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fill a set of 4 ways and access the blocks in order, returns the replaced ways.
+    fn victims(policy: &mut dyn ReplacementPolicy, accesses: &[usize]) -> Vec<usize> {
+        let mut ways: Vec<Option<usize>> = vec![None; 4];
+        let mut replaced = Vec::new();
+        for block in accesses {
+            if let Some(way) = ways.iter().position(|b| *b == Some(*block)) {
+                policy.touch(0, way);
+                continue;
+            }
+            let way = ways.iter().position(|b| b.is_none()).unwrap_or_else(|| {
+                let way = policy.victim(0);
+                replaced.push(way);
+                way
+            });
+            ways[way] = Some(*block);
+            policy.insert(0, way, *block as u32 * 4);
+        }
+        replaced
+    }
+
+    fn belady(accesses: &[usize]) -> Belady {
+        let records = accesses.iter().map(|b| (Label::Load, *b as u32 * 4));
+        Belady::new(1, 4, FutureAccesses::new(records, 4))
+    }
+
+    /// Like victims, with the access notification of the Belady policy.
+    fn belady_victims(accesses: &[usize]) -> Vec<usize> {
+        let mut policy = belady(accesses);
+        let mut replaced = Vec::new();
+        let mut ways: Vec<Option<usize>> = vec![None; 4];
+        for block in accesses {
+            policy.access(*block as u32 * 4);
+            if ways.contains(&Some(*block)) {
+                continue;
+            }
+            let way = policy.victim(0);
+            if ways[way].is_some() {
+                replaced.push(ways[way].unwrap());
+            }
+            ways[way] = Some(*block);
+            policy.insert(0, way, *block as u32 * 4);
+        }
+        replaced
+    }
+
+    #[test]
+    fn future_accesses_skip_compute() {
+        let records = [
+            (Label::Load, 0x10),
+            (Label::Other, 0x10),
+            (Label::Store, 0x13),
+            (Label::Load, 0x20),
+        ];
+        let mut future = FutureAccesses::new(records.into_iter(), 4);
+        assert_eq!(future.next_use(4), Some(0));
+        future.pop(4);
+        assert_eq!(future.next_use(4), Some(1));
+        future.pop(4);
+        assert_eq!(future.next_use(4), None);
+        assert_eq!(future.next_use(8), Some(2));
+    }
+
+    #[test]
+    fn belady_evicts_furthest_reuse() {
+        // 3 is reused last, afterwards only block 4 is used again
+        assert_eq!(
+            belady_victims(&[0, 1, 2, 3, 4, 0, 1, 2, 3, 5, 4]),
+            vec![3, 0, 3]
+        );
+        // LRU misses on every access of the cyclic pattern, OPT keeps 3 of the blocks
+        let cyclic: Vec<usize> = (0..5).cycle().take(50).collect();
+        let misses = 4 + belady_victims(&cyclic).len();
+        assert!(misses < 5 + victims(&mut Lru::new(1, 4), &cyclic).len());
+        assert!(misses <= 4 + 46 / 3 + 1);
+    }
+
+    #[test]
+    fn belady_tags_prefetched_block() {
+        let mut policy = belady(&[0, 1, 2, 0, 1, 2, 7]);
+        policy.access(0);
+        policy.insert(0, 0, 0);
+        // prefetched without an access of its own
+        policy.insert(0, 1, 7 * 4);
+        for (way, block) in [(2, 1), (3, 2)] {
+            policy.access(block * 4);
+            policy.insert(0, way, block * 4);
+        }
+        // block 7 is used last
+        assert_eq!(policy.victim(0), 1);
+    }
+
+    #[test]
+    fn lru() {
+        let mut lru = Lru::new(1, 4);
+        assert_eq!(victims(&mut lru, &[0, 1, 2, 3, 0, 4, 5]), vec![1, 2]);
+        lru.demote(0, 3);
+        assert_eq!(lru.victim(0), 3);
+    }
+
+    #[test]
+    fn fifo_ignores_hits() {
+        let mut fifo = Fifo::new(1, 4);
+        assert_eq!(victims(&mut fifo, &[0, 1, 2, 3, 0, 4, 5]), vec![0, 1]);
+    }
+
+    #[test]
+    fn random_is_seeded() {
+        let accesses: Vec<usize> = (0..64).map(|i| (i * 7) % 13).collect();
+        let run = |seed| victims(&mut Random::new(1, 4, seed), &accesses);
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+        let mut random = Random::new(1, 4, 1);
+        assert_eq!(random.victim(0), 0);
+        (0..4).for_each(|way| random.insert(0, way, 0));
+        assert_eq!(random.victim(0), random.victim(0));
+        random.demote(0, 2);
+        assert_eq!(random.victim(0), 2);
+    }
+
+    #[test]
+    fn tree_plru() {
+        let mut plru = TreePlru::new(1, 4);
+        // 0 and 1 were used after 2 and 3 => the left half is protected, 2 was used before 3
+        assert_eq!(victims(&mut plru, &[0, 1, 2, 3, 0, 1, 4]), vec![2]);
+        // 4 replaced 2 => 3 is the pseudo-LRU block of the right half, but the left is older
+        assert_eq!(plru.victim(0), 0);
+        plru.demote(0, 3);
+        assert_eq!(plru.victim(0), 3);
+    }
+
+    #[test]
+    fn nru() {
+        let mut nru = Nru::new(1, 4);
+        // referencing all blocks clears the bits of the others
+        assert_eq!(victims(&mut nru, &[0, 1, 2, 3, 4]), vec![0]);
+        assert_eq!(nru.victim(0), 1);
+    }
+
+    #[test]
+    fn lfu() {
+        let mut lfu = Lfu::new(1, 4);
+        assert_eq!(victims(&mut lfu, &[0, 0, 1, 1, 2, 3, 3, 4, 5]), vec![2, 2]);
+    }
+
+    #[test]
+    fn srrip_protects_reused_blocks() {
+        let mut srrip = Rrip::new(1, 4, RripInsertion::Static);
+        // 0 was reused, the scan of 4.. only replaces the scanned blocks after the first aging
+        let replaced = victims(&mut srrip, &[0, 1, 2, 3, 0, 4, 5, 6, 7, 8]);
+        assert_eq!(replaced[0], 1);
+        assert!(!replaced[1..].contains(&0));
+    }
+
+    #[test]
+    fn brrip_inserts_distant() {
+        let mut brrip = Rrip::new(1, 4, RripInsertion::Bimodal);
+        victims(&mut brrip, &[0, 1, 2, 3]);
+        // only the first insertion predicted a long re-reference interval
+        assert_eq!(brrip.rrpv[0], vec![RRPV_LONG, RRPV_MAX, RRPV_MAX, RRPV_MAX]);
+        assert_eq!(brrip.victim(0), 1);
+    }
+
+    #[test]
+    fn drrip_follows_leaders() {
+        let mut drrip = Rrip::new(LEADER_PERIOD, 4, RripInsertion::Dynamic);
+        // misses in the SRRIP leader set make the followers use BRRIP
+        for _ in 0..8 {
+            drrip.insert(0, 0, 0);
+        }
+        drrip.num_insertions = 1;
+        drrip.insert(2, 0, 0);
+        assert_eq!(drrip.rrpv[2][0], RRPV_MAX);
+
+        for _ in 0..16 {
+            drrip.insert(1, 0, 0);
+        }
+        drrip.insert(2, 1, 0);
+        assert_eq!(drrip.rrpv[2][1], RRPV_LONG);
+    }
+}
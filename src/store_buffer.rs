@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+#[derive(Default, Clone, Debug)]
+pub struct StoreBufferStats {
+    /// Stores retired into the buffer
+    pub num_buffered_stores: usize,
+    /// Loads served by a buffered store to the same address
+    pub num_forwarded_loads: usize,
+    /// Most stores held by the buffer at once
+    pub max_occupancy: usize,
+}
+
+/// FIFO of retired stores waiting to be written to the cache (TSO). The oldest store stays in the
+/// buffer until the cache performed it, so loads are forwarded from it in the meantime.
+pub struct StoreBuffer {
+    entries: VecDeque<u32>,
+    capacity: usize,
+    stats: StoreBufferStats,
+}
+
+impl StoreBuffer {
+    pub fn new(capacity: usize) -> Self {
+        StoreBuffer {
+            entries: VecDeque::new(),
+            capacity,
+            stats: StoreBufferStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &StoreBufferStats {
+        &self.stats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Retire a store into the buffer. Returns false if the buffer is full.
+    pub fn push(&mut self, addr: u32) -> bool {
+        if self.entries.len() == self.capacity {
+            return false;
+        }
+        self.entries.push_back(addr);
+        self.stats.num_buffered_stores += 1;
+        self.stats.max_occupancy = self.stats.max_occupancy.max(self.entries.len());
+        true
+    }
+
+    /// Returns true if a buffered store to addr supplies the load.
+    pub fn forward(&mut self, addr: u32) -> bool {
+        let hit = self.entries.contains(&addr);
+        if hit {
+            self.stats.num_forwarded_loads += 1;
+        }
+        hit
+    }
+
+    /// Oldest store, the next one to be written to the cache.
+    pub fn front(&self) -> Option<u32> {
+        self.entries.front().copied()
+    }
+
+    /// Remove the oldest store once the cache performed it.
+    pub fn pop(&mut self) -> Option<u32> {
+        self.entries.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_with_forwarding() {
+        let mut buffer = StoreBuffer::new(2);
+        assert!(!buffer.forward(0x10));
+        assert!(buffer.push(0x10));
+        assert!(buffer.push(0x14));
+        assert!(!buffer.push(0x18));
+        assert!(buffer.forward(0x14));
+        assert!(!buffer.forward(0x11));
+
+        assert_eq!(buffer.front(), Some(0x10));
+        assert_eq!(buffer.pop(), Some(0x10));
+        assert!(buffer.push(0x18));
+        assert_eq!(buffer.pop(), Some(0x14));
+        assert_eq!(buffer.pop(), Some(0x18));
+        assert!(buffer.is_empty());
+
+        let stats = buffer.stats();
+        assert_eq!(stats.num_buffered_stores, 3);
+        assert_eq!(stats.num_forwarded_loads, 1);
+        assert_eq!(stats.max_occupancy, 2);
+    }
+}
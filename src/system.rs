@@ -1,10 +1,21 @@
+use crate::arbiter::Arbiter;
+use crate::bus::Phase;
+use crate::cache::Cache;
+use crate::checker::CoherenceChecker;
 use crate::core::{Core, CoreStats};
+use crate::directory::Directory;
+use crate::dram::MemoryController;
+use crate::l2::L2Cache;
+use crate::memory::Transfer;
+#[cfg(sanity_check)]
+use crate::protocol::token::TOKENS_PER_BLOCK;
 use crate::protocol::ProtocolKind;
 use crate::record::RecordStream;
-use crate::Optimizations;
 use crate::{analyzer::Analyzable, bus::Bus};
+use crate::{Optimizations, SystemConfig};
 use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use rand::{rngs::mock::StepRng, seq::SliceRandom};
+#[cfg(sanity_check)]
+use std::collections::HashMap;
 
 /// word size in bytes
 pub const WORD_SIZE: usize = 4;
@@ -13,10 +24,16 @@ pub struct System {
     cores: Vec<Core>,
     active_cores: Vec<usize>,
     bus: Bus,
+    arbiter: Arbiter,
+    directory: Option<Directory>,
+    l2: Option<L2Cache>,
+    memory_controller: Option<MemoryController>,
+    checker: Option<CoherenceChecker>,
+    // data movement of the bus transactions in flight
+    transfers: Vec<Transfer>,
     clk: usize,
     progress: ProgressBar,
     mp_bar: MultiProgress,
-    rng: rand::rngs::mock::StepRng,
     optimizations: Optimizations,
 }
 
@@ -28,7 +45,7 @@ impl System {
         block_size: usize,
         record_streams: Vec<RecordStream>,
         show_process: bool,
-        optimizations: Optimizations,
+        config: SystemConfig,
     ) -> Self {
         let mp_bar = MultiProgress::new();
         let system_progress = mp_bar
@@ -43,12 +60,19 @@ impl System {
             .map(|(id, stream)| {
                 Core::new(
                     protocol,
+                    config.protocol_table.as_ref(),
                     cache_size,
                     associativity,
                     block_size,
                     stream,
                     id,
                     &mp_bar,
+                    &config.hierarchy,
+                    &config.timing,
+                    config.replacement,
+                    config.prefetch,
+                    config.mshrs,
+                    config.store_buffer,
                 )
             })
             .collect();
@@ -56,15 +80,33 @@ impl System {
         if !show_process {
             mp_bar.set_draw_target(ProgressDrawTarget::hidden());
         }
+        // the directory serializes the transactions at the home node of the block, the blocks are
+        // interleaved over one home node per core
+        let num_buses = match config.directory {
+            Some(_) => cores.len(),
+            None => config.num_buses.unwrap_or(1),
+        };
         System {
             active_cores: (0..cores.len()).collect(),
+            arbiter: Arbiter::new(config.arbitration, cores.len()),
+            directory: config
+                .directory
+                .map(|dir_config| Directory::new(dir_config, cores.len(), block_size)),
+            l2: config.l2.map(L2Cache::new),
+            memory_controller: config.dram.map(MemoryController::new),
+            checker: config.check_coherence.then(CoherenceChecker::new),
             cores,
-            bus: Bus::new(),
+            bus: Bus::interleaved(
+                config.timing,
+                config.split_bus,
+                num_buses,
+                block_size / WORD_SIZE,
+            ),
+            transfers: Vec::new(),
             clk: 0,
             progress: system_progress,
             mp_bar,
-            rng: StepRng::new(0, 1),
-            optimizations,
+            optimizations: config.optimizations,
         }
     }
 
@@ -77,15 +119,16 @@ impl System {
         self.progress.inc(1);
 
         self.bus.update();
-        self.active_cores.shuffle(&mut self.rng);
 
-        // run 1: parse new instructions / update state
+        // run 1: parse new instructions / update state. The first core in arbitration order that
+        // requests the free bus is granted it.
         let mut deactivated_cores: Vec<usize> = Vec::new();
-        for core_id in &self.active_cores {
-            if !self.cores[*core_id].step(&mut self.bus, self.clk) {
-                deactivated_cores.push(*core_id);
+        for core_id in self.arbiter.order(&self.active_cores) {
+            if !self.cores[core_id].step(&mut self.bus, self.clk) {
+                deactivated_cores.push(core_id);
             }
         }
+        self.arbiter.update(self.bus.denied(), self.bus.granted());
         if !deactivated_cores.is_empty() {
             self.active_cores = self
                 .active_cores
@@ -94,21 +137,34 @@ impl System {
                 .collect();
         }
 
-        // run 2: snoop other cores' actions
-        for core in self.cores.iter_mut() {
-            core.snoop(&mut self.bus);
+        // run 2 and 3 for each bus. The buses are snooped in the order their transactions were
+        // issued, the L2 and the memory controller serve each new transaction once.
+        for bus_idx in self.bus.issue_order() {
+            self.bus.select_bus(bus_idx);
+            self.snoop_bus();
         }
 
-        // run 2.5: read broadcast optimization (if enabled)
-        if self.optimizations.read_broadcast {
-            for core in self.cores.iter_mut() {
-                core.read_broadcast(&mut self.bus);
+        // run 4: move data of completed transactions, commit this cycle's stores
+        while let Some(idx) = self.transfers.iter().position(|t| t.done(&mut self.bus)) {
+            let mut caches: Vec<&mut Cache> = self.cores.iter_mut().map(Core::cache_mut).collect();
+            self.transfers
+                .remove(idx)
+                .finish(&mut self.bus, &mut caches);
+        }
+        for core in self.cores.iter_mut() {
+            for (addr, data) in core.cache_mut().take_writebacks() {
+                self.bus.memory().write_block(addr, &data);
             }
         }
+        self.bus.memory().commit();
 
-        // run 3: cleanup after bus snooping
-        for core in self.cores.iter_mut() {
-            core.after_snoop(&mut self.bus);
+        if let Some(checker) = self.checker.as_mut() {
+            let mut bus_addrs = Vec::new();
+            for bus_idx in 0..self.bus.num_buses() {
+                self.bus.select_bus(bus_idx);
+                bus_addrs.extend(checker.record(self.clk, &mut self.bus));
+            }
+            checker.check(self.clk, &bus_addrs, &self.cores);
         }
 
         if self.active_cores.is_empty() {
@@ -120,6 +176,67 @@ impl System {
         self.active_cores.is_empty()
     }
 
+    /// Snoop the transaction of the selected bus.
+    fn snoop_bus(&mut self) {
+        // capture the data of a newly issued transaction before the caches react to it
+        let issued = self.bus.active_task().map(|t| t.id);
+        if issued.is_some_and(|id| !self.transfers.iter().any(|t| t.task_id() == id)) {
+            let caches: Vec<&mut Cache> = self.cores.iter_mut().map(Core::cache_mut).collect();
+            self.transfers
+                .extend(Transfer::begin(&mut self.bus, &caches));
+        }
+        // responses of split transactions are not snooped, the caches reacted to their request
+        let snoop = self
+            .bus
+            .active_task()
+            .is_none_or(|t| t.phase == Phase::Request);
+
+        // run 2: snoop other cores' actions
+        match self.directory.as_mut() {
+            _ if !snoop => (),
+            // only the registered sharers receive the request
+            Some(directory) => {
+                for core_id in directory.route(&mut self.bus) {
+                    self.cores[*core_id].snoop(&mut self.bus);
+                }
+            }
+            None => {
+                for core in self.cores.iter_mut() {
+                    core.snoop(&mut self.bus);
+                }
+            }
+        }
+
+        for transfer in self.transfers.iter_mut() {
+            transfer.snooped(&mut self.bus);
+        }
+
+        // run 2.5: read broadcast optimization (if enabled)
+        if self.optimizations.read_broadcast && snoop {
+            for core in self.cores.iter_mut() {
+                core.read_broadcast(&mut self.bus);
+            }
+        }
+
+        // run 3: cleanup after bus snooping
+        for core in self.cores.iter_mut() {
+            core.after_snoop(&mut self.bus);
+        }
+        if let Some(directory) = self.directory.as_mut() {
+            let cores = &mut self.cores;
+            directory.after_snoop(&mut self.bus, |core_id, addr| {
+                cores[core_id].invalidate(addr)
+            });
+        }
+        let served_by_l2 = match self.l2.as_mut() {
+            Some(l2) => l2.update(&mut self.bus),
+            None => false,
+        };
+        if let Some(controller) = self.memory_controller.as_mut() {
+            controller.update(&mut self.bus, self.clk, served_by_l2);
+        }
+    }
+
     // compare cache state and cache protocol state
     #[cfg(sanity_check)]
     fn sanity_check(&self) {
@@ -127,6 +244,16 @@ impl System {
         for core in &self.cores {
             core.sanity_check();
         }
+
+        // token coherence: tokens are neither created nor duplicated, there is one owner at most
+        let mut tokens: HashMap<u32, (usize, usize)> = HashMap::new();
+        for (addr, num_tokens, owner) in self.cores.iter().flat_map(|c| c.tokens()) {
+            let (sum, owners) = tokens.entry(addr).or_default();
+            *sum += num_tokens;
+            *owners += owner as usize;
+            assert!(*sum <= TOKENS_PER_BLOCK, "Too many tokens for {:#x}", addr);
+            assert!(*owners <= 1, "Multiple owners of {:#x}", addr);
+        }
     }
 
     pub fn hide_progress(&self) {
@@ -140,5 +267,15 @@ impl Analyzable for System {
         (0..self.cores.len()).for_each(|_| stats.cores.push(CoreStats::default()));
         self.cores.iter().for_each(|c| c.report(stats));
         self.bus.report(stats);
+        self.arbiter.report(stats);
+        if let Some(directory) = &self.directory {
+            directory.report(stats);
+        }
+        if let Some(l2) = &self.l2 {
+            l2.report(stats);
+        }
+        if let Some(controller) = &self.memory_controller {
+            controller.report(stats);
+        }
     }
 }
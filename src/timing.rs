@@ -0,0 +1,142 @@
+use crate::bus::BusAction;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+/// Latency and bandwidth model of the memory system, all latencies in cycles.
+///
+/// A timing file consists of `key = value` pairs named like the fields, `#` starts a comment and
+/// omitted keys keep their default.
+///
+/// ```text
+/// memory_latency = 200    # DRAM access
+/// bus_width = 8           # bytes per beat
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingConfig {
+    /// Main memory serving a read (or a write-through of a word)
+    pub memory_latency: usize,
+    /// Bytes transferred per bus beat
+    pub bus_width: usize,
+    pub cycles_per_beat: usize,
+    /// Hit latency of the coherent (last private) cache level
+    pub hit_latency: usize,
+    /// Snooping caches looking up their tags before they supply or update a block, added to the
+    /// transfer of cache-to-cache transactions
+    pub snoop_latency: usize,
+    /// Main memory absorbing a flushed block
+    pub writeback_latency: usize,
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig {
+            memory_latency: 100,
+            bus_width: 4,
+            cycles_per_beat: 2,
+            hit_latency: 1,
+            snoop_latency: 0,
+            writeback_latency: 100,
+        }
+    }
+}
+
+impl TimingConfig {
+    pub fn open(path: &str) -> Result<TimingConfig, Error> {
+        TimingConfig::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(spec: &str) -> Result<TimingConfig, Error> {
+        let mut timing = TimingConfig::default();
+        for (line_idx, line) in spec.lines().enumerate() {
+            let line_nr = line_idx + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| parse_error(line_nr, "expected key = value"))?;
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| parse_error(line_nr, "expected a number of cycles or bytes"))?;
+            let field = match key.trim() {
+                "memory_latency" => &mut timing.memory_latency,
+                "bus_width" => &mut timing.bus_width,
+                "cycles_per_beat" => &mut timing.cycles_per_beat,
+                "hit_latency" => &mut timing.hit_latency,
+                "snoop_latency" => &mut timing.snoop_latency,
+                "writeback_latency" => &mut timing.writeback_latency,
+                key => return Err(parse_error(line_nr, &format!("unknown key {:?}", key))),
+            };
+            *field = value;
+        }
+        Ok(timing)
+    }
+
+    /// Cycles to move size bytes over the bus
+    pub fn transfer_cycles(&self, size: usize) -> usize {
+        size.div_ceil(self.bus_width) * self.cycles_per_beat
+    }
+
+    /// Query number of cycles required for the entered action
+    pub fn price(&self, action: &BusAction) -> usize {
+        match action {
+            BusAction::BusRdMem(_, _) => self.memory_latency,
+            BusAction::BusRdShared(_, c) => self.snoop_latency + self.transfer_cycles(*c),
+            BusAction::BusRdXMem(_, _) => self.memory_latency,
+            BusAction::BusRdXShared(_, c) => self.snoop_latency + self.transfer_cycles(*c),
+            BusAction::BusUpdMem(_, _) => self.memory_latency,
+            BusAction::BusUpdShared(_, c) => self.snoop_latency + self.transfer_cycles(*c),
+            BusAction::Flush(_, _) => self.writeback_latency,
+        }
+    }
+}
+
+fn parse_error(line_nr: usize, msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("timing specification, line {}: {}", line_nr, msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_file_matches_default() {
+        let timing = TimingConfig::parse(include_str!("../timing/default.toml")).unwrap();
+        assert_eq!(timing, TimingConfig::default());
+    }
+
+    #[test]
+    fn prices() {
+        let timing = TimingConfig::default();
+        assert_eq!(timing.price(&BusAction::BusRdMem(0, 32)), 100);
+        assert_eq!(timing.price(&BusAction::BusRdShared(0, 32)), 16);
+        assert_eq!(timing.price(&BusAction::BusUpdShared(0, 4)), 2);
+        assert_eq!(timing.price(&BusAction::Flush(0, 32)), 100);
+
+        let timing = TimingConfig::parse(
+            "memory_latency = 200\n\
+             bus_width = 8  # bytes\n\
+             snoop_latency = 3\n\
+             writeback_latency = 150",
+        )
+        .unwrap();
+        assert_eq!(timing.price(&BusAction::BusRdXMem(0, 32)), 200);
+        assert_eq!(timing.price(&BusAction::BusRdXShared(0, 32)), 3 + 8);
+        assert_eq!(timing.price(&BusAction::BusUpdShared(0, 4)), 3 + 2);
+        assert_eq!(timing.price(&BusAction::Flush(0, 32)), 150);
+        assert_eq!(timing.hit_latency, 1);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let message = |spec| TimingConfig::parse(spec).unwrap_err().to_string();
+        assert!(message("memory_latency 100").contains("line 1"));
+        assert!(message("\nbus_width = wide").contains("line 2"));
+        assert!(message("dram_latency = 100").contains("unknown key"));
+    }
+}
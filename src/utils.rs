@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 pub struct Counter {
     pub value: u32,
 }
@@ -69,7 +71,60 @@ impl AddressLayout {
         addr >> right_offset
     }
 
+    /// Reconstruct the (block aligned) address of a cached block from its tag and set index.
+    pub fn addr(&self, tag: u32, index: usize) -> u32 {
+        let tag_part = tag
+            .checked_shl((self.offset_length + self.index_length) as u32)
+            .unwrap_or(0);
+        tag_part | ((index as u32) << self.offset_length)
+    }
+
     pub fn nested_to_flat(&self, set_idx: usize, block_idx: usize) -> usize {
         set_idx * (self.set_size / self.block_size) + block_idx
     }
 }
+
+/// Bounded, set-associative storage of block addresses with LRU replacement.
+/// Only tracks which blocks are present, e.g. the entries of a sparse directory.
+pub struct LruSets {
+    // blocks of each set, least recently used first
+    sets: Vec<VecDeque<u32>>,
+    associativity: usize,
+}
+
+impl LruSets {
+    pub fn new(num_entries: usize, associativity: usize) -> Self {
+        let associativity = associativity.min(num_entries);
+        assert!(associativity > 0 && num_entries.is_multiple_of(associativity));
+        LruSets {
+            sets: vec![VecDeque::new(); num_entries / associativity],
+            associativity,
+        }
+    }
+
+    /// Mark the block as used. Returns the block that was evicted to make room.
+    pub fn touch(&mut self, block: u32) -> Option<u32> {
+        let num_sets = self.sets.len();
+        let set = &mut self.sets[block as usize % num_sets];
+        if let Some(pos) = set.iter().position(|b| *b == block) {
+            set.remove(pos);
+            set.push_back(block);
+            return None;
+        }
+        set.push_back(block);
+        if set.len() > self.associativity {
+            set.pop_front()
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        self.sets[block as usize % self.sets.len()].contains(&block)
+    }
+
+    pub fn remove(&mut self, block: u32) {
+        let num_sets = self.sets.len();
+        self.sets[block as usize % num_sets].retain(|b| *b != block);
+    }
+}
@@ -0,0 +1,428 @@
+//! Exhaustive state space exploration of the coherence protocols.
+//!
+//! A model consists of N single-line caches on one bus, accessing one or two blocks that map to
+//! the same cache line (so that evictions and writebacks are explored as well). Every reachable
+//! combination of line states is visited in breadth-first order by replaying event sequences on
+//! fresh caches, and the single-writer/multiple-reader invariant is checked in each of them. Loads
+//! returning stale values (see `memory`) are reported as well.
+//!
+//! Every access runs to completion before the next one is issued. Interleavings of concurrent
+//! transactions (and thus the transient states) are not part of the explored state space.
+use crate::bus::Bus;
+use crate::cache::Cache;
+use crate::hierarchy::HierarchyConfig;
+use crate::memory::Transfer;
+use crate::protocol::table::ProtocolTable;
+use crate::protocol::{Permission, ProtocolKind};
+use crate::replacement::ReplacementConfig;
+use crate::timing::TimingConfig;
+use std::cell::Cell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::Once;
+
+thread_local! {
+    // set while a replay runs on this thread, its panics are reported as violations instead
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Wrap the panic hook once so that it stays silent for panics of replays only. Other threads
+/// keep reporting their panics through the previous hook.
+fn install_quiet_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(Cell::get) {
+                hook(info);
+            }
+        }));
+    });
+}
+
+// one set with one line of one word => all blocks conflict
+const BLOCK_SIZE: usize = 4;
+
+// an access still running after this many cycles does not make progress
+const MAX_ACCESS_CYCLES: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Event {
+    pub core_id: usize,
+    pub block: usize,
+    pub write: bool,
+}
+
+impl Event {
+    /// Word address of the block (tag 0 is used as placeholder of empty cache lines)
+    fn addr(&self) -> u32 {
+        self.block as u32 + 1
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "core {} {} block {}",
+            self.core_id,
+            if self.write { "stores" } else { "loads" },
+            self.block
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    pub reason: String,
+    /// Events leading from the initial state to the violation
+    pub trace: Vec<Event>,
+    /// Line state names per core and block when the violation was detected
+    pub states: Vec<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub num_states: usize,
+    pub num_transitions: usize,
+    pub violation: Option<Violation>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Explored {} states ({} transitions).",
+            self.num_states, self.num_transitions
+        )?;
+        match &self.violation {
+            None => write!(f, "No violations found."),
+            Some(violation) => {
+                writeln!(f, "Violation: {}", violation.reason)?;
+                writeln!(f, "Trace:")?;
+                for (i, event) in violation.trace.iter().enumerate() {
+                    writeln!(f, "  {}. {}", i + 1, event)?;
+                }
+                write!(f, "States:")?;
+                for (core_id, blocks) in violation.states.iter().enumerate() {
+                    write!(f, "\n  core {}: {}", core_id, blocks.join(" "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Model {
+    caches: Vec<Cache>,
+    bus: Bus,
+    transfer: Option<Transfer>,
+}
+
+impl Model {
+    fn new(kind: &ProtocolKind, table: Option<&Rc<ProtocolTable>>, num_caches: usize) -> Self {
+        Model {
+            caches: (0..num_caches)
+                .map(|core_id| {
+                    Cache::new(
+                        core_id,
+                        BLOCK_SIZE,
+                        1,
+                        BLOCK_SIZE,
+                        kind,
+                        table,
+                        &HierarchyConfig::default(),
+                        &TimingConfig::default(),
+                        ReplacementConfig::default(),
+                        None,
+                        None,
+                        None,
+                    )
+                })
+                .collect(),
+            bus: Bus::new(),
+            transfer: None,
+        }
+    }
+
+    /// Run the access of the event until the bus is quiet again.
+    fn access(&mut self, event: &Event) -> Result<(), String> {
+        let cache = &mut self.caches[event.core_id];
+        if event.write {
+            cache.store(event.addr());
+        } else {
+            cache.load(event.addr());
+        }
+        for _ in 0..MAX_ACCESS_CYCLES {
+            self.bus.update();
+            let stalled = self.caches[event.core_id].update(&mut self.bus).is_some();
+            if self.transfer.is_none() {
+                let caches: Vec<&mut Cache> = self.caches.iter_mut().collect();
+                self.transfer = Transfer::begin(&mut self.bus, &caches);
+            }
+            for cache in self.caches.iter_mut() {
+                cache.snoop(&mut self.bus);
+            }
+            if let Some(transfer) = self.transfer.as_mut() {
+                transfer.snooped(&mut self.bus);
+            }
+            for cache in self.caches.iter_mut() {
+                cache.after_snoop(&mut self.bus);
+            }
+            if self
+                .transfer
+                .as_ref()
+                .is_some_and(|t| t.done(&mut self.bus))
+            {
+                let mut caches: Vec<&mut Cache> = self.caches.iter_mut().collect();
+                self.transfer
+                    .take()
+                    .unwrap()
+                    .finish(&mut self.bus, &mut caches);
+            }
+            self.bus.memory().commit();
+            if self.bus.memory().num_stale_reads() > 0 {
+                return Err(String::from("Load returned a stale value."));
+            }
+            if !stalled && !self.bus.occupied() {
+                return Ok(());
+            }
+        }
+        Err(format!(
+            "Access did not complete within {} cycles.",
+            MAX_ACCESS_CYCLES
+        ))
+    }
+
+    fn states(&self, num_blocks: usize) -> Vec<Vec<(Permission, String)>> {
+        self.caches
+            .iter()
+            .map(|cache| {
+                (0..num_blocks)
+                    .map(|block| cache.line_state(block as u32 + 1))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn names(states: &[Vec<(Permission, String)>]) -> Vec<Vec<String>> {
+    states
+        .iter()
+        .map(|blocks| blocks.iter().map(|(_, name)| name.clone()).collect())
+        .collect()
+}
+
+/// Check the single-writer/multiple-reader invariant and that the last access left the block in
+/// the accessing cache.
+fn check(states: &[Vec<(Permission, String)>], last: Option<&Event>) -> Result<(), String> {
+    for block in 0..states[0].len() {
+        let writers: Vec<usize> = (0..states.len())
+            .filter(|&c| states[c][block].0 == Permission::Write)
+            .collect();
+        let readers: Vec<usize> = (0..states.len())
+            .filter(|&c| states[c][block].0 == Permission::Read)
+            .collect();
+        if writers.len() > 1 || (writers.len() == 1 && !readers.is_empty()) {
+            return Err(format!(
+                "Block {} is writable by cores {:?} while readable by cores {:?}.",
+                block, writers, readers
+            ));
+        }
+    }
+    if let Some(event) = last {
+        if states[event.core_id][event.block].0 == Permission::Invalid {
+            return Err(format!(
+                "Core {} lost block {} during its access.",
+                event.core_id, event.block
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replay the event sequence on a fresh model, converting panics into errors.
+fn replay(
+    kind: &ProtocolKind,
+    table: Option<&Rc<ProtocolTable>>,
+    num_caches: usize,
+    trace: &[Event],
+) -> Result<Model, String> {
+    install_quiet_hook();
+    QUIET.with(|quiet| quiet.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut model = Model::new(kind, table, num_caches);
+        for event in trace {
+            model.access(event)?;
+        }
+        Ok(model)
+    }));
+    QUIET.with(|quiet| quiet.set(false));
+    result.unwrap_or_else(|payload| {
+        Err(match payload.downcast_ref::<&str>() {
+            Some(msg) => msg.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(msg) => msg.clone(),
+                None => String::from("Unknown panic."),
+            },
+        })
+    })
+}
+
+/// Exhaustively explore all reachable states of the protocol with num_caches caches accessing
+/// num_blocks (1 or 2) blocks. Stops at the first violation.
+pub fn verify(
+    kind: &ProtocolKind,
+    table: Option<&Rc<ProtocolTable>>,
+    num_caches: usize,
+    num_blocks: usize,
+) -> Report {
+    assert!(num_caches > 0, "At least one cache is required.");
+    assert!(
+        (1..=2).contains(&num_blocks),
+        "Only one or two blocks are supported."
+    );
+
+    let events: Vec<Event> = (0..num_caches)
+        .flat_map(|core_id| {
+            (0..num_blocks).flat_map(move |block| {
+                [false, true].map(|write| Event {
+                    core_id,
+                    block,
+                    write,
+                })
+            })
+        })
+        .collect();
+
+    // the protocols panic on invalid transitions, which are reported as violations instead
+    let initial = replay(kind, table, num_caches, &[]).expect("Protocol initialization failed.");
+    let mut visited = HashSet::from([names(&initial.states(num_blocks))]);
+    let mut queue = VecDeque::from([Vec::new()]);
+    let mut num_transitions = 0;
+    let mut violation = None;
+
+    'search: while let Some(trace) = queue.pop_front() {
+        for event in events.iter() {
+            let mut next = trace.clone();
+            next.push(*event);
+            num_transitions += 1;
+
+            let result = replay(kind, table, num_caches, &next).and_then(|model| {
+                let states = model.states(num_blocks);
+                check(&states, Some(event)).map(|_| names(&states))
+            });
+            match result {
+                Ok(states) => {
+                    if visited.insert(states) {
+                        queue.push_back(next);
+                    }
+                }
+                Err(reason) => {
+                    // report the states before the failing access if it panicked
+                    let states = replay(kind, table, num_caches, &next)
+                        .or_else(|_| replay(kind, table, num_caches, &trace))
+                        .map(|model| names(&model.states(num_blocks)))
+                        .unwrap_or_default();
+                    violation = Some(Violation {
+                        reason,
+                        trace: next,
+                        states,
+                    });
+                    break 'search;
+                }
+            }
+        }
+    }
+
+    Report {
+        num_states: visited.len(),
+        num_transitions,
+        violation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_verified(kind: ProtocolKind, num_blocks: usize) {
+        let report = verify(&kind, None, 3, num_blocks);
+        assert!(report.violation.is_none(), "{:?}: {}", kind, report);
+        assert!(report.num_states > 1);
+    }
+
+    #[test]
+    fn builtin_protocols() {
+        for kind in [
+            ProtocolKind::Mesi,
+            ProtocolKind::Dragon,
+            ProtocolKind::Msi,
+            ProtocolKind::Moesi,
+            ProtocolKind::Mesif,
+            ProtocolKind::Firefly,
+            ProtocolKind::Berkeley,
+            ProtocolKind::WriteOnce,
+            ProtocolKind::Token,
+        ] {
+            assert_verified(kind, 1);
+        }
+    }
+
+    #[test]
+    fn conflicting_blocks() {
+        assert_verified(ProtocolKind::Mesi, 2);
+        assert_verified(ProtocolKind::Dragon, 2);
+    }
+
+    #[test]
+    fn mesi_state_count() {
+        // MESI with 2 caches and one block: II, SI, IS, SS, EI, IE, MI, IM
+        let report = verify(&ProtocolKind::Mesi, None, 2, 1);
+        assert!(report.violation.is_none());
+        assert_eq!(report.num_states, 8);
+    }
+
+    #[test]
+    fn broken_table_is_caught() {
+        // MESI reader keeps E although another cache supplied the line
+        let spec = include_str!("../protocols/mesi.toml")
+            .replace("BusRdShared  E  ->  S", "BusRdShared  E  ->  E");
+        let table = Rc::new(ProtocolTable::parse(&spec).unwrap());
+        let report = verify(&ProtocolKind::Table, Some(&table), 2, 1);
+        let violation = report.violation.expect("violation not detected");
+        assert!(violation.reason.contains("writable"));
+    }
+
+    #[test]
+    fn lost_writeback_is_caught() {
+        // MESI dropping the dirty line instead of supplying it
+        let spec = include_str!("../protocols/mesi.toml").replace(
+            "BusRdMem                   M      ->  S      BusRdShared   cost=Flush",
+            "BusRdMem                   M      ->  I",
+        );
+        let table = Rc::new(ProtocolTable::parse(&spec).unwrap());
+        let report = verify(&ProtocolKind::Table, Some(&table), 2, 1);
+        let violation = report.violation.expect("violation not detected");
+        assert!(violation.reason.contains("stale"));
+    }
+
+    #[test]
+    fn missing_rule_is_caught() {
+        let spec = include_str!("../protocols/mesi.toml")
+            .lines()
+            .filter(|line| {
+                !line
+                    .split_whitespace()
+                    .eq(["S", "write", "hit", "->", "M", "BusRdXMem", "word"])
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let table = Rc::new(ProtocolTable::parse(&spec).unwrap());
+        let report = verify(&ProtocolKind::Table, Some(&table), 2, 1);
+        let violation = report.violation.expect("violation not detected");
+        assert!(violation.reason.contains("Unresolved processor event"));
+    }
+}
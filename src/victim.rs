@@ -0,0 +1,157 @@
+use crate::memory::Memory;
+use crate::utils::LruSets;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VictimConfig {
+    pub num_entries: usize,
+    /// Additional latency of a hit in cycles
+    pub hit_latency: usize,
+}
+
+impl Default for VictimConfig {
+    fn default() -> Self {
+        VictimConfig {
+            num_entries: 8,
+            hit_latency: 1,
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct VictimStats {
+    pub num_hits: usize,
+    pub num_insertions: usize,
+    /// Entries dropped because another core accessed the block
+    pub num_invalidations: usize,
+}
+
+/// Small fully-associative (LRU) cache of the blocks evicted from a private cache. The protocol
+/// state of a block is lost with its eviction, so entries are clean read-only copies: they serve
+/// loads, but cannot answer snoops and are dropped by any transaction of another core.
+pub struct VictimCache {
+    config: VictimConfig,
+    blocks: LruSets,
+    // word versions of each held block
+    data: HashMap<u32, Vec<u64>>,
+    words_per_block: usize,
+    stats: VictimStats,
+}
+
+impl VictimCache {
+    pub fn new(config: VictimConfig, words_per_block: usize) -> Self {
+        VictimCache {
+            config,
+            blocks: LruSets::new(config.num_entries, config.num_entries),
+            data: HashMap::new(),
+            words_per_block,
+            stats: VictimStats::default(),
+        }
+    }
+
+    pub fn hit_latency(&self) -> usize {
+        self.config.hit_latency
+    }
+
+    pub fn stats(&self) -> &VictimStats {
+        &self.stats
+    }
+
+    fn block(&self, addr: u32) -> u32 {
+        addr / self.words_per_block as u32
+    }
+
+    /// Catch a (clean) block evicted from the cache.
+    pub fn insert(&mut self, addr: u32, data: Vec<u64>) {
+        let block = self.block(addr);
+        self.stats.num_insertions += 1;
+        self.data.insert(block, data);
+        if let Some(victim) = self.blocks.touch(block) {
+            self.data.remove(&victim);
+        }
+    }
+
+    /// Serve a load, the loaded version is checked against memory. Returns false on a miss.
+    pub fn load(&mut self, addr: u32, memory: &mut Memory) -> bool {
+        let block = self.block(addr);
+        let data = match self.data.get(&block) {
+            Some(data) => data,
+            None => return false,
+        };
+        memory.check_load(addr, data[addr as usize % self.words_per_block]);
+        self.blocks.touch(block);
+        self.stats.num_hits += 1;
+        true
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        self.data.contains_key(&self.block(addr))
+    }
+
+    /// The block is loaded into the cache again.
+    pub fn remove(&mut self, addr: u32) {
+        let block = self.block(addr);
+        self.blocks.remove(block);
+        self.data.remove(&block);
+    }
+
+    /// Another core accesses the block.
+    pub fn snooped(&mut self, addr: u32) {
+        if self.contains(addr) {
+            self.remove(addr);
+            self.stats.num_invalidations += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn victim_cache() -> VictimCache {
+        VictimCache::new(
+            VictimConfig {
+                num_entries: 2,
+                hit_latency: 1,
+            },
+            4,
+        )
+    }
+
+    #[test]
+    fn lru_entries() {
+        let mut victims = victim_cache();
+        let mut memory = Memory::default();
+        victims.insert(0x10, vec![0; 4]);
+        victims.insert(0x20, vec![0; 4]);
+        assert!(victims.load(0x12, &mut memory));
+        // 0x20 is the least recently used entry
+        victims.insert(0x30, vec![0; 4]);
+        assert!(!victims.load(0x20, &mut memory));
+        assert!(victims.contains(0x10) && victims.contains(0x30));
+        assert_eq!(victims.stats.num_hits, 1);
+        assert_eq!(victims.stats.num_insertions, 3);
+    }
+
+    #[test]
+    fn snoops_invalidate() {
+        let mut victims = victim_cache();
+        let mut memory = Memory::default();
+        victims.insert(0x10, vec![0; 4]);
+        victims.snooped(0x13);
+        victims.snooped(0x20);
+        assert!(!victims.load(0x10, &mut memory));
+        assert_eq!(victims.stats.num_invalidations, 1);
+    }
+
+    #[test]
+    fn loads_are_checked() {
+        let mut victims = victim_cache();
+        let mut memory = Memory::default();
+        victims.insert(0x10, vec![0; 4]);
+        memory.new_version(0x11);
+        memory.commit();
+        assert!(victims.load(0x11, &mut memory));
+        assert_eq!(memory.num_stale_reads(), 1);
+    }
+}
@@ -1,4 +1,10 @@
-use cacher::{Analyzer, FileLoader, ProtocolKind, System};
+use cacher::{
+    Analyzer, ArbitrationConfig, ArbitrationKind, DirectoryConfig, DramConfig, FileLoader,
+    HierarchyConfig, InclusionPolicy, L2Config, LevelConfig, PrefetchConfig, PrefetchKind,
+    ProtocolKind, ProtocolTable, ReplacementConfig, ReplacementKind, System, SystemConfig,
+    TimingConfig, VictimConfig,
+};
+use std::rc::Rc;
 
 struct ProgramArgs {
     /// Cache coherence protocol
@@ -41,6 +47,17 @@ impl ProgramArgs {
 }
 
 fn run(args: ProgramArgs) -> Analyzer {
+    run_with_config(args, Default::default())
+}
+
+fn table_config(path: &str) -> SystemConfig {
+    SystemConfig {
+        protocol_table: Some(Rc::new(ProtocolTable::open(path).unwrap())),
+        ..Default::default()
+    }
+}
+
+fn run_with_config(args: ProgramArgs, config: SystemConfig) -> Analyzer {
     let record_streams = match FileLoader::open(&args.input_file, !args.no_progress) {
         Ok(streams) => streams,
         Err(e) => {
@@ -59,7 +76,7 @@ fn run(args: ProgramArgs) -> Analyzer {
         args.block_size,
         record_streams,
         !args.no_progress,
-        Default::default(),
+        config,
     );
 
     loop {
@@ -252,3 +269,1193 @@ fn sequence_16_4_4() {
     assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
     assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
 }
+
+#[test]
+fn sequence_16_4_4_msi() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Msi,
+        16,
+        4,
+        4,
+        true,
+    );
+
+    // the upgrade of the shared line (write to 0x10) costs a full bus transaction without E
+    let analyzer = run(args);
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 1 + 101 + 101 + 101 + 1 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 8);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
+}
+
+#[test]
+fn sequence_16_4_4_firefly() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Firefly,
+        16,
+        4,
+        4,
+        true,
+    );
+
+    // without sharers, Firefly never writes through and behaves like Dragon
+    let analyzer = run(args);
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 1 + 1 + 101 + 101 + 1 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 7);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 0);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
+}
+
+#[test]
+fn silent_eviction_16_1_4_firefly() {
+    let args = ProgramArgs::new(
+        String::from("data/multi_thread/silent_eviction.zip"),
+        ProtocolKind::Firefly,
+        16,
+        1,
+        4,
+        true,
+    );
+
+    // core 1 evicts its shared copy of 0x10 silently, the write through of core 0 finds no
+    // sharer and makes the line exclusive. It is still a data access.
+    let analyzer = run(args);
+    assert_eq!(analyzer.stats.exec_cycles, 1227);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 1);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 3);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 1);
+    assert_eq!(analyzer.stats.num_stale_reads, 0);
+}
+
+#[test]
+fn sequence_16_4_4_berkeley() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Berkeley,
+        16,
+        4,
+        4,
+        true,
+    );
+
+    // without sharers, Berkeley behaves like MSI (no exclusive state)
+    let analyzer = run(args);
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 1 + 101 + 101 + 101 + 1 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 8);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
+}
+
+#[test]
+fn sequence_16_4_4_write_once() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::WriteOnce,
+        16,
+        4,
+        4,
+        true,
+    );
+
+    // the write to 0x10 is written through, so its eviction requires no writeback
+    let analyzer = run(args);
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 1 + 101 + 101 + 101 + 1 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 7);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
+}
+
+#[test]
+fn sequence_16_1_4_directory() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Mesi,
+        16,
+        1,
+        4,
+        true,
+    );
+    let config = SystemConfig {
+        directory: Some(DirectoryConfig {
+            lookup_latency: 5,
+            hop_latency: 10,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // same as sequence_16_1_4, each request takes two hops and each of the three writebacks one
+    let analyzer = run_with_config(args, config);
+    let snooping_cycles = 101 + 10 + 102 + 101 + 101 + 1 + 101 + 101 + 101 + 101 + 101 + 101 + 101;
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        snooping_cycles + 8 * (5 + 2 * 10) + 3 * (5 + 10)
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    let directory = analyzer.stats.directory.unwrap();
+    assert_eq!(directory.num_lookups, 11);
+    assert_eq!(directory.num_messages(), 8 * 2 + 3);
+    assert_eq!(directory.num_forwards, 0);
+    assert_eq!(directory.message_latency, (8 * 2 + 3) * 10);
+}
+
+#[test]
+fn directory_home_nodes_serve_in_parallel() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10.zip"),
+            ProtocolKind::Mesi,
+            4096,
+            2,
+            32,
+            true,
+        )
+    };
+    let snooping = run(args());
+    assert_eq!(snooping.stats.exec_cycles, 1927);
+
+    // the four home nodes serve their blocks independently of each other
+    let analyzer = run_with_config(
+        args(),
+        SystemConfig {
+            directory: Some(DirectoryConfig::default()),
+            ..Default::default()
+        },
+    );
+    assert_eq!(analyzer.stats.exec_cycles, 1169);
+    assert_eq!(analyzer.stats.bus_traffic, 608);
+    let traffic: Vec<usize> = analyzer.stats.buses.iter().map(|b| b.traffic).collect();
+    assert_eq!(traffic, vec![96, 224, 160, 128]);
+    assert!(analyzer.pretty_print().contains("Home Node 3 Traffic:"));
+    let directory = analyzer.stats.directory.unwrap();
+    assert_eq!(directory.num_lookups, 19);
+    assert_eq!(directory.num_replies, 19);
+}
+
+#[test]
+fn sequence_16_1_4_token() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Token,
+        16,
+        1,
+        4,
+        true,
+    );
+
+    // without other caches, memory hands out all tokens on a read => same as MESI
+    let analyzer = run(args);
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 101 + 101 + 1 + 101 + 101 + 101 + 101 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    assert_eq!(analyzer.stats.bus_num_persistent_requests, 0);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 1);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 8);
+}
+
+#[test]
+fn token_persistent_requests_bound_starvation() {
+    let run_protocol = |protocol| {
+        run_with_config(
+            ProgramArgs::new(
+                String::from("data/blackscholes/blackscholes_10.zip"),
+                protocol,
+                4096,
+                2,
+                32,
+                true,
+            ),
+            SystemConfig {
+                arbitration: ArbitrationConfig {
+                    kind: ArbitrationKind::FixedPriority,
+                    seed: 0,
+                },
+                ..Default::default()
+            },
+        )
+    };
+    let max_wait = |analyzer: &Analyzer| -> Vec<usize> {
+        let cores = &analyzer.stats.cores;
+        cores
+            .iter()
+            .map(|c| c.arbitration.max_wait_cycles)
+            .collect()
+    };
+
+    // the cores with low priority starve behind the others
+    let mesi = run_protocol(ProtocolKind::Mesi);
+    assert_eq!(mesi.stats.bus_num_persistent_requests, 0);
+    assert_eq!(max_wait(&mesi), vec![89, 101, 1010, 1111]);
+
+    // starving requests escalate to persistent requests, which reserve the bus for them
+    let token = run_protocol(ProtocolKind::Token);
+    assert_eq!(token.stats.bus_num_persistent_requests, 4);
+    assert_eq!(max_wait(&token), vec![190, 385, 505, 606]);
+    assert_eq!(token.stats.bus_traffic, mesi.stats.bus_traffic);
+}
+
+#[test]
+fn sequence_16_1_4_mesi_table() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Table,
+        16,
+        1,
+        4,
+        true,
+    );
+
+    // reference table reproduces sequence_16_1_4
+    let analyzer = run_with_config(args, table_config("protocols/mesi.toml"));
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 101 + 101 + 1 + 101 + 101 + 101 + 101 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 1);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 8);
+}
+
+#[test]
+fn sequence_16_4_4_dragon_table() {
+    let args = ProgramArgs::new(
+        String::from("data/single_thread/sequence.zip"),
+        ProtocolKind::Table,
+        16,
+        4,
+        4,
+        true,
+    );
+
+    // reference table reproduces sequence_16_4_4
+    let analyzer = run_with_config(args, table_config("protocols/dragon.toml"));
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        101 + 10 + 102 + 1 + 1 + 101 + 101 + 1 + 101 + 101 + 101
+    );
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 7);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 0);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 9);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 0);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_hits, 3);
+    assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 6);
+}
+
+#[test]
+fn reference_tables_match_builtin_protocols() {
+    for (protocol, path) in [
+        (ProtocolKind::Mesi, "protocols/mesi.toml"),
+        (ProtocolKind::Dragon, "protocols/dragon.toml"),
+    ] {
+        let builtin = run(ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10.zip"),
+            protocol,
+            4096,
+            2,
+            32,
+            true,
+        ));
+        let table = run_with_config(
+            ProgramArgs::new(
+                String::from("data/blackscholes/blackscholes_10.zip"),
+                ProtocolKind::Table,
+                4096,
+                2,
+                32,
+                true,
+            ),
+            table_config(path),
+        );
+        assert_eq!(builtin.pretty_print(), table.pretty_print());
+    }
+}
+
+#[test]
+fn coherence_checker_passes() {
+    for protocol in [
+        ProtocolKind::Mesi,
+        ProtocolKind::Dragon,
+        ProtocolKind::Msi,
+        ProtocolKind::Moesi,
+        ProtocolKind::Mesif,
+        ProtocolKind::Firefly,
+        ProtocolKind::Berkeley,
+        ProtocolKind::WriteOnce,
+        ProtocolKind::Token,
+    ] {
+        run_with_config(
+            ProgramArgs::new(
+                String::from("data/blackscholes/blackscholes_10.zip"),
+                protocol,
+                4096,
+                2,
+                32,
+                true,
+            ),
+            SystemConfig {
+                check_coherence: true,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "Coherence violation")]
+fn coherence_checker_detects_violation() {
+    // the reader keeps E although another cache supplied the line
+    let spec = std::fs::read_to_string("protocols/mesi.toml")
+        .unwrap()
+        .replace("BusRdShared  E  ->  S", "BusRdShared  E  ->  E");
+    run_with_config(
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10_000.zip"),
+            ProtocolKind::Table,
+            4096,
+            2,
+            32,
+            true,
+        ),
+        SystemConfig {
+            protocol_table: Some(Rc::new(ProtocolTable::parse(&spec).unwrap())),
+            check_coherence: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn lost_writeback_causes_stale_reads() {
+    // the dirty line is dropped instead of being supplied to the reader
+    let spec = std::fs::read_to_string("protocols/mesi.toml")
+        .unwrap()
+        .replace(
+            "BusRdMem                   M      ->  S      BusRdShared   cost=Flush",
+            "BusRdMem                   M      ->  I",
+        );
+    let analyzer = run_with_config(
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10_000.zip"),
+            ProtocolKind::Table,
+            4096,
+            2,
+            32,
+            true,
+        ),
+        SystemConfig {
+            protocol_table: Some(Rc::new(ProtocolTable::parse(&spec).unwrap())),
+            ..Default::default()
+        },
+    );
+    assert!(analyzer.stats.num_stale_reads > 0);
+
+    let analyzer = run(ProgramArgs::new(
+        String::from("data/blackscholes/blackscholes_10_000.zip"),
+        ProtocolKind::Mesi,
+        4096,
+        2,
+        32,
+        true,
+    ));
+    assert_eq!(analyzer.stats.num_stale_reads, 0);
+}
+
+#[test]
+fn l2_reduces_miss_penalty() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/sequence.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let without = run(args());
+    let with = run_with_config(
+        args(),
+        SystemConfig {
+            l2: Some(L2Config::default()),
+            ..Default::default()
+        },
+    );
+    // all accessed words share one 64 byte L2 block: the first miss pays the L2 lookup on top of
+    // the memory latency, all later transactions hit in the L2 instead of waiting for memory
+    assert_eq!(
+        with.stats.exec_cycles,
+        (101 + 10) + 10 + 12 + 11 + 11 + 1 + 11 + 11 + 11 + 11 + 11 + 11 + 11
+    );
+    let l2 = with.stats.l2.as_ref().unwrap();
+    assert_eq!((l2.num_hits, l2.num_misses), (10, 1));
+    assert_eq!(with.stats.bus_traffic, without.stats.bus_traffic);
+    assert_eq!(
+        with.stats.cores[0].cache.num_data_cache_misses,
+        without.stats.cores[0].cache.num_data_cache_misses
+    );
+    assert!(with.pretty_print().contains("No. L2 Hits:"));
+    assert!(!without.pretty_print().contains("No. L2 Hits:"));
+}
+
+#[test]
+fn private_hierarchy_policies() {
+    // 0x05 and 0x09 conflict in the direct mapped coherent level, but both fit into the upper one
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/evict.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let config = |policy| SystemConfig {
+        hierarchy: HierarchyConfig {
+            upper_levels: vec![LevelConfig {
+                size: 8,
+                associativity: 2,
+                hit_latency: 1,
+            }],
+            policy,
+            victim: None,
+        },
+        timing: TimingConfig {
+            hit_latency: 5,
+            ..Default::default()
+        },
+        check_coherence: true,
+        ..Default::default()
+    };
+
+    // evicting a block from the coherent level removes its upper copy: all three loads miss
+    let inclusive = run_with_config(args(), config(InclusionPolicy::Inclusive));
+    assert_eq!(inclusive.stats.exec_cycles, 101 + 101 + 101 + 1);
+    assert_eq!(inclusive.stats.bus_traffic, 3 * 4);
+    let stats = &inclusive.stats.cache.hierarchy;
+    assert_eq!((stats.num_hits[0], stats.num_misses), (0, 3));
+    assert_eq!(stats.num_inclusion_victims, 2);
+    assert_eq!(stats.num_snoop_invalidations, 0);
+    assert_eq!(inclusive.stats.num_stale_reads, 0);
+    assert!(inclusive
+        .pretty_print()
+        .contains("No. Upper Level 1 Load Hits:"));
+
+    // the upper copy of 0x05 survives its eviction: the last load hits in the upper level
+    for policy in [InclusionPolicy::Exclusive, InclusionPolicy::Nine] {
+        let analyzer = run_with_config(args(), config(policy));
+        assert_eq!(
+            analyzer.stats.exec_cycles,
+            101 + 101 + 1 + 1,
+            "{:?}",
+            policy
+        );
+        assert_eq!(analyzer.stats.bus_traffic, 2 * 4);
+        let stats = &analyzer.stats.cache.hierarchy;
+        assert_eq!((stats.num_hits[0], stats.num_misses), (1, 2));
+        assert_eq!(stats.num_inclusion_victims, 0);
+        assert_eq!(analyzer.stats.cores[0].cache.num_data_cache_misses, 2);
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+    }
+}
+
+#[test]
+fn replacement_policies() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10_000.zip"),
+            ProtocolKind::Mesi,
+            4096,
+            4,
+            32,
+            true,
+        )
+    };
+    let lru = run(args());
+    for kind in [
+        ReplacementKind::Lru,
+        ReplacementKind::Fifo,
+        ReplacementKind::Random,
+        ReplacementKind::TreePlru,
+        ReplacementKind::Nru,
+        ReplacementKind::Lfu,
+        ReplacementKind::Srrip,
+        ReplacementKind::Brrip,
+        ReplacementKind::Drrip,
+        ReplacementKind::Belady,
+    ] {
+        let analyzer = run_with_config(
+            args(),
+            SystemConfig {
+                replacement: ReplacementConfig { kind, seed: 0 },
+                ..Default::default()
+            },
+        );
+        let stats = &analyzer.stats;
+        assert_eq!(stats.num_stale_reads, 0, "{:?}", kind);
+        assert_eq!(
+            stats.cache.num_data_cache_hits as usize + stats.cache.num_data_cache_misses,
+            lru.stats.cache.num_data_cache_hits as usize + lru.stats.cache.num_data_cache_misses,
+            "{:?}",
+            kind
+        );
+        match kind {
+            ReplacementKind::Lru => assert_eq!(stats.exec_cycles, lru.stats.exec_cycles),
+            ReplacementKind::Belady => {
+                assert!(stats.cache.num_data_cache_misses < lru.stats.cache.num_data_cache_misses)
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn victim_cache() {
+    // 0x05 and 0x09 conflict in the direct mapped cache
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/evict.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let without = run(args());
+    assert_eq!(without.stats.exec_cycles, 101 + 101 + 101 + 1);
+    assert!(without.stats.cache.victim.is_none());
+
+    let with = run_with_config(
+        args(),
+        SystemConfig {
+            hierarchy: HierarchyConfig {
+                victim: Some(VictimConfig::default()),
+                ..Default::default()
+            },
+            check_coherence: true,
+            ..Default::default()
+        },
+    );
+    // the evicted 0x05 is reloaded from the victim cache without a bus transaction
+    assert_eq!(with.stats.exec_cycles, 101 + 101 + 2 + 1);
+    assert_eq!(with.stats.bus_traffic, 2 * 4);
+    assert_eq!(with.stats.cores[0].cache.num_data_cache_misses, 2);
+    let stats = with.stats.cache.victim.as_ref().unwrap();
+    assert_eq!((stats.num_hits, stats.num_insertions), (1, 1));
+    assert_eq!(with.stats.num_stale_reads, 0);
+    assert!(with.pretty_print().contains("No. Victim Cache Hits:"));
+}
+
+#[test]
+fn prefetchers() {
+    let args = |protocol| {
+        ProgramArgs::new(
+            String::from("data/single_thread/sequence.zip"),
+            protocol,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let run_prefetch = |protocol, kind| {
+        run_with_config(
+            args(protocol),
+            SystemConfig {
+                prefetch: Some(PrefetchConfig { kind, degree: 2 }),
+                check_coherence: true,
+                ..Default::default()
+            },
+        )
+    };
+    for protocol in [
+        ProtocolKind::Mesi,
+        ProtocolKind::Dragon,
+        ProtocolKind::Moesi,
+    ] {
+        // the bus is idle during the computation after the first miss of 0x10: 0x11 is
+        // prefetched, the store to 0x14 waits for it and the later load of 0x11 hits
+        let analyzer = run_prefetch(protocol, PrefetchKind::NextLine);
+        assert_eq!(analyzer.stats.exec_cycles, 1123 - 8, "{:?}", protocol);
+        assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+        let cache = &analyzer.stats.cores[0].cache;
+        assert_eq!(
+            (cache.num_data_cache_hits, cache.num_data_cache_misses),
+            (2, 7)
+        );
+        let stats = analyzer.stats.cache.prefetch.as_ref().unwrap();
+        assert_eq!((stats.num_issued, stats.num_useful), (1, 1));
+        assert_eq!(stats.num_evicted_unused + stats.num_invalidated, 0);
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+        assert!(analyzer.pretty_print().contains("No. Useful Prefetches:"));
+
+        // stride and stream prefetchers need more misses to confirm a pattern
+        for kind in [PrefetchKind::Stride, PrefetchKind::Stream] {
+            let analyzer = run_prefetch(protocol, kind);
+            assert_eq!(
+                analyzer.stats.exec_cycles,
+                run(args(protocol)).stats.exec_cycles
+            );
+            assert_eq!(analyzer.stats.cache.prefetch.unwrap().num_issued, 0);
+        }
+    }
+}
+
+#[test]
+fn non_blocking_caches() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/sequence.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let blocking = run(args());
+    assert!(blocking.stats.cache.mshr.is_none());
+    let core = &blocking.stats.cores[0];
+    assert_eq!((core.bus_stall_cycles, core.mshr_stall_cycles), (1100, 0));
+    assert!(!blocking.pretty_print().contains("MSHR Full Stalls:"));
+
+    let run_mshrs = |mshrs| {
+        run_with_config(
+            args(),
+            SystemConfig {
+                mshrs: Some(mshrs),
+                check_coherence: true,
+                ..Default::default()
+            },
+        )
+    };
+    // the first load miss overlaps the computation. The same transactions complete, including
+    // the one of the last store.
+    for (mshrs, exec_cycles, bus_stalls, mshr_stalls) in [(1, 1116, 991, 100), (2, 1115, 1091, 0)] {
+        let non_blocking = run_mshrs(mshrs);
+        assert_eq!(non_blocking.stats.exec_cycles, exec_cycles);
+        assert_eq!(non_blocking.stats.bus_traffic, blocking.stats.bus_traffic);
+        assert_eq!(non_blocking.stats.bus_num_invalid_or_upd, 3);
+        let stats = non_blocking.stats.cache.mshr.as_ref().unwrap();
+        assert_eq!(stats.num_primary_misses, 5);
+        assert_eq!(stats.num_secondary_misses + stats.num_hits_under_miss, 0);
+        // with a single MSHR, the load of 0x12 waits for the miss of 0x11
+        let core = &non_blocking.stats.cores[0];
+        assert_eq!(
+            (core.bus_stall_cycles, core.mshr_stall_cycles),
+            (bus_stalls, mshr_stalls)
+        );
+        assert_eq!(non_blocking.stats.num_stale_reads, 0);
+        assert!(non_blocking.pretty_print().contains("MSHR Full Stalls:"));
+    }
+}
+
+#[test]
+fn store_buffers() {
+    // 0x10, 0x12 and 0x14 share a set of two ways, 0x10 is not used after its second store
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/forward.zip"),
+            ProtocolKind::Mesi,
+            16,
+            2,
+            4,
+            true,
+        )
+    };
+    let run_buffer = |kind, store_buffer| {
+        run_with_config(
+            args(),
+            SystemConfig {
+                replacement: ReplacementConfig { kind, seed: 0 },
+                store_buffer,
+                check_coherence: true,
+                ..Default::default()
+            },
+        )
+    };
+    let unbuffered = run_buffer(ReplacementKind::Lru, None);
+    assert!(unbuffered.stats.store_buffer.is_none());
+    assert_eq!(unbuffered.stats.exec_cycles, 508);
+    assert_eq!(unbuffered.stats.cores[0].drain_stall_cycles, 0);
+    assert!(!unbuffered.pretty_print().contains("Drain Stalls:"));
+
+    // the load of 0x10 is served by the buffered store and never reaches the cache
+    let lru = run_buffer(ReplacementKind::Lru, Some(2));
+    let stats = lru.stats.store_buffer.as_ref().unwrap();
+    assert_eq!(stats.num_buffered_stores, 2);
+    assert_eq!(stats.num_forwarded_loads, 1);
+    assert_eq!(stats.max_occupancy, 1);
+    // LRU keeps the recently stored 0x10 and replaces 0x12, which misses again
+    assert_eq!(lru.stats.exec_cycles, 509);
+    let cache = &lru.stats.cores[0].cache;
+    assert_eq!(
+        (cache.num_data_cache_hits, cache.num_data_cache_misses),
+        (1, 4)
+    );
+    assert_eq!(lru.stats.num_stale_reads, 0);
+    assert!(lru.pretty_print().contains("No. Forwarded Loads:"));
+    assert!(lru.pretty_print().contains("Drain Stalls:"));
+
+    // the forwarded load consumed the last load of 0x10 in the future accesses of Belady, so it
+    // replaces 0x10 instead
+    let belady = run_buffer(ReplacementKind::Belady, Some(2));
+    assert_eq!(belady.stats.exec_cycles, 409);
+    let cache = &belady.stats.cores[0].cache;
+    assert_eq!(
+        (cache.num_data_cache_hits, cache.num_data_cache_misses),
+        (2, 3)
+    );
+    assert_eq!(belady.stats.num_stale_reads, 0);
+}
+
+#[test]
+fn split_transaction_bus() {
+    let split_config = || SystemConfig {
+        split_bus: true,
+        check_coherence: true,
+        ..Default::default()
+    };
+    // a single core has one transaction in flight at a time and gains nothing
+    let sequence = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/sequence.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let atomic = run(sequence());
+    assert_eq!(atomic.stats.bus_num_split_transactions, 0);
+    let split = run_with_config(sequence(), split_config());
+    assert_eq!(split.stats.exec_cycles, atomic.stats.exec_cycles);
+    assert_eq!(split.stats.bus_num_split_transactions, 11);
+    assert_eq!(split.stats.bus_max_outstanding, 1);
+
+    let args = |protocol| {
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10.zip"),
+            protocol,
+            4096,
+            2,
+            32,
+            true,
+        )
+    };
+    for protocol in [
+        ProtocolKind::Mesi,
+        ProtocolKind::Dragon,
+        ProtocolKind::Token,
+    ] {
+        let atomic = run(args(protocol));
+        assert_eq!(atomic.stats.exec_cycles, 1927);
+        assert_eq!(atomic.stats.bus_busy_cycles, 1919);
+
+        // the other cores use the bus while memory is working on a request
+        let split = run_with_config(args(protocol), split_config());
+        assert_eq!(split.stats.exec_cycles, 749, "{:?}", protocol);
+        assert_eq!(split.stats.bus_busy_cycles, 361);
+        assert_eq!(split.stats.bus_traffic, atomic.stats.bus_traffic);
+        assert_eq!(split.stats.bus_num_split_transactions, 19);
+        assert_eq!(split.stats.bus_max_outstanding, 4);
+        assert_eq!(split.stats.num_stale_reads, 0);
+        assert!(split.pretty_print().contains("Bus Utilization:"));
+    }
+}
+
+#[test]
+fn default_configuration_matches_baseline() {
+    // the results of the simulator before any of the optional features were added
+    let analyzer = run(ProgramArgs::new(
+        String::from("data/blackscholes/blackscholes_10_000.zip"),
+        ProtocolKind::Mesi,
+        4096,
+        2,
+        32,
+        true,
+    ));
+    assert_eq!(analyzer.stats.exec_cycles, 1099838);
+    assert_eq!(analyzer.stats.cache.num_private_data_access, 19020);
+    assert_eq!(analyzer.stats.cache.num_shared_data_access, 980);
+    assert_eq!(analyzer.stats.bus_traffic, 349020);
+    assert_eq!(analyzer.stats.bus_num_invalid_or_upd, 3767);
+}
+
+#[test]
+fn arbitration_policies() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10.zip"),
+            ProtocolKind::Mesi,
+            4096,
+            2,
+            32,
+            true,
+        )
+    };
+    let run_policy = |kind| {
+        run_with_config(
+            args(),
+            SystemConfig {
+                arbitration: ArbitrationConfig { kind, seed: 42 },
+                check_coherence: true,
+                ..Default::default()
+            },
+        )
+    };
+    let max_wait = |analyzer: &Analyzer| {
+        let cores = &analyzer.stats.cores;
+        cores
+            .iter()
+            .map(|c| c.arbitration.max_wait_cycles)
+            .collect::<Vec<_>>()
+    };
+
+    // the cores with the lowest priority starve
+    let fixed = run_policy(ArbitrationKind::FixedPriority);
+    assert_eq!(fixed.stats.exec_cycles, 1937);
+    assert_eq!(max_wait(&fixed), vec![89, 101, 1010, 1111]);
+    assert_eq!(fixed.stats.num_stale_reads, 0);
+    assert!(fixed
+        .pretty_print()
+        .contains("Arbitration Fairness (Jain):"));
+
+    for (kind, exec_cycles, wait) in [
+        (ArbitrationKind::Shuffle, 1927, vec![303, 295, 279, 295]),
+        (ArbitrationKind::RoundRobin, 1927, vec![291, 295, 279, 303]),
+        (ArbitrationKind::Fcfs, 1927, vec![291, 295, 279, 303]),
+        (ArbitrationKind::Random, 1937, vec![373, 388, 336, 497]),
+    ] {
+        let analyzer = run_policy(kind);
+        assert_eq!(analyzer.stats.exec_cycles, exec_cycles, "{:?}", kind);
+        assert_eq!(max_wait(&analyzer), wait, "{:?}", kind);
+        let grants: Vec<usize> = analyzer
+            .stats
+            .cores
+            .iter()
+            .map(|c| c.arbitration.num_grants)
+            .collect();
+        assert_eq!(grants, vec![5, 5, 4, 5]);
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+        assert!(analyzer.stats.arbitration_fairness > fixed.stats.arbitration_fairness);
+        // the default policy prints no arbitration statistics
+        assert_eq!(
+            analyzer
+                .pretty_print()
+                .contains("Arbitration Fairness (Jain):"),
+            kind != ArbitrationKind::Shuffle
+        );
+    }
+}
+
+#[test]
+fn timing_model() {
+    let args = |file: &str| {
+        ProgramArgs::new(
+            format!("data/single_thread/{}.zip", file),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let run_timing = |file, timing| {
+        run_with_config(
+            args(file),
+            SystemConfig {
+                timing,
+                ..Default::default()
+            },
+        )
+    };
+    for file in ["read_miss", "evict", "sequence"] {
+        let reference = run_timing(file, TimingConfig::open("timing/default.toml").unwrap());
+        assert_eq!(
+            reference.stats.exec_cycles,
+            run(args(file)).stats.exec_cycles
+        );
+    }
+
+    // a read miss costs the memory latency, a read hit the hit latency
+    let slow = TimingConfig {
+        memory_latency: 200,
+        hit_latency: 3,
+        ..Default::default()
+    };
+    assert_eq!(run_timing("read_miss", slow).stats.exec_cycles, 202);
+    assert_eq!(run_timing("read_hit", slow).stats.exec_cycles, 205);
+
+    // cheaper flushes and cache-to-cache transfers
+    let wide = TimingConfig {
+        bus_width: 8,
+        cycles_per_beat: 1,
+        writeback_latency: 50,
+        ..Default::default()
+    };
+    let analyzer = run_timing("sequence", wide);
+    assert!(analyzer.stats.exec_cycles < run(args("sequence")).stats.exec_cycles);
+    assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+}
+
+#[test]
+fn dram_memory_controller() {
+    // a read miss opens a row of a closed bank instead of paying the flat memory latency
+    let read_miss = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/read_miss.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let dram = DramConfig::default();
+    let analyzer = run_with_config(
+        read_miss(),
+        SystemConfig {
+            dram: Some(dram),
+            ..Default::default()
+        },
+    );
+    let flat = run(read_miss()).stats.exec_cycles;
+    assert_eq!(
+        analyzer.stats.exec_cycles,
+        flat - TimingConfig::default().memory_latency + dram.t_rcd + dram.t_cas + dram.t_burst
+    );
+    let stats = analyzer.stats.dram.as_ref().unwrap();
+    assert_eq!((stats.num_requests(), stats.num_row_misses), (1, 1));
+
+    // all words of the sequence trace share the first row, the later transactions hit it. With
+    // 16 byte rows in a single bank, 0x10 and 0x14 alternately close each other's row.
+    let sequence = || {
+        ProgramArgs::new(
+            String::from("data/single_thread/sequence.zip"),
+            ProtocolKind::Mesi,
+            16,
+            1,
+            4,
+            true,
+        )
+    };
+    let one_bank = DramConfig {
+        banks: 1,
+        row_size: 16,
+        ..dram
+    };
+    for (split_bus, config, exec_cycles, rows) in [
+        (false, dram, 639, (10, 1, 0)),
+        (false, one_bank, 1167, (4, 1, 6)),
+        (true, dram, 658, (10, 1, 0)),
+        (true, one_bank, 1186, (4, 1, 6)),
+    ] {
+        let analyzer = run_with_config(
+            sequence(),
+            SystemConfig {
+                dram: Some(config),
+                split_bus,
+                check_coherence: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(analyzer.stats.exec_cycles, exec_cycles);
+        assert_eq!(analyzer.stats.bus_traffic, 4 * 11);
+        let stats = analyzer.stats.dram.as_ref().unwrap();
+        assert_eq!(
+            (
+                stats.num_row_hits,
+                stats.num_row_misses,
+                stats.num_row_conflicts
+            ),
+            rows
+        );
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+        assert!(analyzer.pretty_print().contains("Row Buffer Hit Rate:"));
+    }
+}
+
+#[test]
+fn interleaved_buses() {
+    let args = || {
+        ProgramArgs::new(
+            String::from("data/blackscholes/blackscholes_10.zip"),
+            ProtocolKind::Mesi,
+            4096,
+            2,
+            32,
+            true,
+        )
+    };
+    let single = run(args());
+    assert_eq!(single.stats.exec_cycles, 1927);
+    assert_eq!(single.stats.buses.len(), 1);
+    assert!(!single.pretty_print().contains("Bus 0 Utilization:"));
+
+    // transactions to different slices of the blocks proceed in parallel
+    let run_buses = |split_bus| {
+        run_with_config(
+            args(),
+            SystemConfig {
+                num_buses: Some(4),
+                split_bus,
+                check_coherence: true,
+                ..Default::default()
+            },
+        )
+    };
+    let interleaved = run_buses(false);
+    assert_eq!(interleaved.stats.exec_cycles, 1058);
+    assert_eq!(interleaved.stats.num_stale_reads, 0);
+    let buses = &interleaved.stats.buses;
+    let traffic: Vec<usize> = buses.iter().map(|b| b.traffic).collect();
+    assert_eq!(traffic, vec![96, 224, 160, 128]);
+    assert_eq!(traffic.iter().sum::<usize>(), single.stats.bus_traffic);
+    // each atomic transaction holds its bus for the memory latency
+    let busy: Vec<usize> = buses.iter().map(|b| b.busy_cycles).collect();
+    assert_eq!(busy, vec![3 * 101, 7 * 101, 5 * 101, 4 * 101]);
+    assert!(interleaved.pretty_print().contains("Bus 3 Utilization:"));
+
+    // split transactions work per bus and per home node of the directory
+    let split = run_buses(true);
+    assert_eq!(split.stats.exec_cycles, 674);
+    assert_eq!(split.stats.bus_num_split_transactions, 19);
+    assert_eq!(split.stats.bus_max_outstanding, 3);
+    assert_eq!(split.stats.num_stale_reads, 0);
+    let directory = run_with_config(
+        args(),
+        SystemConfig {
+            directory: Some(DirectoryConfig::default()),
+            split_bus: true,
+            check_coherence: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(directory.stats.exec_cycles, 799);
+    assert_eq!(directory.stats.bus_num_split_transactions, 19);
+    assert_eq!(directory.stats.bus_max_outstanding, 3);
+    assert_eq!(directory.stats.num_stale_reads, 0);
+}
+
+#[test]
+fn interleaved_buses_with_mshrs_and_prefetching() {
+    // non-blocking caches and prefetchers access blocks of other buses while a transaction is in
+    // flight, the protocols must not change the state of a line before the cache got the bus
+    for protocol in [
+        ProtocolKind::Mesi,
+        ProtocolKind::Msi,
+        ProtocolKind::Moesi,
+        ProtocolKind::Mesif,
+        ProtocolKind::Dragon,
+        ProtocolKind::Firefly,
+        ProtocolKind::Berkeley,
+        ProtocolKind::WriteOnce,
+        ProtocolKind::Token,
+    ] {
+        let analyzer = run_with_config(
+            ProgramArgs::new(
+                String::from("data/blackscholes/blackscholes_10.zip"),
+                protocol,
+                32,
+                1,
+                16,
+                true,
+            ),
+            SystemConfig {
+                num_buses: Some(4),
+                mshrs: Some(2),
+                prefetch: Some(PrefetchConfig {
+                    kind: PrefetchKind::NextLine,
+                    degree: 1,
+                }),
+                check_coherence: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(analyzer.stats.exec_cycles, 1516, "{:?}", protocol);
+        assert_eq!(analyzer.stats.cache.mshr.unwrap().num_primary_misses, 7);
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+    }
+}
+
+#[test]
+fn directory_with_mshrs_and_prefetching() {
+    // every home node has its own bus, so the same gating applies as with interleaved buses
+    for protocol in [
+        ProtocolKind::Mesi,
+        ProtocolKind::Msi,
+        ProtocolKind::Moesi,
+        ProtocolKind::Mesif,
+        ProtocolKind::Dragon,
+        ProtocolKind::Firefly,
+        ProtocolKind::Berkeley,
+        ProtocolKind::WriteOnce,
+        ProtocolKind::Token,
+    ] {
+        let analyzer = run_with_config(
+            ProgramArgs::new(
+                String::from("data/blackscholes/blackscholes_10.zip"),
+                protocol,
+                64,
+                1,
+                16,
+                true,
+            ),
+            SystemConfig {
+                directory: Some(DirectoryConfig::default()),
+                mshrs: Some(2),
+                prefetch: Some(PrefetchConfig {
+                    kind: PrefetchKind::NextLine,
+                    degree: 1,
+                }),
+                check_coherence: true,
+                ..Default::default()
+            },
+        );
+        let expected = match protocol {
+            ProtocolKind::Token => 1851,
+            _ => 1977,
+        };
+        assert_eq!(analyzer.stats.exec_cycles, expected, "{:?}", protocol);
+        assert_eq!(analyzer.stats.cache.mshr.unwrap().num_primary_misses, 7);
+        assert_eq!(analyzer.stats.num_stale_reads, 0);
+    }
+}